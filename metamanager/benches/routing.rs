@@ -0,0 +1,194 @@
+// Throughput and latency of the two routing modes with many chatty players
+// Run with `cargo bench --bench routing -- [--players 2,16,64] [--messages 2000]`
+//
+// Participants live in this process behind in-memory pipes, so the numbers are the cost of
+// the router itself rather than of process pipes. The manager echoes every message straight
+// back to whoever sent it. Two scenarios per mode and player count:
+//   ping-pong: every player waits for each echo before sending again, round trips are timed
+//   flood: every player sends all its messages at once, then reads all the echoes
+
+use anyhow::Result;
+use metamanager::{Match, Routing};
+use std::time::{Duration, Instant};
+use tokio::io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+const PIPE_SIZE: usize = 64 * 1024;
+const PAYLOAD: &str = "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scenario {
+    PingPong,
+    Flood,
+}
+
+struct Stats {
+    elapsed: Duration,
+    messages: usize,
+    // Sorted round trip times, only measured for ping-pong
+    round_trips: Vec<Duration>,
+}
+
+impl Stats {
+    fn percentile(&self, percent: usize) -> Duration {
+        if self.round_trips.is_empty() {
+            return Duration::ZERO;
+        }
+        self.round_trips[(self.round_trips.len() - 1) * percent / 100]
+    }
+}
+
+// Echo every `<player><delim><message>` line back to the same player
+async fn echo_manager(transport: DuplexStream) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(transport);
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        writer.write_all(format!("{line}\n").as_bytes()).await?;
+        writer.flush().await?;
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+async fn chatty_player(
+    transport: DuplexStream,
+    messages: usize,
+    scenario: Scenario,
+) -> Result<Vec<Duration>> {
+    let (reader, mut writer) = tokio::io::split(transport);
+    let mut lines = BufReader::new(reader).lines();
+    let mut round_trips = Vec::new();
+    match scenario {
+        Scenario::PingPong => {
+            for _ in 0..messages {
+                let sent = Instant::now();
+                writer.write_all(format!("{PAYLOAD}\n").as_bytes()).await?;
+                writer.flush().await?;
+                lines.next_line().await?.expect("an echo for every message");
+                round_trips.push(sent.elapsed());
+            }
+        }
+        Scenario::Flood => {
+            // Read concurrently, otherwise both directions fill up and everyone deadlocks
+            let read_all = async {
+                for _ in 0..messages {
+                    lines.next_line().await?.expect("an echo for every message");
+                }
+                anyhow::Ok(())
+            };
+            let write_all = async {
+                for _ in 0..messages {
+                    writer.write_all(format!("{PAYLOAD}\n").as_bytes()).await?;
+                }
+                writer.flush().await?;
+                anyhow::Ok(())
+            };
+            let (read, write) = tokio::join!(read_all, write_all);
+            read?;
+            write?;
+        }
+    }
+    writer.shutdown().await?;
+    Ok(round_trips)
+}
+
+async fn run(
+    routing: Routing,
+    num_players: usize,
+    messages: usize,
+    scenario: Scenario,
+) -> Result<Stats> {
+    let (manager, manager_transport) = duplex(PIPE_SIZE);
+    let mut builder = Match::builder()
+        .routing(routing)
+        .manager(BufReader::new(manager_transport));
+    let mut players = Vec::new();
+    for _ in 0..num_players {
+        let (player, transport) = duplex(PIPE_SIZE);
+        builder = builder.player(BufReader::new(transport));
+        players.push(player);
+    }
+    let game = tokio::spawn(builder.build()?.run());
+    let manager = tokio::spawn(echo_manager(manager));
+    let started = Instant::now();
+    let players = players
+        .into_iter()
+        .map(|player| tokio::spawn(chatty_player(player, messages, scenario)));
+    let mut round_trips = Vec::new();
+    for player in futures::future::join_all(players).await {
+        round_trips.extend(player??);
+    }
+    let elapsed = started.elapsed();
+    game.await??;
+    manager.await??;
+    round_trips.sort();
+    Ok(Stats {
+        elapsed,
+        // Every message crosses the router twice, once each way
+        messages: 2 * messages * num_players,
+        round_trips,
+    })
+}
+
+fn parse_args() -> (Vec<usize>, usize) {
+    let mut player_counts = vec![2, 16, 64];
+    let mut messages = 2000;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--players" => {
+                let value = args.next().expect("--players needs a value");
+                player_counts = value
+                    .split(',')
+                    .map(|count| count.parse().expect("player counts are numbers"))
+                    .collect();
+            }
+            "--messages" => {
+                let value = args.next().expect("--messages needs a value");
+                messages = value.parse().expect("--messages is a number");
+            }
+            // cargo bench passes --bench, and maybe filters we don't support
+            _ => {}
+        }
+    }
+    (player_counts, messages)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let (player_counts, messages) = parse_args();
+    println!("{messages} messages per player, manager echoes each one back");
+    println!(
+        "{:<10} {:<10} {:>8} {:>14} {:>10} {:>10} {:>10}",
+        "scenario", "routing", "players", "msgs/s", "p50 us", "p99 us", "max us"
+    );
+    for scenario in [Scenario::PingPong, Scenario::Flood] {
+        for &num_players in &player_counts {
+            for routing in [Routing::Direct, Routing::Channels] {
+                let stats = run(routing, num_players, messages, scenario).await?;
+                let micros = |duration: Duration| duration.as_micros();
+                let (p50, p99, max) = match scenario {
+                    Scenario::PingPong => (
+                        micros(stats.percentile(50)).to_string(),
+                        micros(stats.percentile(99)).to_string(),
+                        micros(stats.percentile(100)).to_string(),
+                    ),
+                    Scenario::Flood => ("-".into(), "-".into(), "-".into()),
+                };
+                println!(
+                    "{:<10} {:<10} {:>8} {:>14.0} {:>10} {:>10} {:>10}",
+                    match scenario {
+                        Scenario::PingPong => "ping-pong",
+                        Scenario::Flood => "flood",
+                    },
+                    format!("{routing:?}").to_lowercase(),
+                    num_players,
+                    stats.messages as f64 / stats.elapsed.as_secs_f64(),
+                    p50,
+                    p99,
+                    max,
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,864 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+use metamanager::handshake::Handshake;
+use metamanager::logging::{self, LogFormat, DEFAULT_LOG_LEVEL};
+use metamanager::manifest::{Manifest, MANIFEST_FILE};
+use metamanager::protocol::Protocol;
+use metamanager::replay::RecordedMatch;
+use metamanager::sandbox::SandboxConfig;
+use metamanager::spectators::SpectatorFeed;
+use metamanager::sprt::SprtConfig;
+use metamanager::timing::TimeControl;
+use metamanager::tournament::{Format, TournamentConfig};
+use metamanager::transport::Framing;
+use metamanager::{MatchConfig, ProcessConfig, Routing};
+use std::path::{Path, PathBuf};
+
+/// Route messages between a manager (referee) process and its players.
+/// The manager writes `<player><delim><message>` lines to reach a player,
+/// `*<delim><message>` to reach every player or `0,2<delim><message>` to reach several,
+/// or `spec<delim><message>` to reach spectators. It receives `<player><delim><message>`
+/// lines for everything players say.
+#[derive(Debug, Parser)]
+#[command(name = "metamanager", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Play a recorded match back to one live player and report where its output diverges
+    Replay(ReplayArgs),
+    /// Run a local bot as a remote player of a metamanager started with --listen or --ws-listen
+    Connect(ConnectArgs),
+    /// Play bots against each other under one referee and print the standings
+    Tournament(TournamentArgs),
+    /// Play a new bot version against the old one until an SPRT decides whether it's stronger
+    Sprt(SprtArgs),
+    /// Show Elo and Glicko-2 ratings from a rating store, or one bot's rating history
+    Ratings(RatingsArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct TournamentArgs {
+    /// TOML (or .json) file describing the referee, bots and settings.
+    /// Any other flags override what the file says
+    #[arg(short, long, value_name = "FILE")]
+    pub tournament_file: Option<PathBuf>,
+
+    /// Referee command, split like a shell would. It ends each game by printing
+    /// `mm<delim>result <score of player 0> <score of player 1> [reason]`
+    #[arg(long, value_name = "COMMAND")]
+    pub referee: Option<String>,
+
+    /// A bot given as [<NAME>=]<COMMAND>, may be repeated
+    #[arg(long, value_name = "[NAME=]COMMAND")]
+    pub bot: Vec<String>,
+
+    /// Who plays whom
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Games per pairing, seats are swapped every other game
+    #[arg(short, long)]
+    pub games: Option<usize>,
+
+    /// How many games run at the same time
+    #[arg(short = 'j', long)]
+    pub concurrency: Option<usize>,
+
+    /// Number of Swiss rounds
+    #[arg(long)]
+    pub rounds: Option<usize>,
+
+    /// Bot that plays everyone else in a gauntlet, the first bot by default
+    #[arg(long, value_name = "NAME")]
+    pub gauntlet: Option<String>,
+
+    /// Keep each game's manifest, stderr logs and transcript under <DIR>/game<N>-<BOT>-vs-<BOT>
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+
+    /// Character separating the player tag from the message
+    #[arg(short, long)]
+    pub delim: Option<char>,
+
+    /// How messages are routed between processes
+    #[arg(long, value_enum)]
+    pub routing: Option<Routing>,
+
+    /// How messages are told apart: one per line, or length-prefixed for binary payloads
+    #[arg(long, value_enum)]
+    pub framing: Option<Framing>,
+
+    /// What messages look like: <TAG><DELIM><MESSAGE>, or JSON objects checked by the
+    /// metamanager
+    #[arg(long, value_enum)]
+    pub protocol: Option<Protocol>,
+
+    /// JSON file with a JSON Schema per player message type, implies --protocol json
+    #[arg(long, value_name = "FILE")]
+    pub schema: Option<PathBuf>,
+
+    /// Chess clock per player as <BASE>+<INCREMENT> in seconds, e.g. 60+0.5
+    #[arg(long, value_name = "BASE+INC")]
+    pub time_control: Option<TimeControl>,
+
+    /// Add every game with a result to this rating store
+    #[arg(long, value_name = "FILE")]
+    pub ratings: Option<PathBuf>,
+
+    /// Greet bots with a hello for GAME before every game, they have to answer with their
+    /// name and version
+    #[arg(long, value_name = "GAME")]
+    pub handshake: Option<String>,
+
+    /// Restart a bot that crashes mid-game up to N times, if the referee answers
+    /// `mm<delim>disconnect <player>` with `mm<delim>restart <player>`
+    #[arg(long, value_name = "N")]
+    pub restarts: Option<usize>,
+
+    #[command(flatten)]
+    pub sandbox: SandboxArgs,
+
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = DEFAULT_LOG_LEVEL)]
+    pub log_level: LevelFilter,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SprtArgs {
+    /// Referee command, it reports each game with `mm<delim>result`
+    #[arg(long, value_name = "COMMAND")]
+    pub referee: String,
+
+    /// The new bot version as [<NAME>=]<COMMAND>, named "new" by default
+    #[arg(long, value_name = "[NAME=]COMMAND")]
+    pub new: String,
+
+    /// The old bot version as [<NAME>=]<COMMAND>, named "old" by default
+    #[arg(long, value_name = "[NAME=]COMMAND")]
+    pub old: String,
+
+    /// Elo gain of the new version under H0
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    pub elo0: f64,
+
+    /// Elo gain of the new version under H1
+    #[arg(long, default_value_t = 5.0, allow_hyphen_values = true)]
+    pub elo1: f64,
+
+    /// Chance of accepting H1 when H0 is true
+    #[arg(long, default_value_t = 0.05)]
+    pub alpha: f64,
+
+    /// Chance of accepting H0 when H1 is true
+    #[arg(long, default_value_t = 0.05)]
+    pub beta: f64,
+
+    /// Stop without a verdict after this many games
+    #[arg(long)]
+    pub max_games: Option<usize>,
+
+    /// How many games run at the same time
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub concurrency: usize,
+
+    /// Give every game a directory with a manifest, stderr logs and a transcript under DIR
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+
+    /// Character separating the player tag from the message
+    #[arg(short, long)]
+    pub delim: Option<char>,
+
+    /// How messages are routed between processes
+    #[arg(long, value_enum)]
+    pub routing: Option<Routing>,
+
+    /// How messages are told apart: one per line, or length-prefixed for binary payloads
+    #[arg(long, value_enum)]
+    pub framing: Option<Framing>,
+
+    /// What messages look like: <TAG><DELIM><MESSAGE>, or JSON objects checked by the
+    /// metamanager
+    #[arg(long, value_enum)]
+    pub protocol: Option<Protocol>,
+
+    /// JSON file with a JSON Schema per player message type, implies --protocol json
+    #[arg(long, value_name = "FILE")]
+    pub schema: Option<PathBuf>,
+
+    /// Chess clock per player as <BASE>+<INCREMENT> in seconds, e.g. 60+0.5
+    #[arg(long, value_name = "BASE+INC")]
+    pub time_control: Option<TimeControl>,
+
+    /// Add every game with a result to this rating store
+    #[arg(long, value_name = "FILE")]
+    pub ratings: Option<PathBuf>,
+
+    /// Greet bots with a hello for GAME before every game, they have to answer with their
+    /// name and version
+    #[arg(long, value_name = "GAME")]
+    pub handshake: Option<String>,
+
+    /// Restart a bot that crashes mid-game up to N times, if the referee answers
+    /// `mm<delim>disconnect <player>` with `mm<delim>restart <player>`
+    #[arg(long, value_name = "N")]
+    pub restarts: Option<usize>,
+
+    #[command(flatten)]
+    pub sandbox: SandboxArgs,
+
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = DEFAULT_LOG_LEVEL)]
+    pub log_level: LevelFilter,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SandboxArgs {
+    /// Run players in a sandbox (Linux only): their own process group, killed once they're
+    /// done, a private TMPDIR and no network. A player going over a limit forfeits, the
+    /// manager is sent `mm<delim>forfeit <player> <reason>`
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// CPU seconds each player may use, implies --sandbox
+    #[arg(long, value_name = "SECS")]
+    pub cpu_limit: Option<u64>,
+
+    /// Memory each player may map in MiB, implies --sandbox
+    #[arg(long, value_name = "MB")]
+    pub memory_limit: Option<u64>,
+
+    /// Largest file each player may write in MiB, implies --sandbox
+    #[arg(long, value_name = "MB")]
+    pub file_size_limit: Option<u64>,
+
+    /// Processes the user running players may have, implies --sandbox. Not enforced for root
+    #[arg(long, value_name = "N")]
+    pub process_limit: Option<u64>,
+
+    /// Let sandboxed players use the network
+    #[arg(long)]
+    pub allow_network: bool,
+}
+
+impl SandboxArgs {
+    // Apply the flags on top of the sandbox a config file asked for, if any
+    fn apply(self, sandbox: &mut Option<SandboxConfig>) {
+        let any_limit = self.cpu_limit.is_some()
+            || self.memory_limit.is_some()
+            || self.file_size_limit.is_some()
+            || self.process_limit.is_some();
+        if !(self.sandbox || any_limit || self.allow_network) {
+            return;
+        }
+        let sandbox = sandbox.get_or_insert_with(SandboxConfig::default);
+        if self.cpu_limit.is_some() {
+            sandbox.cpu_seconds = self.cpu_limit;
+        }
+        if self.memory_limit.is_some() {
+            sandbox.memory_mb = self.memory_limit;
+        }
+        if self.file_size_limit.is_some() {
+            sandbox.file_size_mb = self.file_size_limit;
+        }
+        if self.process_limit.is_some() {
+            sandbox.processes = self.process_limit;
+        }
+        sandbox.network |= self.allow_network;
+    }
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct LogArgs {
+    /// How log lines are written. Json gives one object per line with the match id and,
+    /// for routed messages, the process index, direction and payload.
+    /// RUST_LOG overrides --log-level, e.g. RUST_LOG=metamanager::routing=trace
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Append logs to FILE instead of writing them to stderr
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+}
+
+impl LogArgs {
+    pub fn init(&self, level: LevelFilter) -> Result<()> {
+        logging::init(level, self.log_format, self.log_file.as_deref())
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RatingsArgs {
+    /// Rating store written by --ratings
+    #[arg(long, value_name = "FILE", default_value = "ratings.jsonl")]
+    pub ratings: PathBuf,
+
+    /// Show this bot's rating history instead of the leaderboard
+    #[arg(value_name = "BOT")]
+    pub bot: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConnectArgs {
+    /// Where the metamanager listens, tcp://<HOST>:<PORT> or ws://<HOST>:<PORT>
+    pub url: String,
+
+    /// Command for the bot, split like a shell would
+    #[arg(value_name = "COMMAND")]
+    pub command: String,
+
+    /// Working directory for the bot
+    #[arg(long, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+
+    /// Environment variable for the bot given as <KEY>=<VALUE>
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub env: Vec<String>,
+
+    /// How messages are told apart, has to match the metamanager's --framing
+    #[arg(long, value_enum, default_value_t = Framing::Lines)]
+    pub framing: Framing,
+
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = DEFAULT_LOG_LEVEL)]
+    pub log_level: LevelFilter,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Transcript recorded by an earlier run with --transcript
+    pub transcript: PathBuf,
+
+    /// Command for the process under test, split like a shell would
+    #[arg(value_name = "COMMAND")]
+    pub command: String,
+
+    /// Which player of the recorded match the process under test stands in for
+    #[arg(short, long)]
+    pub player: usize,
+
+    /// How long to wait for each recorded response before calling it missing
+    #[arg(long, value_name = "MS", default_value_t = 10_000)]
+    pub timeout_ms: u64,
+
+    /// Working directory for the process under test
+    #[arg(long, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+
+    /// Environment variable for the process under test given as <KEY>=<VALUE>
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub env: Vec<String>,
+
+    /// Seed the recorded match was played with, from the manifest.json next to the
+    /// transcript unless given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Delimiter of the recorded match, from the manifest unless given, ':' without one
+    #[arg(short, long)]
+    pub delim: Option<char>,
+
+    /// How messages are told apart in the recorded match, from the manifest unless given,
+    /// lines without one
+    #[arg(long, value_enum)]
+    pub framing: Option<Framing>,
+
+    /// One of off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = DEFAULT_LOG_LEVEL)]
+    pub log_level: LevelFilter,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// TOML (or .json) file describing the manager, players and match settings.
+    /// Any other flags override what the file says
+    #[arg(short, long, value_name = "FILE")]
+    pub match_file: Option<PathBuf>,
+
+    /// Manager command, followed by one command per player. Each is split like a
+    /// shell would, so quote commands with arguments: "python3 better_random.py".
+    /// A player given as @tcp or @ws connects over the network instead
+    #[arg(value_name = "COMMAND")]
+    pub commands: Vec<String>,
+
+    /// Character separating the player tag from the message
+    #[arg(short, long)]
+    pub delim: Option<char>,
+
+    /// Capacity of each mpsc channel when routing through channels
+    #[arg(long)]
+    pub channel_size: Option<usize>,
+
+    /// How messages are routed between processes
+    #[arg(long, value_enum)]
+    pub routing: Option<Routing>,
+
+    /// How messages are told apart: one per line, or length-prefixed for binary payloads
+    #[arg(long, value_enum)]
+    pub framing: Option<Framing>,
+
+    /// What messages look like: <TAG><DELIM><MESSAGE>, or JSON objects checked by the
+    /// metamanager
+    #[arg(long, value_enum)]
+    pub protocol: Option<Protocol>,
+
+    /// JSON file with a JSON Schema per player message type, implies --protocol json
+    #[arg(long, value_name = "FILE")]
+    pub schema: Option<PathBuf>,
+
+    /// One of off, error, warn, info, debug or trace, warn unless the match file says
+    #[arg(long)]
+    pub log_level: Option<LevelFilter>,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+
+    /// Name for the match in logs
+    #[arg(long, value_name = "ID")]
+    pub match_id: Option<String>,
+
+    /// Write a manifest.json describing the match, the transcript and each process'
+    /// stderr as <label>.stderr.log to DIR
+    #[arg(long, value_name = "DIR")]
+    pub match_dir: Option<PathBuf>,
+
+    /// Record every routed message to FILE as JSON Lines, by default
+    /// transcript.jsonl in the match directory
+    #[arg(long, value_name = "FILE")]
+    pub transcript: Option<PathBuf>,
+
+    /// Seed passed to every local process as MM_SEED, random by default
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+
+    /// Chess clock per player as <BASE>+<INCREMENT> in seconds, e.g. 60+0.5.
+    /// The manager is sent `mm<delim>timeout <player>` when a player runs out
+    #[arg(long, value_name = "BASE+INC")]
+    pub time_control: Option<TimeControl>,
+
+    /// Send the manager `mm<delim>time <player> <elapsed_ms> [<remaining_ms>]` after
+    /// every player message that answered one from the manager
+    #[arg(long)]
+    pub report_times: bool,
+
+    /// Address @tcp players connect to, e.g. 0.0.0.0:7000
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<String>,
+
+    /// Address @ws players connect to, e.g. 0.0.0.0:7001
+    #[arg(long, value_name = "ADDR")]
+    pub ws_listen: Option<String>,
+
+    /// How long to wait for every remote player to connect
+    #[arg(long, value_name = "MS")]
+    pub connect_timeout_ms: Option<u64>,
+
+    /// Command for a spectator: it is fed the match but can't send anything, may be repeated
+    #[arg(long, value_name = "COMMAND")]
+    pub spectator: Vec<String>,
+
+    /// What spectators see: every routed message as JSON, or only the manager's
+    /// `spec<delim><message>` lines
+    #[arg(long, value_enum)]
+    pub spectator_feed: Option<SpectatorFeed>,
+
+    /// Unix socket spectators can connect to while the match runs
+    #[arg(long, value_name = "PATH")]
+    pub spectate_socket: Option<PathBuf>,
+
+    /// Add the result to this rating store, players are rated under their names
+    #[arg(long, value_name = "FILE")]
+    pub ratings: Option<PathBuf>,
+
+    /// Greet players with `hello game=<GAME> protocol=<version> player=<index>
+    /// players=<count> [time=<base>+<inc>]` before the match. Each has to answer
+    /// `hello name=<name> version=<version>`, the manager is told who they are
+    #[arg(long, value_name = "GAME")]
+    pub handshake: Option<String>,
+
+    /// How long each player has to answer the hello
+    #[arg(long, value_name = "MS", requires = "handshake")]
+    pub handshake_timeout_ms: Option<u64>,
+
+    /// Restart a local player that crashes up to N times. The manager is sent
+    /// `mm<delim>disconnect <player>` and has to answer `mm<delim>restart <player>`. The
+    /// player is then sent everything it was sent before and has to answer the same way,
+    /// after which the manager gets `mm<delim>restarted <player>`
+    #[arg(long, value_name = "N")]
+    pub restarts: Option<usize>,
+
+    #[command(flatten)]
+    pub sandbox: SandboxArgs,
+
+    /// Extra argument for the manager, may be repeated
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    pub manager_arg: Vec<String>,
+
+    /// Working directory for the manager
+    #[arg(long, value_name = "DIR")]
+    pub manager_cwd: Option<PathBuf>,
+
+    /// Environment variable for the manager given as <KEY>=<VALUE>, may be repeated
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub manager_env: Vec<String>,
+
+    /// Extra argument for a player given as <PLAYER>=<ARG>, may be repeated
+    #[arg(long, value_name = "PLAYER=ARG", allow_hyphen_values = true)]
+    pub player_arg: Vec<String>,
+
+    /// Working directory for a player given as <PLAYER>=<DIR>
+    #[arg(long, value_name = "PLAYER=DIR")]
+    pub player_cwd: Vec<String>,
+
+    /// Environment variable for a player given as <PLAYER>=<KEY>=<VALUE>, may be repeated
+    #[arg(long, value_name = "PLAYER=KEY=VALUE")]
+    pub player_env: Vec<String>,
+}
+
+// Split a <KEY>=<VALUE> environment flag
+fn parse_env_value(flag: &str, value: &str) -> Result<(String, String)> {
+    let (key, value) = value
+        .split_once('=')
+        .with_context(|| format!("--{flag} expects <KEY>=<VALUE>, got '{value}'"))?;
+    if key.is_empty() {
+        bail!("--{flag}: environment variable name can't be empty");
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+// Split a <PLAYER>=<VALUE> flag and make sure PLAYER exists
+fn parse_player_value<'a>(
+    flag: &str,
+    value: &'a str,
+    players: &[ProcessConfig],
+) -> Result<(usize, &'a str)> {
+    let (player, rest) = value
+        .split_once('=')
+        .with_context(|| format!("--{flag} expects <PLAYER>=<VALUE>, got '{value}'"))?;
+    let player = player
+        .parse::<usize>()
+        .with_context(|| format!("--{flag}: '{player}' is not a player index"))?;
+    if player >= players.len() {
+        bail!(
+            "--{flag}: player {player} doesn't exist, there are only {} players",
+            players.len()
+        );
+    }
+    Ok((player, rest))
+}
+
+// A single local process from a command line plus its --cwd and --env flags
+fn single_process_config(
+    command: &str,
+    cwd: &Option<PathBuf>,
+    env: &[String],
+) -> Result<ProcessConfig> {
+    let mut process = ProcessConfig::from_command_line(command)?;
+    if process.remote.is_some() {
+        bail!("'{command}' has to be a local command");
+    }
+    process.cwd = cwd.clone();
+    for value in env {
+        let (key, value) = parse_env_value("env", value)?;
+        process.env.insert(key, value);
+    }
+    Ok(process)
+}
+
+impl ReplayArgs {
+    // The process under test, with its cwd and environment applied
+    pub fn process_config(&self) -> Result<ProcessConfig> {
+        single_process_config(&self.command, &self.cwd, &self.env)
+    }
+
+    // How the match was set up, as the match directory the transcript is in says
+    // and the command line overrides
+    pub fn recorded_match(&self) -> Result<RecordedMatch> {
+        let dir = self.transcript.parent().unwrap_or(Path::new(""));
+        let mut recorded = if dir.join(MANIFEST_FILE).is_file() {
+            RecordedMatch::from_manifest(&Manifest::read(dir)?)
+        } else {
+            RecordedMatch::default()
+        };
+        if self.seed.is_some() {
+            recorded.seed = self.seed;
+        }
+        if let Some(delim) = self.delim {
+            recorded.delim = delim;
+        }
+        if let Some(framing) = self.framing {
+            recorded.framing = framing;
+        }
+        Ok(recorded)
+    }
+}
+
+impl ConnectArgs {
+    pub fn process_config(&self) -> Result<ProcessConfig> {
+        single_process_config(&self.command, &self.cwd, &self.env)
+    }
+}
+
+impl RunArgs {
+    // Combine the match file (if any) with the command line into one config
+    pub fn into_config(self) -> Result<MatchConfig> {
+        let mut config = match &self.match_file {
+            Some(path) => MatchConfig::from_file(path)?,
+            None => MatchConfig::default(),
+        };
+        if let Some((manager, players)) = self.commands.split_first() {
+            config.manager = ProcessConfig::from_command_line(manager)?;
+            config.players = players
+                .iter()
+                .map(|player| ProcessConfig::from_command_line(player))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(delim) = self.delim {
+            config.delim = delim;
+        }
+        if let Some(channel_size) = self.channel_size {
+            config.channel_size = channel_size;
+        }
+        if let Some(routing) = self.routing {
+            config.routing = routing;
+        }
+        if let Some(framing) = self.framing {
+            config.framing = framing;
+        }
+        if let Some(protocol) = self.protocol {
+            config.protocol = protocol;
+        }
+        if let Some(schema) = self.schema {
+            config.schema = Some(schema);
+            config.protocol = Protocol::Json;
+        }
+        if let Some(log_level) = self.log_level {
+            config.log_level = log_level;
+        }
+        if self.match_id.is_some() {
+            config.id = self.match_id;
+        }
+        if self.match_dir.is_some() {
+            config.match_dir = self.match_dir;
+        }
+        if self.transcript.is_some() {
+            config.transcript = self.transcript;
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if self.time_control.is_some() {
+            config.time_control = self.time_control;
+        }
+        config.report_times |= self.report_times;
+        if self.listen.is_some() {
+            config.listen = self.listen;
+        }
+        if self.ws_listen.is_some() {
+            config.ws_listen = self.ws_listen;
+        }
+        if let Some(connect_timeout_ms) = self.connect_timeout_ms {
+            config.connect_timeout_ms = connect_timeout_ms;
+        }
+        for spectator in &self.spectator {
+            config
+                .spectators
+                .push(ProcessConfig::from_command_line(spectator)?);
+        }
+        if let Some(spectator_feed) = self.spectator_feed {
+            config.spectator_feed = spectator_feed;
+        }
+        if self.spectate_socket.is_some() {
+            config.spectate_socket = self.spectate_socket;
+        }
+        if self.ratings.is_some() {
+            config.ratings = self.ratings;
+        }
+        if let Some(game) = self.handshake {
+            config.handshake = Some(Handshake::new(&game));
+        }
+        if let (Some(handshake), Some(timeout_ms)) =
+            (&mut config.handshake, self.handshake_timeout_ms)
+        {
+            handshake.timeout_ms = timeout_ms;
+        }
+        if let Some(restarts) = self.restarts {
+            config.restarts = restarts;
+        }
+        self.sandbox.apply(&mut config.sandbox);
+        config.manager.args.extend(self.manager_arg);
+        if self.manager_cwd.is_some() {
+            config.manager.cwd = self.manager_cwd;
+        }
+        for value in &self.manager_env {
+            let (key, value) = parse_env_value("manager-env", value)?;
+            config.manager.env.insert(key, value);
+        }
+        for value in &self.player_arg {
+            let (player, arg) = parse_player_value("player-arg", value, &config.players)?;
+            config.players[player].args.push(arg.to_string());
+        }
+        for value in &self.player_cwd {
+            let (player, cwd) = parse_player_value("player-cwd", value, &config.players)?;
+            config.players[player].cwd = Some(PathBuf::from(cwd));
+        }
+        for value in &self.player_env {
+            let (player, env) = parse_player_value("player-env", value, &config.players)?;
+            let (key, value) = parse_env_value("player-env", env)?;
+            config.players[player].env.insert(key, value);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+// Split a [<NAME>=]<COMMAND> bot flag. Only a plain word counts as a name, so commands
+// like `bot --depth=3` aren't mistaken for one
+fn parse_bot(value: &str) -> Result<ProcessConfig> {
+    let (name, command) = match value.split_once('=') {
+        Some((name, command))
+            if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) =>
+        {
+            (Some(name.to_string()), command)
+        }
+        _ => (None, value),
+    };
+    let mut bot = ProcessConfig::from_command_line(command)?;
+    bot.name = name;
+    Ok(bot)
+}
+
+impl TournamentArgs {
+    // Combine the tournament file (if any) with the command line into one config
+    pub fn into_config(self) -> Result<TournamentConfig> {
+        let mut config = match &self.tournament_file {
+            Some(path) => TournamentConfig::from_file(path)?,
+            None => TournamentConfig::default(),
+        };
+        if let Some(referee) = &self.referee {
+            config.referee = ProcessConfig::from_command_line(referee)?;
+        }
+        if !self.bot.is_empty() {
+            config.bots = self
+                .bot
+                .iter()
+                .map(|bot| parse_bot(bot))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if let Some(games) = self.games {
+            config.games_per_pairing = games;
+        }
+        if let Some(concurrency) = self.concurrency {
+            config.concurrency = concurrency;
+        }
+        if self.rounds.is_some() {
+            config.rounds = self.rounds;
+        }
+        if self.gauntlet.is_some() {
+            config.gauntlet = self.gauntlet;
+        }
+        if self.dir.is_some() {
+            config.dir = self.dir;
+        }
+        if let Some(delim) = self.delim {
+            config.delim = delim;
+        }
+        if let Some(routing) = self.routing {
+            config.routing = routing;
+        }
+        if let Some(framing) = self.framing {
+            config.framing = framing;
+        }
+        if let Some(protocol) = self.protocol {
+            config.protocol = protocol;
+        }
+        if let Some(schema) = self.schema {
+            config.schema = Some(schema);
+            config.protocol = Protocol::Json;
+        }
+        if self.time_control.is_some() {
+            config.time_control = self.time_control;
+        }
+        if self.ratings.is_some() {
+            config.ratings = self.ratings;
+        }
+        if let Some(game) = self.handshake {
+            config.handshake = Some(Handshake::new(&game));
+        }
+        if let Some(restarts) = self.restarts {
+            config.restarts = restarts;
+        }
+        self.sandbox.apply(&mut config.sandbox);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl SprtArgs {
+    pub fn into_config(self) -> Result<SprtConfig> {
+        let mut bots = Vec::new();
+        for (value, default_name) in [(&self.new, "new"), (&self.old, "old")] {
+            let mut bot = parse_bot(value)?;
+            bot.name.get_or_insert_with(|| default_name.to_string());
+            bots.push(bot);
+        }
+        let mut games = TournamentConfig {
+            referee: ProcessConfig::from_command_line(&self.referee)?,
+            bots,
+            concurrency: self.concurrency,
+            dir: self.dir,
+            routing: self.routing.unwrap_or_default(),
+            framing: self.framing.unwrap_or_default(),
+            protocol: match self.schema {
+                Some(_) => Protocol::Json,
+                None => self.protocol.unwrap_or_default(),
+            },
+            schema: self.schema,
+            time_control: self.time_control,
+            ratings: self.ratings,
+            handshake: self.handshake.as_deref().map(Handshake::new),
+            restarts: self.restarts.unwrap_or_default(),
+            ..Default::default()
+        };
+        self.sandbox.apply(&mut games.sandbox);
+        if let Some(delim) = self.delim {
+            games.delim = delim;
+        }
+        let config = SprtConfig {
+            games,
+            elo0: self.elo0,
+            elo1: self.elo1,
+            alpha: self.alpha,
+            beta: self.beta,
+            max_games: self.max_games,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
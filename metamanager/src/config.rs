@@ -0,0 +1,354 @@
+use crate::handshake::Handshake;
+use crate::logging::DEFAULT_LOG_LEVEL;
+use crate::net::Remote;
+use crate::process::process_name;
+use crate::protocol::Protocol;
+use crate::sandbox::SandboxConfig;
+use crate::spectators::SpectatorFeed;
+use crate::timing::TimeControl;
+use crate::transport::Framing;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// How messages get from one process to another
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Routing {
+    // Readers write straight into the stdin of the destination process
+    #[default]
+    Direct,
+    // Every process gets a task feeding its stdin from an mpsc channel
+    Channels,
+}
+
+// One process taking part in a match, either the manager or a player
+// In a match file either give path (plus optional args) or a whole command line
+// like command = "python3 better_random.py --depth 6"
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessConfig {
+    // What to call the process in reports, defaults to the file name of path
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Only used while parsing, split into path and args by expand_command
+    #[serde(default)]
+    command: Option<String>,
+    // Extra environment variables, on top of the ones the metamanager inherited
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    // Set for players that connect over the network instead of being spawned
+    #[serde(default)]
+    pub remote: Option<Remote>,
+}
+
+impl ProcessConfig {
+    // A seat for a player that will connect over the network
+    pub fn remote(remote: Remote) -> ProcessConfig {
+        ProcessConfig {
+            remote: Some(remote),
+            ..Default::default()
+        }
+    }
+
+    // Build a config from a shell-like command line, e.g. "python3 bot.py --depth 6"
+    // The special commands @tcp and @ws stand for a player connecting over the network
+    pub fn from_command_line(command_line: &str) -> Result<ProcessConfig> {
+        match command_line.trim() {
+            "@tcp" => return Ok(ProcessConfig::remote(Remote::Tcp)),
+            "@ws" => return Ok(ProcessConfig::remote(Remote::Ws)),
+            _ => {}
+        }
+        let mut words = shell_words::split(command_line)
+            .with_context(|| format!("Could not parse command line '{command_line}'"))?
+            .into_iter();
+        let path = match words.next() {
+            Some(path) => path,
+            None => bail!("Empty command line"),
+        };
+        Ok(ProcessConfig {
+            path,
+            args: words.collect(),
+            ..Default::default()
+        })
+    }
+
+    // Move a `command` given in a match file into path and args
+    fn expand_command(&mut self) -> Result<()> {
+        if let Some(command_line) = self.command.take() {
+            if !self.path.is_empty() {
+                bail!("Give either path or command for a process, not both: '{command_line}'");
+            }
+            let parsed = ProcessConfig::from_command_line(&command_line)?;
+            self.path = parsed.path;
+            // Args given alongside a command are appended to it
+            self.args.splice(0..0, parsed.args);
+        }
+        Ok(())
+    }
+
+    // Finish a process read from a file in directory base: expand its command, and make
+    // relative paths relative to the file, so checked in files can be run from anywhere
+    // Bare program names like python3 are left for PATH lookup
+    pub(crate) fn resolve(&mut self, base: &Path) -> Result<()> {
+        self.expand_command()?;
+        if self.path.contains('/') && Path::new(&self.path).is_relative() {
+            self.path = base.join(&self.path).to_string_lossy().into_owned();
+        }
+        if let Some(cwd) = self.cwd.as_mut() {
+            if cwd.is_relative() {
+                *cwd = base.join(&cwd);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => process_name(&self.path),
+        }
+    }
+
+    // The full command line, quoted so it can be pasted back into a shell
+    pub fn command_line(&self) -> String {
+        match self.remote {
+            Some(Remote::Tcp) => return String::from("@tcp"),
+            Some(Remote::Ws) => return String::from("@ws"),
+            None => {}
+        }
+        shell_words::join(std::iter::once(&self.path).chain(&self.args))
+    }
+}
+
+pub(crate) fn default_delim() -> char {
+    ':'
+}
+// TODO(mbwang): arbitrary channel size, 32 is probably big enough
+fn default_channel_size() -> usize {
+    32
+}
+fn default_log_level() -> LevelFilter {
+    DEFAULT_LOG_LEVEL
+}
+fn default_connect_timeout_ms() -> u64 {
+    60_000
+}
+
+// Everything needed to run a match, loaded from a match file and/or the command line
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchConfig {
+    #[serde(default = "default_delim")]
+    pub delim: char,
+    #[serde(default = "default_channel_size")]
+    pub channel_size: usize,
+    #[serde(default)]
+    pub routing: Routing,
+    // How messages are told apart on the wire, lines unless payloads need to be binary
+    #[serde(default)]
+    pub framing: Framing,
+    #[serde(default)]
+    pub protocol: Protocol,
+    // With the JSON protocol, what player messages have to look like, see GameSchema
+    pub schema: Option<PathBuf>,
+    #[serde(default = "default_log_level")]
+    pub log_level: LevelFilter,
+    // If set, a manifest, the transcript and per-process stderr logs are written under
+    // this directory, see Manifest
+    pub match_dir: Option<PathBuf>,
+    // If set, every routed message is recorded here as JSON Lines. Defaults to
+    // transcript.jsonl in the match directory
+    pub transcript: Option<PathBuf>,
+    // Passed to every local process as MM_SEED, picked at random if not set
+    pub seed: Option<u64>,
+    // Chess clock budget per player, the manager gets `timeout <player>` when one runs out
+    pub time_control: Option<TimeControl>,
+    // Send the manager `time <player> <elapsed_ms> [<remaining_ms>]` after each player message
+    #[serde(default)]
+    pub report_times: bool,
+    // Address remote @tcp players connect to, e.g. 0.0.0.0:7000
+    pub listen: Option<String>,
+    // Address remote @ws players connect to
+    pub ws_listen: Option<String>,
+    // How long to wait for every remote player to connect
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    pub manager: ProcessConfig,
+    pub players: Vec<ProcessConfig>,
+    // Local processes that watch the match without playing, see spectator_feed
+    #[serde(default)]
+    pub spectators: Vec<ProcessConfig>,
+    #[serde(default)]
+    pub spectator_feed: SpectatorFeed,
+    // Unix socket more spectators can join through while the match runs
+    pub spectate_socket: Option<PathBuf>,
+    // If set, the result is added to this rating store, see the ratings command
+    pub ratings: Option<PathBuf>,
+    // If set, local players run sandboxed. The manager and spectators are trusted
+    pub sandbox: Option<SandboxConfig>,
+    // What the match is called in logs
+    pub id: Option<String>,
+    // If set, players have to answer a hello before the match starts
+    pub handshake: Option<Handshake>,
+    // How many times each local player may be restarted after crashing, if the manager
+    // asks for it. The manager is only told about crashes when this is above 0
+    #[serde(default)]
+    pub restarts: usize,
+}
+
+impl Default for MatchConfig {
+    fn default() -> MatchConfig {
+        MatchConfig {
+            delim: default_delim(),
+            channel_size: default_channel_size(),
+            routing: Routing::default(),
+            framing: Framing::default(),
+            protocol: Protocol::default(),
+            schema: None,
+            log_level: default_log_level(),
+            match_dir: None,
+            transcript: None,
+            seed: None,
+            time_control: None,
+            report_times: false,
+            listen: None,
+            ws_listen: None,
+            connect_timeout_ms: default_connect_timeout_ms(),
+            manager: ProcessConfig::default(),
+            players: Vec::new(),
+            spectators: Vec::new(),
+            spectator_feed: SpectatorFeed::default(),
+            spectate_socket: None,
+            ratings: None,
+            sandbox: None,
+            id: None,
+            handshake: None,
+            restarts: 0,
+        }
+    }
+}
+
+impl MatchConfig {
+    // Parse a match file, JSON if it ends in .json and TOML otherwise
+    pub fn from_file(path: &Path) -> Result<MatchConfig> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read match file {}", path.display()))?;
+        let mut config: MatchConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid JSON match file {}", path.display()))?,
+            _ => toml::from_str(&contents)
+                .with_context(|| format!("Invalid TOML match file {}", path.display()))?,
+        };
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for process in std::iter::once(&mut config.manager)
+            .chain(config.players.iter_mut())
+            .chain(config.spectators.iter_mut())
+        {
+            process.resolve(base)?;
+        }
+        for file in [
+            config.match_dir.as_mut(),
+            config.transcript.as_mut(),
+            config.spectate_socket.as_mut(),
+            config.ratings.as_mut(),
+            config.schema.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if file.is_relative() {
+                *file = base.join(&file);
+            }
+        }
+        Ok(config)
+    }
+
+    // Where routed messages are recorded, if anywhere
+    pub fn transcript_path(&self) -> Option<PathBuf> {
+        self.transcript.clone().or_else(|| {
+            self.match_dir
+                .as_ref()
+                .map(|dir| dir.join("transcript.jsonl"))
+        })
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.manager.path.is_empty() || self.players.is_empty() {
+            bail!("The metamanager needs to be run with at least two other processes - a manager and a player");
+        }
+        if self.manager.remote.is_some() {
+            bail!("The manager has to be a local process");
+        }
+        for (player, process) in self.players.iter().enumerate() {
+            match process.remote {
+                Some(_) if !process.path.is_empty() => {
+                    bail!("Player {player} is remote, it can't also have a path to spawn")
+                }
+                Some(Remote::Tcp) if self.listen.is_none() => {
+                    bail!("Player {player} connects over TCP, but there's no --listen address")
+                }
+                Some(Remote::Ws) if self.ws_listen.is_none() => {
+                    bail!("Player {player} connects over websocket, but there's no --ws-listen address")
+                }
+                None if process.path.is_empty() => bail!("Player {player} has no path"),
+                _ => {}
+            }
+        }
+        for (spectator, process) in self.spectators.iter().enumerate() {
+            if process.remote.is_some() {
+                bail!(
+                    "Spectator {spectator} can't be remote, join through --spectate-socket instead"
+                );
+            }
+            if process.path.is_empty() {
+                bail!("Spectator {spectator} has no path");
+            }
+        }
+        // Digits, commas and * make up player tags
+        if self.delim == '\n' || self.delim.is_ascii_digit() || matches!(self.delim, ',' | '*') {
+            bail!("{:?} can't be used as a delimiter", self.delim);
+        }
+        if self.channel_size == 0 {
+            bail!("Channel size must be at least 1");
+        }
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.validate()?;
+        }
+        if let Some(handshake) = &self.handshake {
+            handshake.validate()?;
+        }
+        // Results are rated under player names, and a bot can't be rated against itself
+        if self.ratings.is_some() {
+            let names = self
+                .players
+                .iter()
+                .map(ProcessConfig::display_name)
+                .collect::<Vec<_>>();
+            for (player, name) in names.iter().enumerate() {
+                if let Some(other) = names[..player].iter().position(|other| other == name) {
+                    bail!(
+                        "Players {other} and {player} are both called '{name}', give them different names to rate them"
+                    );
+                }
+            }
+        }
+        if self.schema.is_some() && self.protocol != Protocol::Json {
+            bail!("A schema only works with the JSON protocol");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+// Control messages are what the metamanager itself sends to the manager, tagged with
+// CONTROL_TAG where a player index would normally be, e.g. `mm:timeout 1`
+pub const CONTROL_TAG: &str = "mm";
+
+// Format a control message, ready to be framed and sent to the manager
+pub fn tagged_control(delim: char, message: &str) -> Vec<u8> {
+    format!("{CONTROL_TAG}{delim}{message}").into_bytes()
+}
@@ -0,0 +1,155 @@
+use crate::timing::TimeControl;
+use crate::transcript::{Direction, Transcript};
+use crate::transport::{MessageReader, MessageWriter};
+use anyhow::{bail, Context, Result};
+use futures::future::join_all;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Version of the protocol players are spoken to in, bumped whenever it changes in a way
+// players could notice
+pub const PROTOCOL_VERSION: u32 = 1;
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+// Opening handshake. Before anything else, every player is sent
+//   hello game=<game> protocol=<version> player=<index> players=<count> [time=<base>+<inc>]
+// and has to answer
+//   hello name=<name> version=<version> [game=<game>] [protocol=<version>]
+// or `error <reason>` to refuse. A player naming another game or protocol version fails the
+// match before it starts. Unknown keys are ignored either way so more can be added later
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Handshake {
+    pub game: String,
+    // How long each player has to answer
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Handshake {
+    pub fn new(game: &str) -> Handshake {
+        Handshake {
+            game: game.to_string(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.game.is_empty() || self.game.contains(char::is_whitespace) {
+            bail!("'{}' can't be used as a game name", self.game);
+        }
+        Ok(())
+    }
+
+    fn hello(&self, player: usize, players: usize, time_control: Option<TimeControl>) -> String {
+        let mut hello = format!(
+            "hello game={} protocol={PROTOCOL_VERSION} player={player} players={players}",
+            self.game
+        );
+        if let Some(time_control) = time_control {
+            hello.push_str(&format!(
+                " time={}+{}",
+                time_control.base_ms.as_secs_f64(),
+                time_control.increment_ms.as_secs_f64()
+            ));
+        }
+        hello
+    }
+}
+
+// Who a player said it was
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PlayerHello {
+    pub name: String,
+    pub version: String,
+}
+
+impl PlayerHello {
+    pub fn parse(reply: &str, handshake: &Handshake) -> Result<PlayerHello> {
+        let mut words = reply.split_whitespace();
+        match words.next() {
+            Some("hello") => {}
+            Some("error") => bail!("refused: {}", words.collect::<Vec<_>>().join(" ")),
+            _ => bail!("answered the hello with '{reply}', does it speak the handshake?"),
+        }
+        let (mut name, mut version) = (None, None);
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .with_context(|| format!("'{word}' in its hello isn't a key=value pair"))?;
+            match key {
+                "name" => name = Some(value.to_string()),
+                "version" => version = Some(value.to_string()),
+                "game" if value != handshake.game => {
+                    bail!("plays {value}, but this match is {}", handshake.game)
+                }
+                "protocol" if value != PROTOCOL_VERSION.to_string() => {
+                    bail!("speaks protocol {value}, but the metamanager speaks {PROTOCOL_VERSION}")
+                }
+                _ => {}
+            }
+        }
+        match (name, version) {
+            (Some(name), Some(version)) => Ok(PlayerHello { name, version }),
+            _ => bail!("didn't give a name and version in its hello: '{reply}'"),
+        }
+    }
+}
+
+// Both the hello and the answer are recorded, so a replayed player is greeted too
+async fn greet_player(
+    (reader, writer): &mut (MessageReader, MessageWriter),
+    player: usize,
+    hello: String,
+    handshake: &Handshake,
+    transcript: &Transcript,
+) -> Result<PlayerHello> {
+    writer.send(hello.as_bytes()).await?;
+    transcript
+        .record(Direction::ManagerToPlayer, player, hello.as_bytes(), None)
+        .await?;
+    let timeout = Duration::from_millis(handshake.timeout_ms);
+    let reply = match tokio::time::timeout(timeout, reader.next_line()).await {
+        Ok(reply) => reply?,
+        Err(_) => bail!("didn't answer the hello within {}ms", handshake.timeout_ms),
+    };
+    let reply = match reply {
+        Some(reply) => reply,
+        None => bail!("exited instead of answering the hello"),
+    };
+    transcript
+        .record(Direction::PlayerToManager, player, reply.as_bytes(), None)
+        .await?;
+    PlayerHello::parse(&reply, handshake)
+}
+
+// Shake hands with every player at once, failing if any of them doesn't go along
+pub(crate) async fn greet(
+    players: &mut [(MessageReader, MessageWriter)],
+    handshake: &Handshake,
+    time_control: Option<TimeControl>,
+    transcript: &Transcript,
+) -> Result<Vec<PlayerHello>> {
+    let count = players.len();
+    let replies = join_all(players.iter_mut().enumerate().map(|(player, endpoint)| {
+        greet_player(
+            endpoint,
+            player,
+            handshake.hello(player, count, time_control),
+            handshake,
+            transcript,
+        )
+    }))
+    .await;
+    let mut hellos = Vec::new();
+    for (player, reply) in replies.into_iter().enumerate() {
+        let hello = reply.with_context(|| format!("Player {player} failed the handshake"))?;
+        info!("{player}: is {} {}", hello.name, hello.version);
+        hellos.push(hello);
+    }
+    Ok(hellos)
+}
@@ -0,0 +1,29 @@
+// Routing core of the metamanager: a manager (referee) process talks to any number of
+// players through one stream of `<player><delim><message>` lines. Build a Match from
+// anything implementing Transport, or from a MatchConfig describing processes to spawn
+pub mod config;
+pub mod control;
+pub mod handshake;
+pub mod logging;
+pub mod manifest;
+pub mod matches;
+pub mod net;
+pub mod process;
+pub mod protocol;
+pub mod ratings;
+pub mod replay;
+pub mod report;
+mod restart;
+mod routing;
+pub mod sandbox;
+pub mod spectators;
+pub mod sprt;
+pub mod timing;
+pub mod tournament;
+pub mod transcript;
+pub mod transport;
+pub mod usage;
+
+pub use config::{MatchConfig, ProcessConfig, Routing};
+pub use matches::{Match, MatchBuilder};
+pub use transport::{BoxedTransport, Framing, Transport};
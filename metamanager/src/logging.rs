@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use env_logger::{Builder, Target};
+use log::kv::{self, Key, VisitSource};
+use log::{LevelFilter, Record};
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+
+// What every command logs unless told otherwise
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Warn;
+
+// How log lines are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    // env_logger's usual human readable lines
+    #[default]
+    Text,
+    // One JSON object per line with time, level, target, message, the match id if there is
+    // one, and any fields the record carries: process (index, the manager is 0), direction
+    // and payload for routed messages
+    Json,
+}
+
+tokio::task_local! {
+    static MATCH_ID: String;
+}
+
+// Tag every log record made while running future with match_id
+pub(crate) async fn in_match<F: Future>(match_id: String, future: F) -> F::Output {
+    MATCH_ID.scope(match_id, future).await
+}
+
+// tokio::spawn, but the task stays in the match of the task spawning it. Task locals
+// aren't inherited, so anything a match spawns has to go through here to keep its id
+pub(crate) fn spawn_in_match<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match current_match_id() {
+        Some(match_id) => tokio::spawn(in_match(match_id, future)),
+        None => tokio::spawn(future),
+    }
+}
+
+// The match whose task a record came from, if any
+pub fn current_match_id() -> Option<String> {
+    MATCH_ID.try_with(String::clone).ok()
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            Value::from(number)
+        } else if let Some(number) = value.to_i64() {
+            Value::from(number)
+        } else if let Some(flag) = value.to_bool() {
+            Value::from(flag)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+// A record as one line of JSON
+pub fn json_line(record: &Record) -> String {
+    let mut line = Map::new();
+    line.insert(
+        "time".to_string(),
+        Value::from(humantime::format_rfc3339_millis(SystemTime::now()).to_string()),
+    );
+    line.insert("level".to_string(), Value::from(record.level().as_str()));
+    line.insert("target".to_string(), Value::from(record.target()));
+    if let Some(match_id) = current_match_id() {
+        line.insert("match_id".to_string(), Value::from(match_id));
+    }
+    line.insert(
+        "message".to_string(),
+        Value::from(record.args().to_string()),
+    );
+    // Fields are best effort, the message already says what happened
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+    Value::Object(line).to_string()
+}
+
+// Log to stderr, or to file if given, at level unless RUST_LOG says otherwise.
+// RUST_LOG takes env_logger's usual filters, e.g. `metamanager::routing=trace`
+pub fn init(level: LevelFilter, format: LogFormat, file: Option<&Path>) -> Result<()> {
+    let mut builder = Builder::new();
+    builder.filter_level(level);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    match file {
+        Some(path) => {
+            let file = File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Could not open log file {}", path.display()))?;
+            builder.target(Target::Pipe(Box::new(file)));
+        }
+        None => {
+            builder.target(Target::Stderr);
+        }
+    }
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record)));
+    }
+    builder.try_init()?;
+    Ok(())
+}
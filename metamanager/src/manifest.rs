@@ -0,0 +1,211 @@
+use crate::config::{default_delim, MatchConfig, ProcessConfig};
+use crate::handshake::PlayerHello;
+use crate::process::process_label;
+use crate::report::{MatchReport, MatchResult};
+use crate::transport::Framing;
+use crate::usage::ProcessUsage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::process::Command;
+
+// Environment variable every local process finds the match's seed in, so a game that
+// draws its randomness from it can be played again exactly
+pub const SEED_VAR: &str = "MM_SEED";
+
+// Environment variables every local process finds the match's --delim and --framing in,
+// so it can read and write messages without being configured separately
+pub const DELIM_VAR: &str = "MM_DELIM";
+pub const FRAMING_VAR: &str = "MM_FRAMING";
+
+// The seed, delim and framing are given to the process on top of its own environment
+pub(crate) fn match_env(
+    process: &ProcessConfig,
+    seed: Option<u64>,
+    delim: char,
+    framing: Framing,
+) -> ProcessConfig {
+    let mut process = process.clone();
+    if let Some(seed) = seed {
+        process.env.insert(SEED_VAR.to_string(), seed.to_string());
+    }
+    process.env.insert(DELIM_VAR.to_string(), delim.to_string());
+    process
+        .env
+        .insert(FRAMING_VAR.to_string(), framing.as_str().to_string());
+    process
+}
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+// A seed for a match that wasn't given one
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// One process in a match
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Participant {
+    // manager, player<N> or spectator<N>, like stderr log names
+    pub label: String,
+    pub name: String,
+    pub command: String,
+    // `git describe` of the repository the program lives in, if it's in one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_describe: Option<String>,
+    // Who a player said it was, if there was a handshake
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hello: Option<PlayerHello>,
+    // Relative to the match directory, none for remote players
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_log: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ProcessUsage>,
+}
+
+// What a match directory holds besides the transcript and stderr logs: everything needed to
+// know what was played, by whom and how it went, without the terminal output
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Manifest {
+    pub id: String,
+    pub metamanager_version: String,
+    pub seed: u64,
+    // What the match's processes were told in DELIM_VAR and FRAMING_VAR, so replays can too
+    #[serde(default = "default_delim")]
+    pub delim: char,
+    #[serde(default)]
+    pub framing: Framing,
+    // RFC 3339 timestamps
+    pub started: String,
+    pub finished: String,
+    pub participants: Vec<Participant>,
+    // Relative to the match directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<MatchResult>,
+    // Why the match failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
+
+// Best effort, a program outside a git checkout or without git around just has no version
+async fn git_describe(program: &Path) -> Option<String> {
+    let dir = program.parent().filter(|dir| !dir.as_os_str().is_empty())?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["describe", "--always", "--dirty", "--tags"])
+        .output()
+        .await
+        .ok()?;
+    let described = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !described.trim().is_empty()).then(|| described.trim().to_string())
+}
+
+impl Manifest {
+    // Describe a match that ran from config, report is its error if it failed
+    pub async fn new(
+        config: &MatchConfig,
+        started: SystemTime,
+        report: Result<&MatchReport, String>,
+    ) -> Manifest {
+        let match_dir = config.match_dir.as_deref();
+        let relative = |path: &Path| {
+            let path = match_dir
+                .and_then(|dir| path.strip_prefix(dir).ok())
+                .unwrap_or(path);
+            path.to_string_lossy().into_owned()
+        };
+        let labelled = std::iter::once(&config.manager)
+            .chain(&config.players)
+            .enumerate()
+            .map(|(idx, process)| (process_label(idx), process))
+            .chain(
+                config
+                    .spectators
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, process)| (format!("spectator{idx}"), process)),
+            );
+        let mut participants = Vec::new();
+        for (idx, (label, process)) in labelled.enumerate() {
+            let local = process.remote.is_none();
+            // Interpreted bots are better described by their script than by the interpreter
+            let program = if process.path.contains('/') {
+                Some(process.path.as_str())
+            } else {
+                process.args.iter().map(String::as_str).find(|arg| {
+                    let cwd = process.cwd.as_deref().unwrap_or(Path::new("."));
+                    cwd.join(arg).is_file()
+                })
+            };
+            let program = program.map(|program| match &process.cwd {
+                Some(cwd) => cwd.join(program),
+                None => Path::new(program).to_path_buf(),
+            });
+            let git_describe = match (local, program) {
+                (true, Some(program)) => git_describe(&program).await,
+                _ => None,
+            };
+            let finished = report.as_ref().ok();
+            let usage = finished.and_then(|report| match idx {
+                0 => Some(report.manager.clone()),
+                _ => report.players.get(idx - 1).cloned(),
+            });
+            participants.push(Participant {
+                name: process.display_name(),
+                command: process.command_line(),
+                git_describe,
+                hello: idx
+                    .checked_sub(1)
+                    .and_then(|player| finished?.hellos.get(player).cloned()),
+                stderr_log: match_dir
+                    .filter(|_| local)
+                    .map(|_| format!("{label}.stderr.log")),
+                usage,
+                label,
+            });
+        }
+        Manifest {
+            id: config.id.clone().unwrap_or_default(),
+            metamanager_version: env!("CARGO_PKG_VERSION").to_string(),
+            seed: config.seed.unwrap_or_default(),
+            delim: config.delim,
+            framing: config.framing,
+            started: timestamp(started),
+            finished: timestamp(SystemTime::now()),
+            participants,
+            transcript: config.transcript_path().map(|path| relative(&path)),
+            result: report
+                .as_ref()
+                .ok()
+                .and_then(|report| report.result.clone()),
+            error: report.err(),
+        }
+    }
+
+    pub async fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(&path, json + "\n")
+            .await
+            .with_context(|| format!("Could not write manifest {}", path.display()))
+    }
+
+    // The manifest of a match directory written by an earlier run
+    pub fn read(dir: &Path) -> Result<Manifest> {
+        let path = dir.join(MANIFEST_FILE);
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read manifest {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+}
@@ -0,0 +1,660 @@
+use crate::config::{MatchConfig, Routing};
+use crate::control::tagged_control;
+use crate::handshake::{greet, Handshake};
+use crate::logging::{in_match, spawn_in_match};
+use crate::manifest::{match_env, random_seed, Manifest};
+use crate::net;
+use crate::process::{
+    child_transport, make_child_stderr_reader, process_label, process_name, spawn_process,
+    spawn_sandboxed, tag_and_echo_stderr, EXIT_GRACE,
+};
+use crate::protocol::{manager_bridge, GameSchema, PlayerCheck, Protocol};
+use crate::report::{MatchReport, Reports};
+use crate::restart::{keep_alive, restartable, PlayerProcess, Restartable};
+use crate::routing::{
+    echo_channel_to_stdin, echo_tagged_stdout_to_channel, route_and_echo_tagged_messages,
+    tag_and_echo_messages, tag_and_echo_stdout_to_channel,
+};
+use crate::sandbox::{watch_sandboxed, SandboxedChild};
+use crate::spectators::{SpectatorFeed, SpectatorSocket, Spectators};
+use crate::timing::{clock_watchdog, Clocks, TimeControl};
+use crate::transcript::Transcript;
+use crate::transport::{
+    split_transport, BoxedTransport, Framing, MessageReader, MessageWriter, Transport,
+};
+use crate::usage::{collect_usage, ByteCounts, ProcessStats};
+use anyhow::{bail, Result};
+use futures::future::{join_all, BoxFuture, FutureExt};
+use log::{debug, info, trace, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs::File;
+use tokio::io::BufWriter;
+use tokio::process::Child;
+use tokio::sync::mpsc::channel;
+
+fn next_match_id() -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    format!("match{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+// Where a process' stderr is saved, if the match has a directory
+fn stderr_log_path(match_dir: Option<&Path>, label: &str) -> Option<PathBuf> {
+    match_dir.map(|dir| dir.join(format!("{label}.stderr.log")))
+}
+
+async fn stderr_log(match_dir: Option<&Path>, label: &str) -> Result<Option<BufWriter<File>>> {
+    match stderr_log_path(match_dir, label) {
+        Some(path) => Ok(Some(BufWriter::new(File::create(path).await?))),
+        None => Ok(None),
+    }
+}
+
+// Settings for a match plus the transports of everyone in it, see Match::builder
+pub struct MatchBuilder {
+    id: Option<String>,
+    delim: char,
+    channel_size: usize,
+    routing: Routing,
+    framing: Framing,
+    protocol: Protocol,
+    schema: Option<Arc<GameSchema>>,
+    transcript: Transcript,
+    time_control: Option<TimeControl>,
+    report_times: bool,
+    handshake: Option<Handshake>,
+    manager: Option<BoxedTransport>,
+    players: Vec<BoxedTransport>,
+    spectator_feed: SpectatorFeed,
+    spectators: Vec<BoxedTransport>,
+    spectate_socket: Option<PathBuf>,
+    // Things that have to live as long as the match, like the processes behind transports
+    side_tasks: Vec<BoxFuture<'static, Result<()>>>,
+    children: Vec<Child>,
+    // Sandboxed players and their player index
+    sandboxed: Vec<(usize, SandboxedChild)>,
+    // CPU and memory use of local processes, by process index (the manager is 0)
+    stats: Vec<(usize, ProcessStats)>,
+    // Players that are restarted if they crash, and their player index
+    restartable: Vec<(usize, Restartable)>,
+}
+
+impl MatchBuilder {
+    // Name for the match in logs, unique within this process by default
+    pub fn id(mut self, id: impl Into<String>) -> MatchBuilder {
+        self.id = Some(id.into());
+        self
+    }
+    pub fn delim(mut self, delim: char) -> MatchBuilder {
+        self.delim = delim;
+        self
+    }
+    pub fn channel_size(mut self, channel_size: usize) -> MatchBuilder {
+        self.channel_size = channel_size;
+        self
+    }
+    pub fn routing(mut self, routing: Routing) -> MatchBuilder {
+        self.routing = routing;
+        self
+    }
+    // How messages are told apart, the same for the manager and every player
+    pub fn framing(mut self, framing: Framing) -> MatchBuilder {
+        self.framing = framing;
+        self
+    }
+    pub fn protocol(mut self, protocol: Protocol) -> MatchBuilder {
+        self.protocol = protocol;
+        self
+    }
+    // What player messages have to look like, only checked with the JSON protocol
+    pub fn schema(mut self, schema: Option<GameSchema>) -> MatchBuilder {
+        self.schema = schema.map(Arc::new);
+        self
+    }
+    pub fn transcript(mut self, transcript: Transcript) -> MatchBuilder {
+        self.transcript = transcript;
+        self
+    }
+    pub fn time_control(mut self, time_control: Option<TimeControl>) -> MatchBuilder {
+        self.time_control = time_control;
+        self
+    }
+    pub fn report_times(mut self, report_times: bool) -> MatchBuilder {
+        self.report_times = report_times;
+        self
+    }
+    // Make players answer a hello before the match starts, see Handshake
+    pub fn handshake(mut self, handshake: Option<Handshake>) -> MatchBuilder {
+        self.handshake = handshake;
+        self
+    }
+    // The manager (referee) everyone's messages go through
+    pub fn manager(mut self, transport: impl Transport) -> MatchBuilder {
+        self.manager = Some(Box::new(transport));
+        self
+    }
+    // Add the next player, players are tagged 0, 1, 2... in the order they're added
+    pub fn player(mut self, transport: impl Transport) -> MatchBuilder {
+        self.players.push(Box::new(transport));
+        self
+    }
+    // What spectators are shown, every routed message or only the manager's `spec` lines
+    pub fn spectator_feed(mut self, feed: SpectatorFeed) -> MatchBuilder {
+        self.spectator_feed = feed;
+        self
+    }
+    // Add someone watching from the start, they don't take a player index
+    pub fn spectator(mut self, transport: impl Transport) -> MatchBuilder {
+        self.spectators.push(Box::new(transport));
+        self
+    }
+    // Let more spectators join through a unix socket at path while the match runs
+    pub fn spectate_socket(mut self, path: Option<PathBuf>) -> MatchBuilder {
+        self.spectate_socket = path;
+        self
+    }
+
+    pub fn build(self) -> Result<Match> {
+        let manager = match self.manager {
+            Some(manager) => manager,
+            None => bail!("A match needs a manager"),
+        };
+        if self.players.is_empty() {
+            bail!("A match needs at least one player");
+        }
+        if self.channel_size == 0 {
+            bail!("Channel size must be at least 1");
+        }
+        Ok(Match {
+            id: self.id.unwrap_or_else(next_match_id),
+            delim: self.delim,
+            channel_size: self.channel_size,
+            routing: self.routing,
+            framing: self.framing,
+            protocol: self.protocol,
+            schema: self.schema,
+            transcript: self.transcript,
+            time_control: self.time_control,
+            report_times: self.report_times,
+            handshake: self.handshake,
+            manager,
+            players: self.players,
+            spectator_feed: self.spectator_feed,
+            spectators: self.spectators,
+            spectate_socket: self.spectate_socket,
+            side_tasks: self.side_tasks,
+            children: self.children,
+            sandboxed: self.sandboxed,
+            stats: self.stats,
+            restartable: self.restartable,
+        })
+    }
+}
+
+// A manager and its players, ready to have messages routed between them
+pub struct Match {
+    id: String,
+    delim: char,
+    channel_size: usize,
+    routing: Routing,
+    framing: Framing,
+    protocol: Protocol,
+    schema: Option<Arc<GameSchema>>,
+    transcript: Transcript,
+    time_control: Option<TimeControl>,
+    report_times: bool,
+    handshake: Option<Handshake>,
+    manager: BoxedTransport,
+    players: Vec<BoxedTransport>,
+    spectator_feed: SpectatorFeed,
+    spectators: Vec<BoxedTransport>,
+    spectate_socket: Option<PathBuf>,
+    side_tasks: Vec<BoxFuture<'static, Result<()>>>,
+    children: Vec<Child>,
+    sandboxed: Vec<(usize, SandboxedChild)>,
+    stats: Vec<(usize, ProcessStats)>,
+    restartable: Vec<(usize, Restartable)>,
+}
+
+impl Match {
+    pub fn builder() -> MatchBuilder {
+        let defaults = MatchConfig::default();
+        MatchBuilder {
+            id: None,
+            delim: defaults.delim,
+            channel_size: defaults.channel_size,
+            routing: defaults.routing,
+            framing: defaults.framing,
+            protocol: defaults.protocol,
+            schema: None,
+            transcript: Transcript::default(),
+            time_control: None,
+            report_times: false,
+            handshake: None,
+            manager: None,
+            players: Vec::new(),
+            spectator_feed: SpectatorFeed::default(),
+            spectators: Vec::new(),
+            spectate_socket: None,
+            side_tasks: Vec::new(),
+            children: Vec::new(),
+            sandboxed: Vec::new(),
+            stats: Vec::new(),
+            restartable: Vec::new(),
+        }
+    }
+
+    // Spawn every local process in config and wait for remote players to connect
+    pub async fn from_config(config: &MatchConfig) -> Result<Match> {
+        config.validate()?;
+        if let Some(dir) = &config.match_dir {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let transcript = match config.transcript_path() {
+            Some(path) => Transcript::create(&path).await?,
+            None => Transcript::default(),
+        };
+        let schema = match &config.schema {
+            Some(path) => Some(GameSchema::from_file(path)?),
+            None => None,
+        };
+        let mut builder = Match::builder();
+        if let Some(id) = &config.id {
+            builder = builder.id(id);
+        }
+        builder = builder
+            .delim(config.delim)
+            .channel_size(config.channel_size)
+            .routing(config.routing)
+            .framing(config.framing)
+            .protocol(config.protocol)
+            .schema(schema)
+            .transcript(transcript)
+            .time_control(config.time_control)
+            .report_times(config.report_times)
+            .handshake(config.handshake.clone())
+            .spectator_feed(config.spectator_feed)
+            .spectate_socket(config.spectate_socket.clone());
+        // Spawn the manager and every local player, remote players get their seats filled below
+        let mut transports: Vec<Option<BoxedTransport>> = Vec::new();
+        let mut remote_seats = Vec::new();
+        for (idx, process_config) in std::iter::once(&config.manager)
+            .chain(&config.players)
+            .enumerate()
+        {
+            let process_config =
+                &match_env(process_config, config.seed, config.delim, config.framing);
+            let label = process_label(idx);
+            if let Some(remote) = process_config.remote {
+                remote_seats.push((idx - 1, remote));
+                transports.push(None);
+                continue;
+            }
+            let mut sandboxed = match &config.sandbox {
+                Some(sandbox) if idx > 0 => Some(spawn_sandboxed(process_config, &label, sandbox)?),
+                _ => None,
+            };
+            let mut spawned = None;
+            let process = match sandboxed.as_mut() {
+                Some(sandboxed) => &mut sandboxed.child,
+                None => spawned.insert(spawn_process(process_config, &label)?),
+            };
+            let stats = process.id().map(ProcessStats::track);
+            builder
+                .stats
+                .extend(stats.clone().map(|stats| (idx, stats)));
+            let log_file = stderr_log(config.match_dir.as_deref(), &label).await?;
+            let prefix = format!("[{label} {}]", process_name(&process_config.path));
+            builder.side_tasks.push(
+                tag_and_echo_stderr(make_child_stderr_reader(process), prefix.clone(), log_file)
+                    .boxed(),
+            );
+            let process = child_transport(process);
+            if idx > 0 && config.restarts > 0 {
+                let player_process = spawned
+                    .take()
+                    .map(PlayerProcess::Plain)
+                    .or_else(|| sandboxed.take().map(PlayerProcess::Sandboxed));
+                if let Some(player_process) = player_process {
+                    let (player, transport) = restartable(
+                        process_config,
+                        label.clone(),
+                        prefix,
+                        stderr_log_path(config.match_dir.as_deref(), &label),
+                        config.sandbox.clone(),
+                        (player_process, process),
+                        stats,
+                        config.restarts,
+                    );
+                    builder.restartable.push((idx - 1, player));
+                    transports.push(Some(transport));
+                    continue;
+                }
+            }
+            transports.push(Some(Box::new(process) as BoxedTransport));
+            builder.children.extend(spawned);
+            builder
+                .sandboxed
+                .extend(sandboxed.map(|sandboxed| (idx - 1, sandboxed)));
+        }
+        for (idx, process_config) in config.spectators.iter().enumerate() {
+            // Spectators are told the framing they're fed in
+            let framing = config.spectator_feed.framing(config.framing);
+            let process_config = &match_env(process_config, config.seed, config.delim, framing);
+            let label = format!("spectator{idx}");
+            let mut process = spawn_process(process_config, &label)?;
+            let log_file = stderr_log(config.match_dir.as_deref(), &label).await?;
+            let prefix = format!("[{label} {}]", process_name(&process_config.path));
+            // Not a side task: spectators only exit after the match is done waiting on those
+            spawn_in_match(tag_and_echo_stderr(
+                make_child_stderr_reader(&mut process),
+                prefix,
+                log_file,
+            ));
+            builder
+                .spectators
+                .push(Box::new(child_transport(&mut process)));
+            builder.children.push(process);
+        }
+        debug!(
+            "Running with {} local processes, {} of them sandboxed",
+            builder.children.len() + builder.sandboxed.len(),
+            builder.sandboxed.len()
+        );
+        if !remote_seats.is_empty() {
+            for (player, transport) in net::accept_remote_players(
+                &remote_seats,
+                config.listen.as_deref(),
+                config.ws_listen.as_deref(),
+                config.connect_timeout(),
+                config.framing,
+            )
+            .await?
+            {
+                transports[player + 1] = Some(transport);
+            }
+        }
+        let mut transports = transports
+            .into_iter()
+            .map(|transport| transport.expect("every seat is filled by now"));
+        builder.manager = transports.next();
+        builder.players = transports.collect();
+        builder.build()
+    }
+
+    // Spawn and run the match config describes, with a seed picked if it has none. A match
+    // with a directory gets a manifest there once it's over, even if it failed
+    pub async fn run_config(config: &MatchConfig) -> Result<MatchReport> {
+        let mut config = config.clone();
+        let id = config.id.get_or_insert_with(next_match_id).clone();
+        config.seed.get_or_insert_with(random_seed);
+        let started = SystemTime::now();
+        // Setting up is part of the match too, as far as logs go
+        let report = in_match(id, async { Match::from_config(&config).await?.run().await }).await;
+        if let Some(dir) = &config.match_dir {
+            let outcome = report.as_ref().map_err(|err| format!("{err:#}"));
+            let manifest = Manifest::new(&config, started, outcome).await;
+            if let Err(err) = manifest.write(dir).await {
+                warn!("{err:#}");
+            }
+        }
+        report
+    }
+
+    // Do the thing
+    // Returns once the manager and every player have closed their output
+    pub async fn run(self) -> Result<MatchReport> {
+        in_match(self.id.clone(), self.route()).await
+    }
+
+    async fn route(self) -> Result<MatchReport> {
+        let delim = self.delim;
+        let framing = self.framing;
+        let chan_size = self.channel_size;
+        let mut tasks = self.side_tasks;
+        let report_times = self.report_times;
+        let reports = Reports::default();
+        // Spectators subscribe before anything is routed, so they see the whole match
+        let spectators = Spectators::new(self.spectator_feed, framing);
+        let watchers = self
+            .spectators
+            .into_iter()
+            .enumerate()
+            .map(|(idx, transport)| spectators.watch(transport, format!("spectator {idx}")))
+            .collect::<Vec<_>>();
+        let spectator_socket = match &self.spectate_socket {
+            Some(path) => Some(SpectatorSocket::bind(path, spectators.clone())?),
+            None => None,
+        };
+        let transcript = if watchers.is_empty() && spectator_socket.is_none() {
+            self.transcript
+        } else {
+            self.transcript.spectated_by(spectators.clone()).await
+        };
+        let clocks = Clocks::new(self.players.len(), self.time_control);
+        // Keep processes alive (they're killed on drop) until every task is done
+        let _children = self.children;
+        let stats = |idx: usize| {
+            self.stats
+                .iter()
+                .find(|(process, _)| *process == idx)
+                .map(|(_, stats)| stats.clone())
+        };
+        let sandboxed = self
+            .sandboxed
+            .into_iter()
+            .map(|(player, child)| (player, child, stats(player + 1)))
+            .collect::<Vec<_>>();
+        let restartable = self.restartable;
+        // Count the bytes through every transport, the manager first
+        let participants = (0..=self.players.len())
+            .map(|idx| (ByteCounts::default(), stats(idx)))
+            .collect::<Vec<_>>();
+        let manager = participants[0].0.count(self.manager);
+        let players = self
+            .players
+            .into_iter()
+            .zip(&participants[1..])
+            .map(|(player, (counts, _))| counts.count(player))
+            .collect::<Vec<_>>();
+        debug!("Running with {} players", players.len());
+        // Both modes behave the same, see benches/routing.rs for how they compare
+        let use_channels = self.routing == Routing::Channels;
+        let mut manager_endpoint = split_transport(manager, framing);
+        // With the JSON protocol the router still sees tagged messages, bridges translate
+        if self.protocol == Protocol::Json {
+            let (endpoint, bridge) = manager_bridge(manager_endpoint, delim, framing);
+            manager_endpoint = endpoint;
+            tasks.push(bridge);
+        }
+        let (manager_stdout, mut manager_stdin) = manager_endpoint;
+        let mut endpoints = players
+            .into_iter()
+            .map(|player| split_transport(player, framing))
+            .collect::<Vec<_>>();
+        let mut hellos = Vec::new();
+        {
+            // Everything for the manager that doesn't come from the manager's own router
+            // goes through here: player messages when routing through channels, and control
+            // messages from the metamanager's own tasks
+            let (p2m_sender, p2m_receiver) = channel::<Vec<u8>>(chan_size);
+            // Restartable players are only reachable through keep_alive, so it has to run
+            // from the start, even before the handshake
+            let keep_alives = restartable
+                .into_iter()
+                .map(|(player, restartable)| {
+                    spawn_in_match(keep_alive(
+                        restartable,
+                        player,
+                        reports.restarts.clone(),
+                        p2m_sender.clone(),
+                        delim,
+                        framing,
+                    ))
+                })
+                .collect::<Vec<_>>();
+            if let Some(handshake) = &self.handshake {
+                let greeted = async {
+                    let hellos =
+                        greet(&mut endpoints, handshake, self.time_control, &transcript).await?;
+                    // Let the manager know who it's refereeing
+                    for (player, hello) in hellos.iter().enumerate() {
+                        let message = format!(
+                            "player {player} name={} version={}",
+                            hello.name, hello.version
+                        );
+                        manager_stdin.send(&tagged_control(delim, &message)).await?;
+                    }
+                    anyhow::Ok(hellos)
+                };
+                hellos = match greeted.await {
+                    Ok(hellos) => hellos,
+                    Err(err) => {
+                        // Nothing awaits them once the match fails, so they'd run on
+                        for task in &keep_alives {
+                            task.abort();
+                        }
+                        return Err(err);
+                    }
+                };
+            }
+            for task in keep_alives {
+                tasks.push(async move { task.await? }.boxed());
+            }
+            // Players only speak JSON once the handshake is done, so they're checked by
+            // the router rather than a bridge
+            let check = PlayerCheck::new(self.protocol, self.schema.clone());
+            if use_channels {
+                let mut m2p_senders = Vec::new();
+                for (idx, (player_stdout, player_stdin)) in endpoints.into_iter().enumerate() {
+                    trace!("Setting up tasks for player {idx}");
+                    let (m2p_sender, m2p_receiver) = channel::<Vec<u8>>(chan_size);
+                    m2p_senders.push(m2p_sender);
+                    let recorded = (idx, transcript.clone(), clocks.clone());
+                    tasks.push(
+                        echo_channel_to_stdin(
+                            player_stdin,
+                            m2p_receiver,
+                            idx.to_string(),
+                            Some(recorded),
+                        )
+                        .boxed(),
+                    );
+                    tasks.push(
+                        tag_and_echo_stdout_to_channel(
+                            player_stdout,
+                            p2m_sender.clone(),
+                            // TODO(mbwang): see above: 0 or 1-index?
+                            idx, // 0 indexed
+                            delim,
+                            transcript.clone(),
+                            clocks.clone(),
+                            report_times,
+                            check.clone(),
+                        )
+                        .boxed(),
+                    );
+                }
+                tasks.push(clock_watchdog(clocks.clone(), p2m_sender.clone(), delim).boxed());
+                for (player, child, stats) in sandboxed {
+                    tasks.push(
+                        watch_sandboxed(
+                            child,
+                            stats,
+                            player,
+                            clocks.clone(),
+                            p2m_sender.clone(),
+                            delim,
+                        )
+                        .boxed(),
+                    );
+                }
+                tasks.push(
+                    echo_channel_to_stdin(manager_stdin, p2m_receiver, "Manager".to_string(), None)
+                        .boxed(),
+                );
+                // The tasks have their own, and spectators are only done once every copy is gone
+                drop(transcript);
+                tasks.push(
+                    echo_tagged_stdout_to_channel(
+                        manager_stdout,
+                        m2p_senders,
+                        delim,
+                        spectators,
+                        reports.clone(),
+                    )
+                    .boxed(),
+                );
+            } else {
+                let mut child_stdins: Vec<MessageWriter> = Vec::new();
+                let mut child_stdouts: Vec<MessageReader> = Vec::new();
+                for (player_stdout, player_stdin) in endpoints {
+                    child_stdins.push(player_stdin);
+                    child_stdouts.push(player_stdout);
+                }
+                tasks.push(
+                    route_and_echo_tagged_messages(
+                        manager_stdout,
+                        child_stdins,
+                        delim,
+                        transcript.clone(),
+                        clocks.clone(),
+                        spectators,
+                        reports.clone(),
+                    )
+                    .boxed(),
+                );
+                let (control_sender, control_receiver) = (p2m_sender, p2m_receiver);
+                tasks.push(clock_watchdog(clocks.clone(), control_sender.clone(), delim).boxed());
+                for (player, child, stats) in sandboxed {
+                    tasks.push(
+                        watch_sandboxed(
+                            child,
+                            stats,
+                            player,
+                            clocks.clone(),
+                            control_sender.clone(),
+                            delim,
+                        )
+                        .boxed(),
+                    );
+                }
+                tasks.push(
+                    tag_and_echo_messages(
+                        child_stdouts,
+                        manager_stdin,
+                        delim,
+                        transcript,
+                        clocks,
+                        report_times,
+                        control_receiver,
+                        check,
+                    )
+                    .boxed(),
+                );
+            }
+        }
+        // Note: if the block above is in the same scope as this join_all call,
+        // the original p2m_sender is still alive here and join_all will never finish
+        // since p2m_receiver waits for the jango fett sender to be dropped before closing
+        // https://en.wikipedia.org/wiki/Jango_Fett#Attack_of_the_Clones
+        let results = join_all(tasks).await;
+        // Every Spectators handle went away with the tasks, so watchers see the end now
+        if let Some(socket) = spectator_socket {
+            socket.close().await;
+        }
+        join_all(watchers).await;
+        for result in results {
+            result?
+        }
+        info!("All tasks resolved");
+        let mut usage = collect_usage(&participants, EXIT_GRACE).await.into_iter();
+        let mut report = reports.report();
+        report.manager = usage.next().unwrap_or_default();
+        report.players = usage.collect();
+        report.hellos = hellos;
+        Ok(report)
+    }
+}
@@ -0,0 +1,257 @@
+use crate::config::ProcessConfig;
+use crate::logging::spawn_in_match;
+use crate::manifest::FRAMING_VAR;
+use crate::process::{
+    child_transport, make_child_stderr_reader, process_name, spawn_process, tag_and_echo_stderr,
+};
+use crate::transport::{split_transport, BoxedTransport, Framing};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+// How many bytes can sit between a websocket and the router before backpressure kicks in
+const WS_BUFFER_SIZE: usize = 64 * 1024;
+// How long a client gets to finish the websocket upgrade once its TCP connection is accepted
+const WS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How a remote player reaches the metamanager
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Remote {
+    // Plain TCP, framed exactly like a local process' stdio
+    Tcp,
+    // WebSocket, one text frame per message, or binary frames with length framing
+    Ws,
+}
+
+async fn bind(addr: Option<&str>, remote: Remote) -> Result<Option<TcpListener>> {
+    let addr = match addr {
+        Some(addr) => addr,
+        None => return Ok(None),
+    };
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Could not listen for {remote:?} players on {addr}"))?;
+    info!(
+        "Listening for {remote:?} players on {}",
+        listener.local_addr()?
+    );
+    Ok(Some(listener))
+}
+
+async fn accept_from(listener: &Option<TcpListener>) -> std::io::Result<TcpStream> {
+    match listener {
+        Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+        None => futures::future::pending().await,
+    }
+}
+
+// Shuttle websocket frames between a websocket and one end of an in-memory pipe, so the
+// other end can be routed like any process' stdio. Either side closing closes the other
+fn websocket_transport<S>(
+    websocket: tokio_tungstenite::WebSocketStream<S>,
+    framing: Framing,
+) -> BoxedTransport
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (router_side, adapter_side) = tokio::io::duplex(WS_BUFFER_SIZE);
+    let (mut adapter_reader, mut adapter_writer) =
+        split_transport(Box::new(BufReader::new(adapter_side)), framing);
+    let (mut ws_sink, mut ws_stream) = websocket.split();
+    spawn_in_match(async move {
+        let incoming = async {
+            let forwarded = async {
+                while let Some(frame) = ws_stream.next().await {
+                    match frame? {
+                        Message::Text(text) => adapter_writer.send(text.as_bytes()).await?,
+                        Message::Binary(data) => match framing {
+                            Framing::Length => adapter_writer.send(&data).await?,
+                            // Some clients send text as binary frames, anything else can't
+                            // be a line
+                            Framing::Lines => match std::str::from_utf8(&data) {
+                                Ok(text) => adapter_writer.send(text.as_bytes()).await?,
+                                Err(_) => bail!("got a binary frame that isn't UTF-8 text"),
+                            },
+                        },
+                        Message::Close(_) => break,
+                        _ => {}
+                    }
+                }
+                anyhow::Ok(())
+            }
+            .await;
+            // Whatever happened, the router sees the player leave
+            adapter_writer.shutdown().await?;
+            forwarded
+        };
+        let outgoing = async {
+            while let Some(message) = adapter_reader.next_message().await? {
+                let frame = match framing {
+                    Framing::Lines => Message::Text(String::from_utf8(message)?),
+                    Framing::Length => Message::Binary(message),
+                };
+                ws_sink.send(frame).await?;
+            }
+            ws_sink.close().await?;
+            anyhow::Ok(())
+        };
+        let (incoming, outgoing) = tokio::join!(incoming, outgoing);
+        // Anything lost on the way in is a player message, so that's worth a warning
+        if let Err(err) = incoming {
+            warn!("Websocket adapter stopped reading: {err:#}");
+        }
+        if let Err(err) = outgoing {
+            debug!("Websocket adapter stopped writing: {err:#}");
+        }
+    });
+    Box::new(BufReader::new(router_side))
+}
+
+// Wait until every remote seat is taken. seats lists each remote player index and how it
+// connects; seats of the same kind are filled in the order players connect
+pub async fn accept_remote_players(
+    seats: &[(usize, Remote)],
+    tcp_addr: Option<&str>,
+    ws_addr: Option<&str>,
+    connect_timeout: Duration,
+    framing: Framing,
+) -> Result<Vec<(usize, BoxedTransport)>> {
+    let wanted = |remote: Remote| seats.iter().filter(move |(_, kind)| *kind == remote);
+    let tcp_listener = match wanted(Remote::Tcp).next() {
+        Some(_) => bind(tcp_addr, Remote::Tcp).await?,
+        None => None,
+    };
+    let ws_listener = match wanted(Remote::Ws).next() {
+        Some(_) => bind(ws_addr, Remote::Ws).await?,
+        None => None,
+    };
+    let mut tcp_seats = wanted(Remote::Tcp).map(|(player, _)| *player);
+    let mut ws_seats = wanted(Remote::Ws).map(|(player, _)| *player);
+    let mut connected = Vec::new();
+    let mut handshakes = FuturesUnordered::new();
+    let accept_all = async {
+        while connected.len() < seats.len() {
+            tokio::select! {
+                stream = accept_from(&tcp_listener) => {
+                    let stream = stream?;
+                    let peer = stream.peer_addr()?;
+                    match tcp_seats.next() {
+                        Some(player) => {
+                            info!("{player}: connected over TCP from {peer}");
+                            stream.set_nodelay(true)?;
+                            connected.push((player, Box::new(BufReader::new(stream)) as BoxedTransport));
+                        }
+                        None => warn!("Turning away TCP connection from {peer}, all seats taken"),
+                    }
+                }
+                stream = accept_from(&ws_listener) => {
+                    let stream = stream?;
+                    let peer = stream.peer_addr()?;
+                    // Upgraded on the side, so a client that never finishes the upgrade can't
+                    // keep anyone else from connecting
+                    let upgrade = tokio_tungstenite::accept_async(stream);
+                    handshakes.push(async move { (peer, timeout(WS_HANDSHAKE_TIMEOUT, upgrade).await) });
+                }
+                Some((peer, upgraded)) = handshakes.next(), if !handshakes.is_empty() => {
+                    let websocket = match upgraded {
+                        Ok(Ok(websocket)) => websocket,
+                        Ok(Err(err)) => {
+                            warn!("Websocket handshake with {peer} failed: {err}");
+                            continue;
+                        }
+                        Err(_) => {
+                            warn!("Websocket handshake with {peer} timed out");
+                            continue;
+                        }
+                    };
+                    match ws_seats.next() {
+                        Some(player) => {
+                            info!("{player}: connected over websocket from {peer}");
+                            connected.push((player, websocket_transport(websocket, framing)));
+                        }
+                        None => warn!("Turning away websocket connection from {peer}, all seats taken"),
+                    }
+                }
+            }
+        }
+        anyhow::Ok(())
+    };
+    match timeout(connect_timeout, accept_all).await {
+        Ok(result) => result?,
+        Err(_) => bail!(
+            "Only {} of {} remote players connected within {}s",
+            connected.len(),
+            seats.len(),
+            connect_timeout.as_secs_f64()
+        ),
+    }
+    Ok(connected)
+}
+
+// Run a local bot and plug its stdio into a metamanager listening at url, which is
+// either tcp://host:port or ws://host:port. To the manager it looks like a local process.
+// framing has to be the match's, the bot speaks it too
+pub async fn connect(url: &str, process_config: &ProcessConfig, framing: Framing) -> Result<()> {
+    let remote: BoxedTransport = if let Some(addr) = url.strip_prefix("tcp://") {
+        let stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("Could not connect to {url}"))?;
+        stream.set_nodelay(true)?;
+        Box::new(BufReader::new(stream))
+    } else if url.starts_with("ws://") {
+        let (websocket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .with_context(|| format!("Could not connect to {url}"))?;
+        websocket_transport(websocket, framing)
+    } else {
+        return Err(anyhow!("Expected a tcp:// or ws:// url, got '{url}'"));
+    };
+    let (remote_reader, mut remote_writer) = split_transport(remote, framing);
+    info!("Connected to {url}");
+    // It speaks the framing too, so it gets told which
+    let mut process_config = process_config.clone();
+    process_config
+        .env
+        .insert(FRAMING_VAR.to_string(), framing.as_str().to_string());
+    let mut process = spawn_process(&process_config, "player")?;
+    let stderr = tag_and_echo_stderr(
+        make_child_stderr_reader(&mut process),
+        format!("[{}]", process_name(&process_config.path)),
+        None,
+    );
+    let (mut stdout, mut stdin) = split_transport(Box::new(child_transport(&mut process)), framing);
+    let to_remote = async {
+        while let Some(message) = stdout.next_message().await? {
+            remote_writer.send(&message).await?;
+        }
+        remote_writer.shutdown().await?;
+        anyhow::Ok(())
+    };
+    let from_remote = async {
+        let mut remote_reader = remote_reader;
+        while let Some(message) = remote_reader.next_message().await? {
+            if let Err(err) = stdin.send(&message).await {
+                info!("Player stopped reading: {err}");
+                break;
+            }
+        }
+        // Closing stdin tells the bot the match is over
+        drop(stdin);
+        anyhow::Ok(())
+    };
+    let (to_remote, from_remote, stderr) = tokio::join!(to_remote, from_remote, stderr);
+    to_remote?;
+    from_remote?;
+    stderr?;
+    let status = process.wait().await?;
+    info!("Player exited with {status}");
+    Ok(())
+}
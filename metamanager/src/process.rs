@@ -0,0 +1,149 @@
+use crate::config::ProcessConfig;
+use crate::sandbox::{self, sandbox_command, SandboxConfig, SandboxedChild};
+use crate::transport::{BoxedReader, BoxedWriter, Transport, MAX_FRAME_LEN};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+
+// How long a process gets to exit once it's done, i.e. it closed its output or the match is
+// over, before it's killed (and for a player that's still playing, counted as crashed)
+pub(crate) const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+pub(crate) type ChildStderrReader = BufReader<ChildStderr>;
+
+// A child's stdout and stdin. Shutting down a ChildStdin does nothing, only dropping it
+// closes the pipe, so the halves must stay independent instead of going through io::split
+pub struct ChildTransport {
+    stdout: ChildStdout,
+    stdin: ChildStdin,
+}
+
+impl Transport for ChildTransport {
+    fn into_halves(self: Box<Self>) -> (BoxedReader, BoxedWriter) {
+        (Box::new(self.stdout), Box::new(self.stdin))
+    }
+}
+
+pub fn child_transport(child: &mut Child) -> ChildTransport {
+    ChildTransport {
+        stdout: child.stdout.take().unwrap(),
+        stdin: child.stdin.take().unwrap(),
+    }
+}
+pub(crate) fn make_child_stderr_reader(child: &mut Child) -> ChildStderrReader {
+    BufReader::new(child.stderr.take().unwrap())
+}
+
+// Label used to tell processes apart in stderr output and log file names
+// The manager is always the first process, players are 0 indexed after it like their tags
+pub fn process_label(idx: usize) -> String {
+    match idx {
+        0 => String::from("manager"),
+        _ => format!("player{}", idx - 1),
+    }
+}
+
+// Short name for a process, just the file name of its executable
+pub fn process_name(exe_path: &str) -> String {
+    Path::new(exe_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| exe_path.to_string())
+}
+
+fn command(config: &ProcessConfig) -> Command {
+    let mut command = Command::new(&config.path);
+    command.args(&config.args).envs(&config.env);
+    if let Some(cwd) = &config.cwd {
+        command.current_dir(cwd);
+    }
+    command
+}
+
+// Spawn a single process with piped stdio, label is only used to explain failures
+pub fn spawn_process(config: &ProcessConfig, label: &str) -> Result<Child> {
+    spawn(command(config), config, label)
+}
+
+// Spawn a player inside a sandbox, see SandboxConfig
+pub(crate) fn spawn_sandboxed(
+    config: &ProcessConfig,
+    label: &str,
+    sandbox: &SandboxConfig,
+) -> Result<SandboxedChild> {
+    let mut command = command(config);
+    let tmp = sandbox_command(&mut command, sandbox)?;
+    Ok(sandbox::spawned(
+        spawn(command, config, label)?,
+        sandbox,
+        tmp,
+    ))
+}
+
+fn spawn(mut command: Command, config: &ProcessConfig, label: &str) -> Result<Child> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| {
+            let cwd = match &config.cwd {
+                Some(cwd) => cwd.display().to_string(),
+                None => String::from("the current directory"),
+            };
+            format!(
+                "Failed to spawn {label} `{}` in {cwd}",
+                config.command_line()
+            )
+        })
+}
+
+// Echo every stderr line to our own stderr with a prefix saying who wrote it
+// If log_file is given, the untagged line is also appended there
+// Stderr is only diagnostics, so nothing a process writes there (or failing to read or
+// log it) ever fails the match
+pub(crate) async fn tag_and_echo_stderr(
+    mut reader: ChildStderrReader,
+    prefix: String,
+    mut log_file: Option<BufWriter<File>>,
+) -> Result<()> {
+    info!("{prefix}: start echoing stderr");
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        // Capped like messages, longer lines are split so a line never ending can't take
+        // all our memory
+        let mut capped = (&mut reader).take(MAX_FRAME_LEN as u64);
+        match capped.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                warn!("{prefix}: stopped reading stderr: {err}");
+                break;
+            }
+        }
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        let line = String::from_utf8_lossy(&buf);
+        eprintln!("{prefix} {line}");
+        if let Some(writer) = log_file.as_mut() {
+            let written = async {
+                writer.write_all(format!("{line}\n").as_bytes()).await?;
+                writer.flush().await
+            };
+            if let Err(err) = written.await {
+                warn!("{prefix}: stopped logging stderr: {err}");
+                log_file = None;
+            }
+        }
+    }
+    info!("{prefix}: done echoing stderr");
+    Ok(())
+}
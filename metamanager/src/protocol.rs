@@ -0,0 +1,278 @@
+use crate::control::{tagged_control, CONTROL_TAG};
+use crate::routing::close_writer;
+use crate::transport::{split_transport, Framing, MessageReader, MessageWriter};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use futures::future::{BoxFuture, FutureExt};
+use jsonschema::JSONSchema;
+use log::{trace, warn};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{duplex, BufReader};
+
+// How many bytes can sit in a JSON bridge before backpressure kicks in
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+// What the manager and players say to each other
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    // `<tag><delim><message>`, the message is whatever the game likes
+    #[default]
+    Tagged,
+    // JSON objects like {"type":"move","data":4}. The manager addresses them with "to",
+    // a player index, a list of them, "*", "mm" or "spec", and gets them with "from".
+    // Control messages look like {"from":"mm","type":"timeout","data":"1"}, and player
+    // messages that aren't valid are dropped and reported as `invalid <player> <error>`
+    Json,
+}
+
+// What players of a game may send, from a JSON file like
+// {"players": {"move": {"type": "integer", "minimum": 0, "maximum": 8}}}
+// mapping every message type to a JSON Schema its data has to match.
+// Only player messages are checked: the manager is trusted, as it is everywhere else
+#[derive(Debug)]
+pub struct GameSchema {
+    players: BTreeMap<String, JSONSchema>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchemaFile {
+    players: BTreeMap<String, Value>,
+}
+
+impl GameSchema {
+    pub fn from_file(path: &Path) -> Result<GameSchema> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read schema {}", path.display()))?;
+        let file: SchemaFile = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid schema file {}", path.display()))?;
+        GameSchema::new(file.players)
+            .with_context(|| format!("Invalid schema file {}", path.display()))
+    }
+
+    // Compile a JSON Schema for the data of each player message type
+    pub fn new(players: BTreeMap<String, Value>) -> Result<GameSchema> {
+        let players = players
+            .into_iter()
+            .map(|(kind, schema)| {
+                let compiled = JSONSchema::compile(&schema)
+                    .map_err(|err| anyhow!("Bad schema for '{kind}' messages: {err}"))?;
+                Ok((kind, compiled))
+            })
+            .collect::<Result<_>>()?;
+        Ok(GameSchema { players })
+    }
+
+    fn validate(&self, kind: &str, data: &Value) -> Result<(), String> {
+        let schema = match self.players.get(kind) {
+            Some(schema) => schema,
+            None => return Err(format!("unknown message type '{kind}'")),
+        };
+        if let Err(mut errors) = schema.validate(data) {
+            if let Some(err) = errors.next() {
+                return Err(format!("'{kind}' data{}: {err}", err.instance_path));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A player's message as the object forwarded to the manager, or why it's rejected
+pub fn check_player_message(
+    message: &[u8],
+    schema: Option<&GameSchema>,
+) -> Result<Map<String, Value>, String> {
+    let object = match serde_json::from_slice(message) {
+        Ok(Value::Object(object)) => object,
+        Ok(_) => return Err("not a JSON object".to_string()),
+        Err(err) => return Err(format!("not JSON: {err}")),
+    };
+    if object.contains_key("from") || object.contains_key("to") {
+        return Err("players can't set 'from' or 'to'".to_string());
+    }
+    let kind = match object.get("type") {
+        Some(Value::String(kind)) => kind.clone(),
+        _ => return Err("no string 'type'".to_string()),
+    };
+    if let Some(schema) = schema {
+        schema.validate(&kind, object.get("data").unwrap_or(&Value::Null))?;
+    }
+    Ok(object)
+}
+
+// How the router vets player messages before they reach the manager. With the JSON
+// protocol they have to pass check_player_message, with the tagged one anything goes
+#[derive(Clone, Default)]
+pub(crate) struct PlayerCheck {
+    json: bool,
+    schema: Option<Arc<GameSchema>>,
+}
+
+impl PlayerCheck {
+    pub(crate) fn new(protocol: Protocol, schema: Option<Arc<GameSchema>>) -> PlayerCheck {
+        PlayerCheck {
+            json: protocol == Protocol::Json,
+            schema,
+        }
+    }
+
+    // The message to route, as the player sent it so transcripts replay, or why it isn't
+    pub(crate) fn check(&self, message: Vec<u8>) -> Result<Vec<u8>, String> {
+        if self.json {
+            check_player_message(&message, self.schema.as_deref())?;
+        }
+        Ok(message)
+    }
+}
+
+// A tagged message for the manager as the JSON object the manager gets
+pub fn to_manager(tagged: &[u8], delim: char) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(tagged).map_err(|err| err.to_string())?;
+    let (tag, message) = text
+        .split_once(delim)
+        .ok_or_else(|| format!("untagged message '{text}'"))?;
+    let object = if tag == CONTROL_TAG {
+        let (kind, args) = message.split_once(' ').unwrap_or((message, ""));
+        let mut object = Map::new();
+        object.insert("from".to_string(), Value::from(CONTROL_TAG));
+        object.insert("type".to_string(), Value::from(kind));
+        if !args.is_empty() {
+            object.insert("data".to_string(), Value::from(args));
+        }
+        object
+    } else {
+        let player = tag
+            .parse::<usize>()
+            .map_err(|_| format!("bad tag '{tag}'"))?;
+        let mut object = match serde_json::from_str(message) {
+            Ok(Value::Object(object)) => object,
+            _ => {
+                return Err(format!(
+                    "player {player} sent something that isn't an object"
+                ))
+            }
+        };
+        object.insert("from".to_string(), Value::from(player));
+        object
+    };
+    Ok(Value::Object(object).to_string().into_bytes())
+}
+
+// A JSON object from the manager as the tagged message the router expects
+pub fn from_manager(message: &[u8], delim: char) -> Result<Vec<u8>, String> {
+    let mut object = match serde_json::from_slice(message) {
+        Ok(Value::Object(object)) => object,
+        Ok(_) => return Err("not a JSON object".to_string()),
+        Err(err) => return Err(format!("not JSON: {err}")),
+    };
+    let tag = match object.remove("to") {
+        Some(Value::Number(player)) if player.is_u64() => player.to_string(),
+        Some(Value::String(tag)) => tag,
+        Some(Value::Array(players)) if !players.is_empty() && players.iter().all(Value::is_u64) => {
+            let players = players.iter().map(Value::to_string).collect::<Vec<_>>();
+            players.join(",")
+        }
+        Some(to) => return Err(format!("can't send to {to}")),
+        None => return Err("no 'to'".to_string()),
+    };
+    if tag == CONTROL_TAG {
+        let kind = match object.get("type") {
+            Some(Value::String(kind)) => kind,
+            _ => return Err("control message without a string 'type'".to_string()),
+        };
+        // Strings as they are, anything else as JSON
+        let message = match object.get("data") {
+            None | Some(Value::Null) => kind.clone(),
+            Some(Value::String(args)) => format!("{kind} {args}"),
+            Some(args) => format!("{kind} {args}"),
+        };
+        return Ok(tagged_control(delim, &message));
+    }
+    Ok(format!("{tag}{delim}{}", Value::Object(object)).into_bytes())
+}
+
+// A pipe for the router to use in place of a participant's, with the match's framing
+fn bridge(
+    framing: Framing,
+) -> (
+    (MessageReader, MessageWriter),
+    (MessageReader, MessageWriter),
+) {
+    let (router_side, bridge_side) = duplex(BRIDGE_BUFFER_SIZE);
+    (
+        split_transport(Box::new(BufReader::new(router_side)), framing),
+        split_transport(Box::new(BufReader::new(bridge_side)), framing),
+    )
+}
+
+// Copy messages from reader to writer, converted by convert. Whatever can't be converted
+// is dropped. Keeps reading after writer is gone, so nobody ever blocks on the bridge.
+// Failing to read fails the match, like it does without a bridge
+async fn pump<F>(
+    mut reader: MessageReader,
+    writer: MessageWriter,
+    who: &str,
+    convert: F,
+) -> Result<()>
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, String>,
+{
+    let mut writer = Some(writer);
+    let read = loop {
+        let message = match reader.next_message().await {
+            Ok(Some(message)) => message,
+            Ok(None) => break Ok(()),
+            Err(err) => break Err(err).with_context(|| format!("Could not read from {who}")),
+        };
+        let converted = match convert(&message) {
+            Ok(converted) => converted,
+            Err(err) => {
+                warn!(
+                    "Dropping message from {who} ({err}): '{}'",
+                    String::from_utf8_lossy(&message)
+                );
+                continue;
+            }
+        };
+        if let Some(open) = writer.as_mut() {
+            if let Err(err) = open.send(&converted).await {
+                trace!("Bridge from {who} closed: {err}");
+                writer = None;
+            }
+        }
+    };
+    if let Some(writer) = writer {
+        close_writer(writer).await;
+    }
+    read
+}
+
+// Put the manager behind a bridge translating its JSON to tagged messages and back.
+// The router uses the returned endpoint, the returned task has to run for messages to flow
+pub(crate) fn manager_bridge(
+    (manager_reader, manager_writer): (MessageReader, MessageWriter),
+    delim: char,
+    framing: Framing,
+) -> (
+    (MessageReader, MessageWriter),
+    BoxFuture<'static, Result<()>>,
+) {
+    let (routed, (bridge_reader, bridge_writer)) = bridge(framing);
+    let task = async move {
+        let (from, to) = tokio::join!(
+            pump(manager_reader, bridge_writer, "the manager", |message| {
+                from_manager(message, delim)
+            }),
+            pump(bridge_reader, manager_writer, "the router", |tagged| {
+                to_manager(tagged, delim)
+            }),
+        );
+        from.and(to)
+    };
+    (routed, task.boxed())
+}
@@ -0,0 +1,411 @@
+use crate::report::MatchResult;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A finished match as kept in the rating store, one JSON line per game
+// players[i] got scores[i], names are what bots are rated under so include a version
+// in them (e.g. `mybot-v2`) to rate versions separately
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RatedGame {
+    // Seconds since the epoch
+    pub time: u64,
+    pub players: Vec<String>,
+    pub scores: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl RatedGame {
+    // A game that just finished
+    pub fn new(players: Vec<String>, result: &MatchResult) -> RatedGame {
+        RatedGame {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            players,
+            scores: result.scores.clone(),
+            reason: result.reason.clone(),
+        }
+    }
+
+    // A name that's in the game more than once, a bot can't be rated against itself
+    pub fn duplicate_player(&self) -> Option<&str> {
+        self.players
+            .iter()
+            .enumerate()
+            .find(|(seat, name)| self.players[..*seat].contains(name))
+            .map(|(_, name)| name.as_str())
+    }
+
+    // How player a did against player b: 1 if it scored more, 0.5 if the same and 0 if less
+    fn outcome(&self, a: usize, b: usize) -> f64 {
+        match self.scores[a].partial_cmp(&self.scores[b]) {
+            Some(std::cmp::Ordering::Greater) => 1.0,
+            Some(std::cmp::Ordering::Less) => 0.0,
+            _ => 0.5,
+        }
+    }
+}
+
+// Game results kept in a JSON Lines file. Ratings are recomputed from every game each time
+// they're shown, so the file is the only state and can be edited or merged by hand
+pub struct RatingStore {
+    path: PathBuf,
+}
+
+impl RatingStore {
+    pub fn open(path: &Path) -> RatingStore {
+        RatingStore {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn record(&self, game: &RatedGame) -> Result<()> {
+        if game.players.len() < 2 || game.players.len() != game.scores.len() {
+            bail!("A rated game needs a score for each of at least two players");
+        }
+        if let Some(name) = game.duplicate_player() {
+            bail!(
+                "'{name}' plays more than one seat, give the players different names to rate them"
+            );
+        }
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Could not open rating store {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(game)?)?;
+        Ok(())
+    }
+
+    // Every game recorded so far, oldest first. A store that doesn't exist yet is empty
+    pub fn games(&self) -> Result<Vec<RatedGame>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("Could not open rating store {}", self.path.display())
+                })
+            }
+        };
+        let mut games = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let game: RatedGame = serde_json::from_str(&line).with_context(|| {
+                format!("Bad game on line {} of {}", number + 1, self.path.display())
+            })?;
+            if game.players.len() != game.scores.len() {
+                bail!(
+                    "Line {} of {} doesn't have a score for every player",
+                    number + 1,
+                    self.path.display()
+                );
+            }
+            games.push(game);
+        }
+        Ok(games)
+    }
+}
+
+const ELO_K: f64 = 32.0;
+const INITIAL_RATING: f64 = 1500.0;
+
+// Glicko-2 constants, see http://www.glicko.net/glicko/glicko2.pdf
+const GLICKO_SCALE: f64 = 173.7178;
+const INITIAL_DEVIATION: f64 = 350.0;
+const INITIAL_VOLATILITY: f64 = 0.06;
+const TAU: f64 = 0.5;
+const CONVERGENCE: f64 = 0.000001;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glicko {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko {
+    fn default() -> Glicko {
+        Glicko {
+            rating: INITIAL_RATING,
+            deviation: INITIAL_DEVIATION,
+            volatility: INITIAL_VOLATILITY,
+        }
+    }
+}
+
+impl Glicko {
+    // 95% confidence interval for the true rating
+    pub fn interval(&self) -> (f64, f64) {
+        (
+            self.rating - 1.96 * self.deviation,
+            self.rating + 1.96 * self.deviation,
+        )
+    }
+
+    fn mu(&self) -> f64 {
+        (self.rating - INITIAL_RATING) / GLICKO_SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / GLICKO_SCALE
+    }
+
+    // One rating period made of a single game against each of opponents, with outcome 1, 0.5 or 0
+    // Every game is its own period and only players in it are updated, so a bot's deviation
+    // doesn't grow while it sits out other bots' games
+    pub fn update(&self, opponents: &[(Glicko, f64)]) -> Glicko {
+        let (mu, phi, sigma) = (self.mu(), self.phi(), self.volatility);
+        let g = |phi: f64| 1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt();
+        let mut inverse_v = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, outcome) in opponents {
+            let g = g(opponent.phi());
+            let expected = 1.0 / (1.0 + (-g * (mu - opponent.mu())).exp());
+            inverse_v += g * g * expected * (1.0 - expected);
+            improvement += g * (outcome - expected);
+        }
+        let v = 1.0 / inverse_v;
+        let delta = v * improvement;
+
+        // New volatility, by the Illinois algorithm
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2))
+                - (x - a) / (TAU * TAU)
+        };
+        let mut low = a;
+        let mut high = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let (mut f_low, mut f_high) = (f(low), f(high));
+        while (high - low).abs() > CONVERGENCE {
+            let next = low + (low - high) * f_low / (f_high - f_low);
+            let f_next = f(next);
+            if f_next * f_high <= 0.0 {
+                low = high;
+                f_low = f_high;
+            } else {
+                f_low /= 2.0;
+            }
+            high = next;
+            f_high = f_next;
+        }
+        let volatility = (low / 2.0).exp();
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+        Glicko {
+            rating: INITIAL_RATING + GLICKO_SCALE * new_mu,
+            deviation: GLICKO_SCALE * new_phi,
+            volatility,
+        }
+    }
+}
+
+// Where a bot stood after one of its games
+#[derive(Clone, Debug)]
+pub struct RatingChange {
+    pub time: u64,
+    pub opponents: Vec<String>,
+    pub score: f64,
+    pub elo: f64,
+    pub glicko: Glicko,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rating {
+    pub name: String,
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    pub elo: f64,
+    pub glicko: Glicko,
+    pub history: Vec<RatingChange>,
+}
+
+impl Rating {
+    fn new(name: &str) -> Rating {
+        Rating {
+            name: name.to_string(),
+            games: 0,
+            wins: 0,
+            draws: 0,
+            losses: 0,
+            elo: INITIAL_RATING,
+            glicko: Glicko::default(),
+            history: Vec::new(),
+        }
+    }
+}
+
+// Everyone's ratings after replaying games in order
+// Games with more than two players count as a game between every pair of them
+pub struct Ratings {
+    ratings: Vec<Rating>,
+}
+
+impl Ratings {
+    pub fn from_games(games: &[RatedGame]) -> Ratings {
+        let mut ratings: Vec<Rating> = Vec::new();
+        let mut index = HashMap::new();
+        for game in games {
+            // Only a hand edited store can have one, record refuses them
+            if let Some(name) = game.duplicate_player() {
+                warn!("Skipping a game of '{name}' against itself");
+                continue;
+            }
+            let seats = game
+                .players
+                .iter()
+                .map(|name| {
+                    *index.entry(name.clone()).or_insert_with(|| {
+                        ratings.push(Rating::new(name));
+                        ratings.len() - 1
+                    })
+                })
+                .collect::<Vec<_>>();
+            // Everyone is rated against what their opponents were rated before the game
+            let before = seats
+                .iter()
+                .map(|&bot| (ratings[bot].elo, ratings[bot].glicko))
+                .collect::<Vec<_>>();
+            let others = seats.len() - 1;
+            for (seat, &bot) in seats.iter().enumerate() {
+                let opponents = (0..seats.len()).filter(|&other| other != seat);
+                let mut elo_change = 0.0;
+                let mut glicko_opponents = Vec::new();
+                let mut points = 0.0;
+                for other in opponents.clone() {
+                    let outcome = game.outcome(seat, other);
+                    let expected =
+                        1.0 / (1.0 + 10f64.powf((before[other].0 - before[seat].0) / 400.0));
+                    elo_change += ELO_K / others as f64 * (outcome - expected);
+                    glicko_opponents.push((before[other].1, outcome));
+                    points += outcome;
+                }
+                let rating = &mut ratings[bot];
+                rating.games += 1;
+                let average = points / others as f64;
+                if average > 0.5 {
+                    rating.wins += 1;
+                } else if average < 0.5 {
+                    rating.losses += 1;
+                } else {
+                    rating.draws += 1;
+                }
+                rating.elo += elo_change;
+                rating.glicko = before[seat].1.update(&glicko_opponents);
+                rating.history.push(RatingChange {
+                    time: game.time,
+                    opponents: opponents.map(|other| game.players[other].clone()).collect(),
+                    score: game.scores[seat],
+                    elo: rating.elo,
+                    glicko: rating.glicko,
+                });
+            }
+        }
+        Ratings { ratings }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rating> {
+        self.ratings.iter().find(|rating| rating.name == name)
+    }
+
+    // Best Glicko-2 rating first
+    pub fn leaderboard(&self) -> Leaderboard {
+        let mut ratings = self.ratings.clone();
+        ratings.sort_by(|a, b| b.glicko.rating.total_cmp(&a.glicko.rating));
+        Leaderboard(ratings)
+    }
+}
+
+fn format_time(time: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(time)).to_string()
+}
+
+pub struct Leaderboard(pub Vec<Rating>);
+
+impl fmt::Display for Leaderboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|rating| rating.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        writeln!(
+            f,
+            "{:>4}  {:<width$}  {:>5}  {:>4}  {:>5}  {:>6}  {:>5}  {:>7}  {:>13}",
+            "Rank", "Bot", "Games", "Wins", "Draws", "Losses", "Elo", "Glicko", "95% interval"
+        )?;
+        for (rank, rating) in self.0.iter().enumerate() {
+            let (low, high) = rating.glicko.interval();
+            writeln!(
+                f,
+                "{:>4}  {:<width$}  {:>5}  {:>4}  {:>5}  {:>6}  {:>5.0}  {:>7.0}  {:>13}",
+                rank + 1,
+                rating.name,
+                rating.games,
+                rating.wins,
+                rating.draws,
+                rating.losses,
+                rating.elo,
+                rating.glicko.rating,
+                format!("{low:.0}..{high:.0}")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rating {
+    // The bot's rating history, one line per game
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} after {} games", self.name, self.games)?;
+        let mut elo = INITIAL_RATING;
+        for change in &self.history {
+            let (low, high) = change.glicko.interval();
+            writeln!(
+                f,
+                "{}  vs {:<20}  scored {:<4}  Elo {:>5.0} ({:+4.0})  Glicko {:>5.0} ({:.0}..{:.0})",
+                format_time(change.time),
+                change.opponents.join(", "),
+                change.score,
+                change.elo,
+                change.elo - elo,
+                change.glicko.rating,
+                low,
+                high
+            )?;
+            elo = change.elo;
+        }
+        Ok(())
+    }
+}
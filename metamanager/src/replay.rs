@@ -0,0 +1,175 @@
+use crate::config::{default_delim, ProcessConfig};
+use crate::manifest::{match_env, Manifest};
+use crate::process::{
+    child_transport, make_child_stderr_reader, process_name, spawn_process, tag_and_echo_stderr,
+};
+use crate::transcript::{read_transcript, Direction};
+use crate::transport::{split_transport, Framing};
+use anyhow::{bail, Result};
+use log::{debug, info};
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::timeout;
+
+// Where the process under test stopped behaving like the recording
+#[derive(Debug)]
+enum Divergence {
+    // Recording says the player answered, the live process said something else
+    Mismatch {
+        seq: u64,
+        expected: String,
+        actual: String,
+    },
+    // Recording says the player answered, the live process exited or went quiet
+    Missing {
+        seq: u64,
+        expected: String,
+        reason: String,
+    },
+    // The live process kept talking after the recording ended
+    Extra {
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Divergence::Mismatch {
+                seq,
+                expected,
+                actual,
+            } => write!(f, "message {seq}: expected '{expected}', got '{actual}'"),
+            Divergence::Missing {
+                seq,
+                expected,
+                reason,
+            } => write!(f, "message {seq}: expected '{expected}', but {reason}"),
+            Divergence::Extra { actual } => {
+                write!(f, "after the recording ended: unexpected '{actual}'")
+            }
+        }
+    }
+}
+
+// How the recorded match was set up, as far as a player can tell. The process under test
+// is told the same, so a bot drawing its randomness from the seed plays the same way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordedMatch {
+    pub seed: Option<u64>,
+    pub delim: char,
+    pub framing: Framing,
+}
+
+impl Default for RecordedMatch {
+    fn default() -> RecordedMatch {
+        RecordedMatch {
+            seed: None,
+            delim: default_delim(),
+            framing: Framing::default(),
+        }
+    }
+}
+
+impl RecordedMatch {
+    pub fn from_manifest(manifest: &Manifest) -> RecordedMatch {
+        RecordedMatch {
+            seed: Some(manifest.seed),
+            delim: manifest.delim,
+            framing: manifest.framing,
+        }
+    }
+}
+
+// Feed a live process everything the manager sent `player` in a recorded match, and
+// check it answers exactly like the recording did. Only messages to and from `player`
+// matter, opponents' moves reach it through the manager so they're replayed as well
+// Returns how many recorded responses matched, or an error at the first divergence
+pub async fn replay(
+    transcript: &Path,
+    player: usize,
+    process_config: &ProcessConfig,
+    response_timeout: Duration,
+    recorded: RecordedMatch,
+) -> Result<usize> {
+    let framing = recorded.framing;
+    let entries = read_transcript(transcript)?
+        .into_iter()
+        .filter(|entry| entry.player == player)
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        bail!(
+            "{} has no messages to or from player {player}",
+            transcript.display(),
+        );
+    }
+    let label = format!("player{player}");
+    let process_config = match_env(process_config, recorded.seed, recorded.delim, framing);
+    let mut process = spawn_process(&process_config, &label)?;
+    let stderr_task = tokio::spawn(tag_and_echo_stderr(
+        make_child_stderr_reader(&mut process),
+        format!("[{label} {}]", process_name(&process_config.path)),
+        None,
+    ));
+    let (mut stdout, mut stdin) = split_transport(Box::new(child_transport(&mut process)), framing);
+
+    let mut divergence = None;
+    let mut responses = 0;
+    for entry in entries {
+        let seq = entry.seq;
+        let message = entry.payload_bytes()?;
+        let payload = entry.payload;
+        match entry.direction {
+            Direction::ManagerToPlayer => {
+                debug!("{seq}: replaying '{payload}'");
+                if let Err(err) = stdin.send(&message).await {
+                    // The process may have exited on purpose, if it still owed us a
+                    // response that shows up as a missing message below
+                    info!("{seq}: could not replay '{payload}': {err}");
+                }
+            }
+            Direction::PlayerToManager => {
+                let reason = match timeout(response_timeout, stdout.next_message()).await {
+                    Ok(Ok(Some(actual))) if actual == message => {
+                        debug!("{seq}: matched '{payload}'");
+                        responses += 1;
+                        continue;
+                    }
+                    Ok(Ok(Some(actual))) => {
+                        divergence = Some(Divergence::Mismatch {
+                            seq,
+                            expected: payload,
+                            actual: String::from_utf8_lossy(&actual).into_owned(),
+                        });
+                        break;
+                    }
+                    Ok(Ok(None)) => String::from("the process closed its stdout"),
+                    Ok(Err(err)) => format!("reading its stdout failed: {err}"),
+                    Err(_) => format!("nothing arrived within {}ms", response_timeout.as_millis()),
+                };
+                divergence = Some(Divergence::Missing {
+                    seq,
+                    expected: payload,
+                    reason,
+                });
+                break;
+            }
+        }
+    }
+    if divergence.is_none() {
+        // Closing stdin lets well behaved players exit, anything they still print is extra
+        drop(stdin);
+        if let Ok(Ok(Some(actual))) = timeout(response_timeout, stdout.next_message()).await {
+            divergence = Some(Divergence::Extra {
+                actual: String::from_utf8_lossy(&actual).into_owned(),
+            });
+        }
+    }
+    process.start_kill().ok();
+    process.wait().await?;
+    stderr_task.await??;
+    match divergence {
+        Some(divergence) => bail!("Player {player} diverged at {divergence}"),
+        None => Ok(responses),
+    }
+}
@@ -0,0 +1,95 @@
+use crate::handshake::PlayerHello;
+use crate::restart::RestartPermits;
+use crate::usage::ProcessUsage;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+// How a match ended, as reported by the manager with
+// `mm<delim>result <score of player 0> <score of player 1> ... [reason]`
+// Scores are usually 1 for a win, 0.5 for a draw and 0 for a loss, e.g. `mm:result 1 0 checkmate`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MatchResult {
+    pub scores: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl MatchResult {
+    // Parse the arguments of a result report, one score per player then an optional reason
+    pub fn parse(args: &str, num_players: usize) -> Result<MatchResult> {
+        let mut words = args.split_whitespace();
+        let scores = words
+            .by_ref()
+            .take(num_players)
+            .map(|score| {
+                score
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|score| score.is_finite())
+                    .with_context(|| format!("'{score}' is not a score"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if scores.len() != num_players {
+            bail!(
+                "Expected a score for each of {num_players} players, got {}",
+                scores.len()
+            );
+        }
+        let reason = words.collect::<Vec<_>>().join(" ");
+        Ok(MatchResult {
+            scores,
+            reason: (!reason.is_empty()).then_some(reason),
+        })
+    }
+}
+
+// What the metamanager knows about a finished match
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MatchReport {
+    // None if the manager never reported a result
+    pub result: Option<MatchResult>,
+    pub manager: ProcessUsage,
+    pub players: Vec<ProcessUsage>,
+    // Who each player said it was, if there was a handshake
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hellos: Vec<PlayerHello>,
+}
+
+// Filled in by the routing tasks as the manager reports things, read once the match is over
+#[derive(Clone, Default)]
+pub(crate) struct Reports {
+    result: Arc<Mutex<Option<MatchResult>>>,
+    pub(crate) restarts: RestartPermits,
+}
+
+impl Reports {
+    // Handle a `mm<delim><message>` line from the manager
+    pub(crate) fn manager_control(&self, message: &str, num_players: usize) {
+        let (command, args) = message.split_once(' ').unwrap_or((message, ""));
+        match command {
+            "result" => match MatchResult::parse(args, num_players) {
+                Ok(result) => {
+                    info!("Manager reported result {result:?}");
+                    if let Some(earlier) = self.result.lock().unwrap().replace(result) {
+                        warn!("Manager reported a result twice, dropping {earlier:?}");
+                    }
+                }
+                Err(err) => warn!("Ignoring bad result report '{message}': {err}"),
+            },
+            "restart" => match args.trim().parse::<usize>() {
+                Ok(player) if player < num_players => self.restarts.permit(player),
+                _ => warn!("Ignoring bad restart request '{message}'"),
+            },
+            _ => warn!("Ignoring unknown control message from the manager: '{message}'"),
+        }
+    }
+
+    pub(crate) fn report(&self) -> MatchReport {
+        MatchReport {
+            result: self.result.lock().unwrap().clone(),
+            ..Default::default()
+        }
+    }
+}
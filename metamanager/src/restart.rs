@@ -0,0 +1,397 @@
+use crate::config::ProcessConfig;
+use crate::control::tagged_control;
+use crate::logging::spawn_in_match;
+use crate::process::{
+    child_transport, make_child_stderr_reader, spawn_process, spawn_sandboxed, tag_and_echo_stderr,
+    ChildTransport, EXIT_GRACE,
+};
+use crate::routing::close_writer;
+use crate::sandbox::{SandboxConfig, SandboxedChild};
+use crate::transport::{split_transport, BoxedTransport, Framing, MessageReader, MessageWriter};
+use crate::usage::{wait_for_exit, ProcessStats};
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::OpenOptions;
+use tokio::io::{duplex, BufReader, BufWriter, DuplexStream};
+use tokio::process::Child;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+// How long the manager has to allow a restart, and a restarted player has to catch up
+const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+// How many bytes of messages are kept to replay to a restarted player. Replays need all of
+// them, so a player that got further than this can't be restarted any more
+const MAX_HISTORY_BYTES: usize = 64 << 20;
+
+// Players the manager allowed to be restarted with `mm<delim>restart <player>`
+#[derive(Clone, Default)]
+pub(crate) struct RestartPermits {
+    permitted: Arc<Mutex<HashSet<usize>>>,
+    notify: Arc<Notify>,
+}
+
+impl RestartPermits {
+    pub(crate) fn permit(&self, player: usize) {
+        self.permitted.lock().unwrap().insert(player);
+        self.notify.notify_waiters();
+    }
+
+    // Forget a permission given before the player was known to need it
+    fn revoke(&self, player: usize) {
+        self.permitted.lock().unwrap().remove(&player);
+    }
+
+    async fn wait(&self, player: usize) {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.permitted.lock().unwrap().remove(&player) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+// A restartable player's process, sandboxed if players are
+pub(crate) enum PlayerProcess {
+    Plain(Child),
+    Sandboxed(SandboxedChild),
+}
+
+impl PlayerProcess {
+    fn child(&mut self) -> &mut Child {
+        match self {
+            PlayerProcess::Plain(child) => child,
+            PlayerProcess::Sandboxed(sandboxed) => &mut sandboxed.child,
+        }
+    }
+
+    // The sandbox limit the process broke to end with status, if any
+    fn violation(&self, status: ExitStatus, stats: Option<&ProcessStats>) -> Option<&'static str> {
+        match self {
+            PlayerProcess::Plain(_) => None,
+            PlayerProcess::Sandboxed(sandboxed) => sandboxed.violation(status, stats),
+        }
+    }
+}
+
+// How to start a player again, in the same sandbox and with its stderr going where it
+// went before
+struct Respawn {
+    config: ProcessConfig,
+    label: String,
+    prefix: String,
+    stderr_log: Option<PathBuf>,
+    sandbox: Option<SandboxConfig>,
+}
+
+impl Respawn {
+    // The new process and the task echoing its stderr
+    async fn spawn(&self) -> Result<(PlayerProcess, JoinHandle<Result<()>>)> {
+        let mut process = match &self.sandbox {
+            Some(sandbox) => {
+                PlayerProcess::Sandboxed(spawn_sandboxed(&self.config, &self.label, sandbox)?)
+            }
+            None => PlayerProcess::Plain(spawn_process(&self.config, &self.label)?),
+        };
+        let log_file = match &self.stderr_log {
+            Some(path) => Some(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            )),
+            None => None,
+        };
+        let stderr = spawn_in_match(tag_and_echo_stderr(
+            make_child_stderr_reader(process.child()),
+            self.prefix.clone(),
+            log_file,
+        ));
+        Ok((process, stderr))
+    }
+}
+
+// A local player that can be respawned if it crashes, see keep_alive
+pub(crate) struct Restartable {
+    respawn: Respawn,
+    process: PlayerProcess,
+    transport: ChildTransport,
+    stats: Option<ProcessStats>,
+    // Our end of the transport the router uses for the player
+    routed: DuplexStream,
+    restarts: usize,
+}
+
+// Put a player's process behind a transport that survives it being restarted up to
+// restarts times. The router is given the returned transport, keep_alive has to run for
+// messages to flow. prefix and stderr_log are where the stderr of restarted processes goes,
+// sandbox what they run in
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn restartable(
+    config: &ProcessConfig,
+    label: String,
+    prefix: String,
+    stderr_log: Option<PathBuf>,
+    sandbox: Option<SandboxConfig>,
+    (process, transport): (PlayerProcess, ChildTransport),
+    stats: Option<ProcessStats>,
+    restarts: usize,
+) -> (Restartable, BoxedTransport) {
+    let (routed, routed_transport) = duplex(64 * 1024);
+    let restartable = Restartable {
+        respawn: Respawn {
+            config: config.clone(),
+            label,
+            prefix,
+            stderr_log,
+            sandbox,
+        },
+        process,
+        transport,
+        stats,
+        routed,
+        restarts,
+    };
+    (restartable, Box::new(BufReader::new(routed_transport)))
+}
+
+// None if the process closed its output but didn't exit, it's killed in that case
+async fn exit_status(child: &mut Child, stats: Option<&ProcessStats>) -> Option<ExitStatus> {
+    match tokio::time::timeout(EXIT_GRACE, wait_for_exit(child, stats)).await {
+        Ok(status) => status.ok(),
+        Err(_) => {
+            let _ = child.start_kill();
+            None
+        }
+    }
+}
+
+// Feed a restarted process everything its predecessor was sent. It has to answer the same
+// way, those answers already went to the manager and aren't sent again
+async fn replay(
+    reader: &mut MessageReader,
+    writer: &mut MessageWriter,
+    received: &[Vec<u8>],
+    sent: &[Vec<u8>],
+) -> Result<(), &'static str> {
+    let feed = async {
+        for message in received {
+            if writer.send(message).await.is_err() {
+                break;
+            }
+        }
+    };
+    let check = async {
+        for expected in sent {
+            match reader.next_message().await {
+                Ok(Some(message)) if message == *expected => {}
+                Ok(Some(_)) => return Err("diverged on replay"),
+                _ => return Err("crashed"),
+            }
+        }
+        Ok(())
+    };
+    let caught_up = async {
+        tokio::pin!(feed, check);
+        let mut fed = false;
+        loop {
+            tokio::select! {
+                result = &mut check => {
+                    // Lines sent after the crash still have to be fed
+                    if result.is_ok() && !fed {
+                        feed.await;
+                    }
+                    return result;
+                }
+                _ = &mut feed, if !fed => fed = true,
+            }
+        }
+    };
+    match tokio::time::timeout(RESTART_TIMEOUT, caught_up).await {
+        Ok(result) => result,
+        Err(_) => Err("too slow to replay"),
+    }
+}
+
+// Everything a player was sent and everything it sent, for replays. Forgotten once it's over
+// MAX_HISTORY_BYTES
+#[derive(Default)]
+struct History {
+    received: Vec<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+    bytes: usize,
+    forgotten: bool,
+}
+
+impl History {
+    fn received(&mut self, message: Vec<u8>) {
+        if self.keep(&message) {
+            self.received.push(message);
+        }
+    }
+
+    fn sent(&mut self, message: Vec<u8>) {
+        if self.keep(&message) {
+            self.sent.push(message);
+        }
+    }
+
+    fn keep(&mut self, message: &[u8]) -> bool {
+        if self.forgotten {
+            return false;
+        }
+        self.bytes += message.len();
+        if self.bytes > MAX_HISTORY_BYTES {
+            *self = History {
+                forgotten: true,
+                ..History::default()
+            };
+            return false;
+        }
+        true
+    }
+}
+
+async fn tell_manager(sender: &Sender<Vec<u8>>, delim: char, message: &str) {
+    if sender.send(tagged_control(delim, message)).await.is_err() {
+        info!("Manager stopped listening before hearing '{message}'");
+    }
+}
+
+// Pass messages between the router and a player's process. If the process crashes while
+// the match is still going, the manager is sent `disconnect <player>` and may answer
+// `restart <player>`. The player is then respawned, sent everything it was sent before and
+// has to answer the same way (so it has to be deterministic), after which the manager is
+// sent `restarted <player>`. A player that can't be brought back forfeits with
+// `forfeit <player> <reason>`, one the manager didn't allow back just stays disconnected
+pub(crate) async fn keep_alive(
+    player: Restartable,
+    idx: usize,
+    permits: RestartPermits,
+    sender: Sender<Vec<u8>>,
+    delim: char,
+    framing: Framing,
+) -> Result<()> {
+    let (mut routed_reader, mut routed_writer) =
+        split_transport(Box::new(BufReader::new(player.routed)), framing);
+    let mut process = player.process;
+    let stats = player.stats;
+    // Stderr of the processes started here, the first one's is echoed by the match
+    let mut stderr_echoes = Vec::new();
+    // Dropping the writer is what closes the process' stdin, so it's None once closed
+    let (mut reader, writer) = split_transport(Box::new(player.transport), framing);
+    let mut writer = Some(writer);
+    let mut restarts_left = player.restarts;
+    let mut history = History::default();
+    loop {
+        tokio::select! {
+            message = routed_reader.next_message(), if writer.is_some() => match message? {
+                Some(message) => {
+                    // A process that stopped reading shows up as its output closing
+                    if let Some(writer) = writer.as_mut() {
+                        let _ = writer.send(&message).await;
+                    }
+                    history.received(message);
+                }
+                None => writer = None,
+            },
+            message = reader.next_message() => {
+                if let Ok(Some(message)) = message {
+                    let _ = routed_writer.send(&message).await;
+                    history.sent(message);
+                    continue;
+                }
+                let status = exit_status(process.child(), stats.as_ref()).await;
+                // Exiting after the router is done with it, or cleanly, isn't crashing
+                if writer.is_none() || status.is_some_and(|status| status.success()) {
+                    break;
+                }
+                // It would only break the limit again
+                let violation =
+                    status.and_then(|status| process.violation(status, stats.as_ref()));
+                if let Some(reason) = violation {
+                    warn!("{idx}: forfeits for going over its {reason}");
+                    tell_manager(&sender, delim, &format!("forfeit {idx} {reason}")).await;
+                    break;
+                }
+                warn!("{idx}: crashed ({status:?})");
+                if restarts_left == 0 {
+                    tell_manager(&sender, delim, &format!("forfeit {idx} crashed")).await;
+                    break;
+                }
+                if history.forgotten {
+                    warn!("{idx}: too far into the match to be replayed");
+                    tell_manager(&sender, delim, &format!("forfeit {idx} crashed")).await;
+                    break;
+                }
+                permits.revoke(idx);
+                tell_manager(&sender, delim, &format!("disconnect {idx}")).await;
+                // Whatever the player is sent in the meantime is replayed too
+                let permitted = tokio::time::timeout(RESTART_TIMEOUT, async {
+                    loop {
+                        tokio::select! {
+                            _ = permits.wait(idx) => return true,
+                            message = routed_reader.next_message() => match message {
+                                Ok(Some(message)) => history.received(message),
+                                _ => return false,
+                            },
+                        }
+                    }
+                })
+                .await;
+                if permitted != Ok(true) {
+                    info!("{idx}: the manager didn't ask for a restart");
+                    break;
+                }
+                restarts_left -= 1;
+                let stderr;
+                (process, stderr) = match player.respawn.spawn().await {
+                    Ok(spawned) => spawned,
+                    Err(err) => {
+                        warn!("{idx}: couldn't be restarted: {err:#}");
+                        tell_manager(&sender, delim, &format!("forfeit {idx} crashed")).await;
+                        break;
+                    }
+                };
+                stderr_echoes.push(stderr);
+                if let (Some(stats), Some(pid)) = (&stats, process.child().id()) {
+                    stats.track_next(pid);
+                }
+                let (new_reader, mut new_writer) =
+                    split_transport(Box::new(child_transport(process.child())), framing);
+                reader = new_reader;
+                let replayed = replay(&mut reader, &mut new_writer, &history.received, &history.sent);
+                if let Err(reason) = replayed.await {
+                    warn!("{idx}: restarted, but {reason}");
+                    tell_manager(&sender, delim, &format!("forfeit {idx} {reason}")).await;
+                    break;
+                }
+                writer = Some(new_writer);
+                info!("{idx}: restarted and caught up");
+                tell_manager(&sender, delim, &format!("restarted {idx}")).await;
+            }
+        }
+    }
+    close_writer(routed_writer).await;
+    // Keep reading so the router never blocks on a player that's gone
+    if writer.is_some() {
+        drop(writer);
+        while let Ok(Some(_)) = routed_reader.next_message().await {}
+    }
+    // Its stderr only closes once the process (and in a sandbox, all it started) is gone
+    drop(process);
+    for stderr in stderr_echoes {
+        let _ = stderr.await;
+    }
+    Ok(())
+}
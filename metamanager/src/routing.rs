@@ -0,0 +1,408 @@
+use crate::control::{tagged_control, CONTROL_TAG};
+use crate::protocol::PlayerCheck;
+use crate::report::Reports;
+use crate::spectators::{Spectators, SPECTATOR_TAG};
+use crate::timing::Clocks;
+use crate::transcript::{Direction, Transcript};
+use crate::transport::{MessageReader, MessageWriter};
+use anyhow::Result;
+use futures::future::select_all;
+use log::{info, trace, warn};
+use std::fmt::Display;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+// Tag addressing a manager line to every player
+const BROADCAST_TAG: &str = "*";
+
+// What the manager is told instead of getting a message check rejected
+fn invalid_report(player: usize, delim: char, err: &str) -> Vec<u8> {
+    warn!("{player}: sent an invalid message ({err})");
+    tagged_control(delim, &format!("invalid {player} {err}"))
+}
+
+// Given a delim and a tag, tag every line from line_reader and send it through sender
+// Messages check rejects are reported to the manager in their place
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn tag_and_echo_stdout_to_channel(
+    mut line_reader: MessageReader,
+    sender: Sender<Vec<u8>>,
+    tag: usize,
+    delim: char,
+    transcript: Transcript,
+    clocks: Clocks,
+    report_times: bool,
+    check: PlayerCheck,
+) -> Result<()> {
+    info!("{tag}: start tagging and echoing stdout");
+    while let Some(message) = line_reader.next_message().await? {
+        let message = match check.check(message) {
+            Ok(message) => message,
+            Err(err) => {
+                let _ = sender.send(invalid_report(tag, delim, &err)).await;
+                continue;
+            }
+        };
+        let line = String::from_utf8_lossy(&message);
+        trace!(
+            process = tag + 1,
+            direction = Direction::PlayerToManager.as_str(),
+            payload = line.as_ref();
+            "{tag}: tagging and forwarding '{line}'"
+        );
+        let response_time = clocks.responded(tag);
+        let elapsed_ms = response_time.map(|time| time.elapsed.as_millis() as u64);
+        transcript
+            .record(Direction::PlayerToManager, tag, &message, elapsed_ms)
+            .await?;
+        // Keep reading after the manager is gone so the player never blocks on a full pipe
+        if sender
+            .send(tag_message(tag, delim, &message))
+            .await
+            .is_err()
+        {
+            trace!("{tag}: manager is gone, dropping '{line}'");
+            continue;
+        }
+        if let (true, Some(time)) = (report_times, response_time) {
+            let _ = sender
+                .send(tagged_control(delim, &time.control_message(tag)))
+                .await;
+        }
+        trace!("{line} sent to channel");
+    }
+    clocks.closed(tag);
+    info!("{tag}: done tagging and echoing stdout");
+    Ok(())
+}
+
+// Tell whoever is on the other end of writer that no more messages are coming
+// Errors are ignored, a participant that already went away doesn't need telling
+pub(crate) async fn close_writer(mut writer: MessageWriter) {
+    if let Err(err) = writer.shutdown().await {
+        trace!("Closing a writer failed: {err}");
+    }
+}
+
+// Prefix message with tag and delim
+pub(crate) fn tag_message(tag: impl Display, delim: char, message: &[u8]) -> Vec<u8> {
+    let mut tagged = format!("{tag}{delim}").into_bytes();
+    tagged.extend_from_slice(message);
+    tagged
+}
+
+// Split a message at its first delim. The tag before it has to be text, what follows is
+// passed on untouched, so with length framing it can be anything
+fn split_tag(message: &[u8], delim: char) -> Option<(&str, &[u8])> {
+    let mut buf = [0; 4];
+    let delim = delim.encode_utf8(&mut buf).as_bytes();
+    let at = message
+        .windows(delim.len())
+        .position(|window| window == delim)?;
+    let tag = std::str::from_utf8(&message[..at]).ok()?;
+    Some((tag, &message[at + delim.len()..]))
+}
+
+// Split a manager line into recipients and message. Only the first delim counts, so the
+// message itself may contain delims. The tag is a player index, a comma separated list of
+// them like `0,2`, or `*` for every player. None if any of it isn't a valid player
+fn parse_tagged_line(line: &[u8], delim: char, num_players: usize) -> Option<(Vec<usize>, &[u8])> {
+    let (prefix, message) = split_tag(line, delim)?;
+    if prefix == BROADCAST_TAG {
+        return Some(((0..num_players).collect(), message));
+    }
+    let mut recipients = Vec::new();
+    for tag in prefix.split(',') {
+        trace!("Trying to parse {tag} as a usize");
+        let recipient = tag.parse::<usize>().ok()?;
+        if recipient >= num_players {
+            return None;
+        }
+        // Saying the same thing twice to someone is never what the manager meant
+        if !recipients.contains(&recipient) {
+            recipients.push(recipient);
+        }
+    }
+    Some((recipients, message))
+}
+
+// The message of a manager line if it's tagged with tag, e.g. CONTROL_TAG
+fn tagged_with<'a>(line: &'a [u8], delim: char, tag: &str) -> Option<&'a [u8]> {
+    let (prefix, message) = split_tag(line, delim)?;
+    (prefix == tag).then_some(message)
+}
+
+// Handle what the manager sends the metamanager or spectators rather than a player.
+// Returns false for lines that are for players
+fn handle_untagged(
+    line: &[u8],
+    delim: char,
+    num_players: usize,
+    spectators: &Spectators,
+    reports: &Reports,
+) -> bool {
+    if let Some(message) = tagged_with(line, delim, CONTROL_TAG) {
+        match std::str::from_utf8(message) {
+            Ok(message) => reports.manager_control(message, num_players),
+            Err(_) => warn!("Dropping control message that isn't UTF-8"),
+        }
+        return true;
+    }
+    if let Some(message) = tagged_with(line, delim, SPECTATOR_TAG) {
+        spectators.public(message);
+        return true;
+    }
+    false
+}
+
+// Uses line_reader to read stdout line-by-line, then parse out tag from message using delim
+// Uses tag to feed the parsed message to the correct channel
+pub(crate) async fn echo_tagged_stdout_to_channel(
+    mut line_reader: MessageReader,
+    senders: Vec<Sender<Vec<u8>>>,
+    delim: char,
+    spectators: Spectators,
+    reports: Reports,
+) -> Result<()> {
+    info!("Tagged stdout echoes starting");
+    while let Some(tagged) = line_reader.next_message().await? {
+        if handle_untagged(&tagged, delim, senders.len(), &spectators, &reports) {
+            continue;
+        }
+        let line = String::from_utf8_lossy(&tagged);
+        let (recipients, message) = match parse_tagged_line(&tagged, delim, senders.len()) {
+            Some(parsed) => parsed,
+            None => {
+                warn!("Dropping manager line without a valid player tag: '{line}'");
+                continue;
+            }
+        };
+        // TODO(mbwang): 0 index children or 1 index them? 1 indexing allows us
+        //               to have the manager as 0 (or maybe the visualizer/log?)
+        // senders[recipient - 1]
+        let text = String::from_utf8_lossy(message);
+        for recipient in recipients {
+            trace!(
+                process = recipient + 1,
+                direction = Direction::ManagerToPlayer.as_str(),
+                payload = text.as_ref();
+                "Read tagged {line}, untagging and forwarding to {recipient}"
+            );
+            // Recorded and timed by echo_channel_to_stdin once the player has it
+            if senders[recipient].send(message.to_vec()).await.is_err() {
+                trace!("{recipient} stopped reading, dropping '{line}'");
+                continue;
+            }
+            trace!("{line} sent to {recipient}");
+        }
+    }
+    info!("Tagged stdout echoes done");
+    Ok(())
+}
+
+// Dump all strings from the channel into the given stdin
+// If whoever is behind writer stops reading, the receiver is dropped so senders can tell.
+// For a player, what it got is recorded in the transcript and starts its clock
+pub(crate) async fn echo_channel_to_stdin(
+    mut writer: MessageWriter,
+    mut receiver: Receiver<Vec<u8>>,
+    name: String,
+    player: Option<(usize, Transcript, Clocks)>,
+) -> Result<()> {
+    info!("Start echoing to {name}'s stdin");
+    while let Some(message) = receiver.recv().await {
+        let message_text = String::from_utf8_lossy(&message);
+        trace!("Received {message_text}, echoing line to stdin");
+        if let Err(err) = writer.send(&message).await {
+            warn!("{name} stopped reading ({err}), dropping its messages");
+            break;
+        }
+        if let Some((idx, transcript, clocks)) = &player {
+            // Only what a player got is in the transcript, or replays would send it more
+            transcript
+                .record(Direction::ManagerToPlayer, *idx, &message, None)
+                .await?;
+            clocks.delivered(*idx);
+        }
+        trace!("{message_text} sent to stdin");
+    }
+    receiver.close();
+    close_writer(writer).await;
+    info!("Done echoing to stdin");
+    Ok(())
+}
+
+// Uses line_reader to read stdout line-by-line, then parse out tag from message using delim
+// Uses tag to feed the parsed message to the correct channel
+pub(crate) async fn route_and_echo_tagged_messages(
+    mut line_reader: MessageReader,
+    stdins: Vec<MessageWriter>,
+    delim: char,
+    transcript: Transcript,
+    clocks: Clocks,
+    spectators: Spectators,
+    reports: Reports,
+) -> Result<()> {
+    info!("Start forwarding manager messages to players...");
+    // None once a player stops reading
+    let mut stdins: Vec<Option<MessageWriter>> = stdins.into_iter().map(Some).collect();
+    while let Some(tagged) = line_reader.next_message().await? {
+        let line = String::from_utf8_lossy(&tagged);
+        trace!("Forwarding '{line}' to a player");
+        if handle_untagged(&tagged, delim, stdins.len(), &spectators, &reports) {
+            continue;
+        }
+        let (recipients, message) = match parse_tagged_line(&tagged, delim, stdins.len()) {
+            Some(parsed) => parsed,
+            None => {
+                warn!("Dropping manager line without a valid player tag: '{line}'");
+                continue;
+            }
+        };
+        // TODO(mbwang): 0 index children or 1 index them? 1 indexing allows us
+        //               to have the manager as 0 (or maybe the visualizer/log?)
+        let text = String::from_utf8_lossy(message);
+        for recipient in recipients {
+            let stdin = match &mut stdins[recipient] {
+                Some(stdin) => stdin,
+                None => {
+                    trace!("{recipient} stopped reading, dropping '{line}'");
+                    continue;
+                }
+            };
+            if let Err(err) = stdin.send(message).await {
+                warn!("{recipient} stopped reading ({err}), dropping its messages");
+                stdins[recipient] = None;
+                continue;
+            }
+            // Only what a player got is in the transcript, or replays would send it more
+            transcript
+                .record(Direction::ManagerToPlayer, recipient, message, None)
+                .await?;
+            clocks.delivered(recipient);
+            trace!(
+                process = recipient + 1,
+                direction = Direction::ManagerToPlayer.as_str(),
+                payload = text.as_ref();
+                "Sent to {recipient}"
+            );
+        }
+    }
+    for stdin in stdins.into_iter().flatten() {
+        close_writer(stdin).await;
+    }
+    info!("Done forwarding manager messages to players!");
+    Ok(())
+}
+
+async fn write_to_manager(stdin: &mut Option<MessageWriter>, message: &[u8]) {
+    if let Some(writer) = stdin {
+        match writer.send(message).await {
+            Ok(()) => trace!("Message sent to manager"),
+            Err(err) => {
+                warn!("Manager stopped reading ({err}), dropping player messages");
+                *stdin = None;
+            }
+        }
+    }
+}
+
+async fn wait_for_next_segment_tagged(
+    mut line_reader: MessageReader,
+    tag: usize,
+) -> Result<(Option<Vec<u8>>, MessageReader, usize)> {
+    Ok((line_reader.next_message().await?, line_reader, tag))
+}
+
+// Given a delim, echo all stdout from line_readers to stdin, after tagging messages with their sender
+// Control messages from control_receiver are passed to stdin in between, and messages
+// check rejects are reported in their place
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn tag_and_echo_messages(
+    line_readers: Vec<MessageReader>,
+    stdin: MessageWriter,
+    delim: char,
+    transcript: Transcript,
+    clocks: Clocks,
+    report_times: bool,
+    mut control_receiver: Receiver<Vec<u8>>,
+    check: PlayerCheck,
+) -> Result<()> {
+    // None once the manager stops reading, players are still drained so they never block
+    let mut stdin = Some(stdin);
+    info!("Start tagging and forwarding player messages to manager");
+    let read_coroutines = line_readers
+        .into_iter()
+        .enumerate()
+        .map(|(idx, reader)| Box::pin(wait_for_next_segment_tagged(reader, idx)))
+        .collect::<Vec<_>>();
+    if read_coroutines.is_empty() {
+        return Ok(());
+    }
+    let mut control_open = true;
+    let mut pending_reads = select_all(read_coroutines);
+    loop {
+        let (result, _, mut waiting_futures) = tokio::select! {
+            finished = &mut pending_reads => finished,
+            control = control_receiver.recv(), if control_open => {
+                match control {
+                    Some(message) => {
+                        trace!(
+                            "Control message for manager: {}",
+                            String::from_utf8_lossy(&message)
+                        );
+                        write_to_manager(&mut stdin, &message).await;
+                    }
+                    None => control_open = false,
+                }
+                continue;
+            }
+        };
+        let (maybe_data, reader, user_id) = result?;
+        match maybe_data.map(|message| check.check(message)) {
+            Some(Ok(message)) => {
+                let data = String::from_utf8_lossy(&message);
+                trace!(
+                    process = user_id + 1,
+                    direction = Direction::PlayerToManager.as_str(),
+                    payload = data.as_ref();
+                    "Message from {user_id}: {data}"
+                );
+                let response_time = clocks.responded(user_id);
+                let elapsed_ms = response_time.map(|time| time.elapsed.as_millis() as u64);
+                transcript
+                    .record(Direction::PlayerToManager, user_id, &message, elapsed_ms)
+                    .await?;
+                write_to_manager(&mut stdin, &tag_message(user_id, delim, &message)).await;
+                if let (true, Some(time)) = (report_times, response_time) {
+                    let control = tagged_control(delim, &time.control_message(user_id));
+                    write_to_manager(&mut stdin, &control).await;
+                }
+                waiting_futures.push(Box::pin(wait_for_next_segment_tagged(reader, user_id)));
+            }
+            Some(Err(err)) => {
+                write_to_manager(&mut stdin, &invalid_report(user_id, delim, &err)).await;
+                waiting_futures.push(Box::pin(wait_for_next_segment_tagged(reader, user_id)));
+            }
+            None => {
+                clocks.closed(user_id);
+                info!("{user_id} sent no data, closing their connection");
+            }
+        }
+        if waiting_futures.is_empty() {
+            break;
+        }
+        pending_reads = select_all(waiting_futures);
+    }
+    // Control messages can still follow the last player's EOF, like a sandboxed player's forfeit
+    while control_open {
+        match control_receiver.recv().await {
+            Some(message) => write_to_manager(&mut stdin, &message).await,
+            None => control_open = false,
+        }
+    }
+    if let Some(stdin) = stdin {
+        close_writer(stdin).await;
+    }
+    info!("Done tagging and forwarding player messages to manager");
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use crate::control::tagged_control;
+use crate::process::EXIT_GRACE;
+use crate::timing::Clocks;
+use crate::usage::{wait_for_exit, ProcessStats, Sample};
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use std::process::ExitStatus;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::Sender;
+
+const EXIT_POLL: Duration = Duration::from_millis(50);
+
+// Isolation for untrusted players, Linux only. Every sandboxed process gets its own process
+// group (killed as a whole once the player is done), a private TMPDIR and, unless network
+// is set, an empty network namespace
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxConfig {
+    // CPU seconds, the process gets SIGXCPU when they run out and SIGKILL a second later
+    pub cpu_seconds: Option<u64>,
+    // Address space in MiB
+    pub memory_mb: Option<u64>,
+    // Largest file the process may write, in MiB
+    pub file_size_mb: Option<u64>,
+    // Processes and threads the user may have. Linux counts every process the user owns
+    // and doesn't apply this to root at all
+    pub processes: Option<u64>,
+    // Keep network access
+    #[serde(default)]
+    pub network: bool,
+}
+
+impl SandboxConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !cfg!(target_os = "linux") {
+            bail!("Sandboxing is only supported on Linux");
+        }
+        for limit in [
+            self.cpu_seconds,
+            self.memory_mb,
+            self.file_size_mb,
+            self.processes,
+        ] {
+            if limit == Some(0) {
+                bail!("Sandbox limits must be at least 1");
+            }
+        }
+        Ok(())
+    }
+
+    // Why a sandboxed process that exited with status broke the rules, if it did.
+    // SIGKILL also comes from outside and a crash may just be a bug, so those are only
+    // blamed on a limit when what the process used says it got there. Running out of
+    // address space makes allocations fail, which usually ends in an abort or a segfault
+    fn violation(&self, status: ExitStatus, used: Option<Sample>) -> Option<&'static str> {
+        use std::os::unix::process::ExitStatusExt;
+        let used = used.unwrap_or_default();
+        let cpu_exhausted = self
+            .cpu_seconds
+            .zip(used.cpu_ms)
+            .is_some_and(|(limit, cpu_ms)| cpu_ms >= limit * 1000);
+        let memory_exhausted = self.memory_mb.is_some_and(|limit| {
+            let near_limit = |kb: Option<u64>| kb.is_some_and(|kb| kb * 100 >= limit * 1024 * 90);
+            near_limit(used.peak_vm_kb) || near_limit(used.peak_rss_kb)
+        });
+        match status.signal()? {
+            libc::SIGXCPU => Some("cpu limit"),
+            libc::SIGKILL if cpu_exhausted => Some("cpu limit"),
+            libc::SIGXFSZ => Some("file size limit"),
+            libc::SIGSEGV | libc::SIGABRT | libc::SIGBUS if memory_exhausted => {
+                Some("memory limit")
+            }
+            _ => None,
+        }
+    }
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal {
+        libc::SIGKILL => "SIGKILL".to_string(),
+        libc::SIGSEGV => "SIGSEGV".to_string(),
+        libc::SIGABRT => "SIGABRT".to_string(),
+        libc::SIGBUS => "SIGBUS".to_string(),
+        libc::SIGTERM => "SIGTERM".to_string(),
+        libc::SIGPIPE => "SIGPIPE".to_string(),
+        _ => format!("signal {signal}"),
+    }
+}
+
+// A sandboxed process and what has to be cleaned up after it
+pub(crate) struct SandboxedChild {
+    pub(crate) child: Child,
+    // The child's pid, which is also its process group's id
+    pgid: Option<u32>,
+    config: SandboxConfig,
+    // Removed when this is dropped
+    _tmp: TempDir,
+}
+
+impl SandboxedChild {
+    // The limit the process broke to end with status, stats being how it was measured
+    pub(crate) fn violation(
+        &self,
+        status: ExitStatus,
+        stats: Option<&ProcessStats>,
+    ) -> Option<&'static str> {
+        self.config
+            .violation(status, stats.map(ProcessStats::latest))
+    }
+}
+
+impl Drop for SandboxedChild {
+    // Whatever the player left running dies with it
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+// Set up command to run in the sandbox. The returned temp dir has to outlive the process
+pub(crate) fn sandbox_command(command: &mut Command, config: &SandboxConfig) -> Result<TempDir> {
+    let tmp = tempfile::Builder::new()
+        .prefix("metamanager-")
+        .tempdir()
+        .context("Could not create a private temp dir")?;
+    for var in ["TMPDIR", "TMP", "TEMP"] {
+        command.env(var, tmp.path());
+    }
+    let limits = [
+        (
+            libc::RLIMIT_CPU,
+            config.cpu_seconds.map(|secs| (secs, secs + 1)),
+        ),
+        (
+            libc::RLIMIT_AS,
+            config.memory_mb.map(|mb| (mb << 20, mb << 20)),
+        ),
+        (
+            libc::RLIMIT_FSIZE,
+            config.file_size_mb.map(|mb| (mb << 20, mb << 20)),
+        ),
+        (libc::RLIMIT_NPROC, config.processes.map(|n| (n, n))),
+    ];
+    let isolate_network = !config.network;
+    // Without root, a user namespace is what allows making a network namespace. Map our
+    // own ids into it so files keep their owners. Everything is formatted before forking,
+    // the child may only make raw syscalls
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let id_maps = [
+        (c"/proc/self/setgroups", String::from("deny")),
+        (c"/proc/self/uid_map", format!("{uid} {uid} 1")),
+        (c"/proc/self/gid_map", format!("{gid} {gid} 1")),
+    ];
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if isolate_network {
+                if uid == 0 {
+                    if libc::unshare(libc::CLONE_NEWNET) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                } else {
+                    if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    for (path, contents) in &id_maps {
+                        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+                        if fd < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+                        libc::close(fd);
+                        if written < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                }
+            }
+            for (resource, limit) in limits {
+                if let Some((soft, hard)) = limit {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: soft as libc::rlim_t,
+                        rlim_max: hard as libc::rlim_t,
+                    };
+                    if libc::setrlimit(resource, &rlimit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(tmp)
+}
+
+pub(crate) fn spawned(child: Child, config: &SandboxConfig, tmp: TempDir) -> SandboxedChild {
+    SandboxedChild {
+        pgid: child.id(),
+        child,
+        config: config.clone(),
+        _tmp: tmp,
+    }
+}
+
+// Wait for a sandboxed player to exit and tell the manager `forfeit <player> <reason>` if
+// it broke one of the limits. A player that closed its stdout but doesn't exit is killed
+// after a grace period, so this never holds up the end of a match
+pub(crate) async fn watch_sandboxed(
+    mut sandboxed: SandboxedChild,
+    stats: Option<ProcessStats>,
+    player: usize,
+    clocks: Clocks,
+    sender: Sender<Vec<u8>>,
+    delim: char,
+) -> Result<()> {
+    let mut closed_for = Duration::ZERO;
+    let status = loop {
+        tokio::select! {
+            status = wait_for_exit(&mut sandboxed.child, stats.as_ref()) => break Some(status?),
+            _ = tokio::time::sleep(EXIT_POLL) => {
+                if clocks.is_closed(player) {
+                    closed_for += EXIT_POLL;
+                    if closed_for >= EXIT_GRACE {
+                        warn!("{player}: still running after closing its output, killing it");
+                        break None;
+                    }
+                }
+            }
+        }
+    };
+    let violation = status.and_then(|status| sandboxed.violation(status, stats.as_ref()));
+    drop(sandboxed);
+    if violation.is_none() {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.and_then(|status| status.signal()) {
+            warn!("{player}: killed by {}", signal_name(signal));
+        }
+    }
+    if let Some(reason) = violation {
+        warn!("{player}: forfeits for going over its {reason}");
+        if sender
+            .send(tagged_control(delim, &format!("forfeit {player} {reason}")))
+            .await
+            .is_err()
+        {
+            info!("{player}: manager stopped listening before hearing about the forfeit");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use crate::logging::spawn_in_match;
+use crate::transcript::TranscriptEntry;
+use crate::transport::{split_transport, BoxedTransport, Framing};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use futures::future::{join_all, pending, FusedFuture, FutureExt};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UnixListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+// Tag the manager uses for lines meant for spectators, e.g. `spec:board x.o......`
+pub const SPECTATOR_TAG: &str = "spec";
+
+// How many messages a spectator may fall behind before it starts missing some
+// Spectators never slow the match down, a slow one is told what it missed instead
+const FEED_CAPACITY: usize = 4096;
+
+// How long a spectator gets to finish up after the match, before its process is killed
+const SPECTATOR_GRACE: Duration = Duration::from_secs(1);
+
+// What spectators get to see
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SpectatorFeed {
+    // Every routed message, one JSON transcript entry per line
+    #[default]
+    All,
+    // Only what the manager sends to `spec`, e.g. for games with hidden information.
+    // Framed like the match, so binary messages arrive intact
+    Public,
+}
+
+impl SpectatorFeed {
+    // How spectators are fed in a match framed like framing
+    pub fn framing(&self, framing: Framing) -> Framing {
+        match self {
+            SpectatorFeed::All => Framing::Lines,
+            SpectatorFeed::Public => framing,
+        }
+    }
+}
+
+// Handle shared by everything that publishes to spectators
+#[derive(Clone)]
+pub struct Spectators {
+    feed: SpectatorFeed,
+    // How the manager's messages are framed for a Public feed, JSON always comes in lines
+    framing: Framing,
+    sender: broadcast::Sender<Vec<u8>>,
+}
+
+impl Spectators {
+    pub fn new(feed: SpectatorFeed, framing: Framing) -> Spectators {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        Spectators {
+            feed,
+            framing,
+            sender,
+        }
+    }
+
+    pub fn feed(&self) -> SpectatorFeed {
+        self.feed
+    }
+
+    // A message was routed, only shown with the All feed
+    pub(crate) fn routed(&self, entry: &TranscriptEntry) -> Result<()> {
+        if self.feed == SpectatorFeed::All {
+            // Nobody watching is fine
+            let _ = self.sender.send(serde_json::to_vec(entry)?);
+        }
+        Ok(())
+    }
+
+    // The manager addressed spectators, only shown with the Public feed. Passed on as is,
+    // binary payloads included
+    pub(crate) fn public(&self, message: &[u8]) {
+        if self.feed == SpectatorFeed::Public {
+            let _ = self.sender.send(message.to_vec());
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Vec<u8>> {
+        self.sender.subscribe()
+    }
+
+    // Start feeding a spectator. It sees everything published from now on, and is done
+    // once every Spectators handle is dropped, i.e. when the match is over
+    pub(crate) fn watch(&self, transport: BoxedTransport, name: String) -> JoinHandle<()> {
+        let framing = self.feed.framing(self.framing);
+        spawn_in_match(watch(transport, framing, self.subscribe(), name))
+    }
+}
+
+async fn watch(
+    transport: BoxedTransport,
+    framing: Framing,
+    mut receiver: broadcast::Receiver<Vec<u8>>,
+    name: String,
+) {
+    info!("{name} is watching");
+    let (mut reader, mut writer) = split_transport(transport, framing);
+    let forward = async {
+        loop {
+            let line = match receiver.recv().await {
+                Ok(line) => line,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("{name} fell behind and missed {missed} messages");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if let Err(err) = writer.send(&line).await {
+                info!("{name} stopped watching: {err}");
+                return;
+            }
+        }
+        let _ = writer.shutdown().await;
+        // Dropped rather than just shut down, a child's stdin only closes when dropped
+        drop(writer);
+    };
+    // Spectators can't send anything, but their output still has to be drained. Fused, as
+    // it may be done before forward is
+    let ignore_output = async {
+        while let Ok(Some(message)) = reader.next_message().await {
+            debug!(
+                "{name}: spectators can't send messages, ignoring '{}'",
+                String::from_utf8_lossy(&message)
+            );
+        }
+    }
+    .fuse();
+    tokio::pin!(ignore_output);
+    tokio::select! {
+        _ = forward => {}
+        _ = async { (&mut ignore_output).await; pending::<()>().await } => {}
+    }
+    // Let it wrap up, e.g. a logger writing out what it saw
+    if !ignore_output.is_terminated() {
+        let _ = timeout(SPECTATOR_GRACE, ignore_output).await;
+    }
+    info!("{name} is done watching");
+}
+
+// Let spectators join a running match through a unix socket at path
+// Runs until aborted, see SpectatorSocket::close
+pub(crate) struct SpectatorSocket {
+    accept_task: JoinHandle<()>,
+    watchers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    path: Box<Path>,
+}
+
+impl SpectatorSocket {
+    pub(crate) fn bind(path: &Path, spectators: Spectators) -> Result<SpectatorSocket> {
+        // A socket left behind by an earlier match would make bind fail
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Could not listen for spectators on {}", path.display()))?;
+        info!("Spectators can join at {}", path.display());
+        let watchers = Arc::new(Mutex::new(Vec::new()));
+        let accept_task = spawn_in_match({
+            let watchers = watchers.clone();
+            async move {
+                let mut joined = 0;
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            let name = format!("socket spectator {joined}");
+                            joined += 1;
+                            let transport = Box::new(tokio::io::BufReader::new(stream));
+                            let watcher = spectators.watch(transport, name);
+                            watchers.lock().unwrap().push(watcher);
+                        }
+                        Err(err) => warn!("Could not accept a spectator: {err}"),
+                    }
+                }
+            }
+        });
+        Ok(SpectatorSocket {
+            accept_task,
+            watchers,
+            path: path.into(),
+        })
+    }
+
+    // Stop letting spectators in, then wait for the ones that joined to finish watching
+    pub(crate) async fn close(self) {
+        self.accept_task.abort();
+        let _ = self.accept_task.await;
+        let _ = std::fs::remove_file(&self.path);
+        let watchers = std::mem::take(&mut *self.watchers.lock().unwrap());
+        join_all(watchers).await;
+    }
+}
@@ -0,0 +1,233 @@
+use crate::tournament::{play_game, Game, GameRecord, TournamentConfig};
+use anyhow::{bail, Result};
+use futures::stream::{self, StreamExt};
+use std::fmt;
+
+// A/B test of a new bot version against an old one, played until a sequential probability
+// ratio test decides between H0 (the new bot gains elo0) and H1 (it gains elo1)
+#[derive(Clone, Debug)]
+pub struct SprtConfig {
+    // Referee and game settings, bots[0] is the new version and bots[1] the old one.
+    // The format and games per pairing are ignored, games come in pairs with swapped seats
+    pub games: TournamentConfig,
+    pub elo0: f64,
+    pub elo1: f64,
+    // Chance of accepting H1 when H0 is true, and the other way around
+    pub alpha: f64,
+    pub beta: f64,
+    // Give up without a verdict after this many games
+    pub max_games: Option<usize>,
+}
+
+impl Default for SprtConfig {
+    fn default() -> SprtConfig {
+        SprtConfig {
+            games: TournamentConfig::default(),
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+            max_games: None,
+        }
+    }
+}
+
+impl SprtConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.games.validate()?;
+        if self.games.bots.len() != 2 {
+            bail!("An SPRT test plays exactly two bots, the new one and the old one");
+        }
+        if self.elo1 <= self.elo0 {
+            bail!("elo1 has to be bigger than elo0");
+        }
+        for bound in [self.alpha, self.beta] {
+            if !(bound > 0.0 && bound < 0.5) {
+                bail!("alpha and beta have to be between 0 and 0.5");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    // The new version gains at least elo1
+    H1,
+    // It gains no more than elo0
+    H0,
+    // max_games ran out first
+    Inconclusive,
+}
+
+// Expected score of a player rated elo above its opponent
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// Results so far, from the new version's side
+#[derive(Clone, Debug)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    // Games that crashed or ended without a result, they don't count
+    pub failed: usize,
+    pub verdict: Option<Verdict>,
+}
+
+impl Sprt {
+    pub fn new(config: &SprtConfig) -> Sprt {
+        Sprt {
+            elo0: config.elo0,
+            elo1: config.elo1,
+            lower: (config.beta / (1.0 - config.alpha)).ln(),
+            upper: ((1.0 - config.beta) / config.alpha).ln(),
+            wins: 0,
+            draws: 0,
+            losses: 0,
+            failed: 0,
+            verdict: None,
+        }
+    }
+
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    // Share of the points the new version got
+    pub fn score(&self) -> f64 {
+        match self.games() {
+            0 => 0.5,
+            games => (self.wins as f64 + self.draws as f64 / 2.0) / games as f64,
+        }
+    }
+
+    // Elo difference the score so far suggests, infinite after a clean sweep
+    pub fn elo(&self) -> f64 {
+        -400.0 * (1.0 / self.score() - 1.0).log10()
+    }
+
+    // Log-likelihood ratio of H1 to H0, by the usual normal approximation of the
+    // win/draw/loss trinomial. Half a game is added to each outcome when estimating the
+    // variance, so a run of only draws or only wins doesn't divide by zero
+    pub fn llr(&self) -> f64 {
+        let games = self.games() as f64;
+        if games == 0.0 {
+            return 0.0;
+        }
+        let score = self.score();
+        let padded = games + 1.5;
+        let (wins, draws) = (
+            (self.wins as f64 + 0.5) / padded,
+            (self.draws as f64 + 0.5) / padded,
+        );
+        let padded_score = wins + draws / 2.0;
+        let variance = wins + draws / 4.0 - padded_score * padded_score;
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        games * (s1 - s0) * (2.0 * score - s0 - s1) / (2.0 * variance)
+    }
+
+    fn record(&mut self, new_score: f64, old_score: f64) {
+        if new_score > old_score {
+            self.wins += 1;
+        } else if new_score < old_score {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+        let llr = self.llr();
+        if llr >= self.upper {
+            self.verdict = Some(Verdict::H1);
+        } else if llr <= self.lower {
+            self.verdict = Some(Verdict::H0);
+        }
+    }
+}
+
+impl fmt::Display for Sprt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LLR {:.2} ({:.2}, {:.2}) [{}, {}], score {:.1}% (+{} ={} -{}), Elo {:+.1} after {} games",
+            self.llr(),
+            self.lower,
+            self.upper,
+            self.elo0,
+            self.elo1,
+            100.0 * self.score(),
+            self.wins,
+            self.draws,
+            self.losses,
+            self.elo(),
+            self.games()
+        )?;
+        if self.failed > 0 {
+            write!(f, " ({} failed)", self.failed)?;
+        }
+        match self.verdict {
+            Some(Verdict::H1) => write!(f, ": H1 accepted, the new version is stronger"),
+            Some(Verdict::H0) => write!(f, ": H0 accepted, the new version is not stronger"),
+            Some(Verdict::Inconclusive) => write!(f, ": inconclusive"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Play the new version against the old one until the test decides, calling on_game as each
+// game finishes. Games still running once it has decided are killed
+pub async fn run_sprt(
+    config: &SprtConfig,
+    mut on_game: impl FnMut(&GameRecord, &Sprt),
+) -> Result<Sprt> {
+    config.validate()?;
+    let games = &config.games;
+    if let Some(dir) = &games.dir {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let names = [games.bots[0].display_name(), games.bots[1].display_name()];
+    // Seats swap every game so each pair of games is played from both sides
+    let schedule = (0..)
+        .map(|idx: usize| Game {
+            number: idx + 1,
+            round: idx / 2 + 1,
+            seats: if idx.is_multiple_of(2) {
+                [0, 1]
+            } else {
+                [1, 0]
+            },
+        })
+        .take(config.max_games.unwrap_or(usize::MAX));
+    let mut finished = stream::iter(schedule)
+        .map(|game| async move { (game, play_game(game, games.game_config(&game)).await) })
+        .buffer_unordered(games.concurrency);
+    let mut sprt = Sprt::new(config);
+    while let Some((game, (result, usage))) = finished.next().await {
+        let record = GameRecord {
+            game,
+            names: game.seats.map(|bot| names[bot].clone()),
+            result,
+            usage,
+        };
+        match &record.result {
+            Some(result) => {
+                let new_seat = game.seats.iter().position(|&bot| bot == 0).unwrap();
+                sprt.record(result.scores[new_seat], result.scores[1 - new_seat]);
+            }
+            None => sprt.failed += 1,
+        }
+        if sprt.failed >= 10 && sprt.failed > sprt.games() {
+            bail!("Most games are failing, check the referee and both bots work");
+        }
+        on_game(&record, &sprt);
+        if sprt.verdict.is_some() {
+            return Ok(sprt);
+        }
+    }
+    sprt.verdict = Some(Verdict::Inconclusive);
+    Ok(sprt)
+}
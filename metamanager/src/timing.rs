@@ -0,0 +1,222 @@
+use crate::control::tagged_control;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+
+// Chess clock style budget: every player starts with base and gains increment per move
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TimeControl {
+    #[serde(with = "millis")]
+    pub base_ms: Duration,
+    #[serde(with = "millis", default)]
+    pub increment_ms: Duration,
+}
+
+mod millis {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+impl std::str::FromStr for TimeControl {
+    type Err = anyhow::Error;
+
+    // Parse chess notation in seconds, e.g. "300+2" or "0.5+0.01"
+    fn from_str(s: &str) -> Result<TimeControl> {
+        let (base, increment) = s.split_once('+').unwrap_or((s, "0"));
+        let seconds = |value: &str| -> Result<Duration> {
+            let value = value
+                .trim()
+                .parse::<f64>()
+                .with_context(|| format!("'{value}' is not a number of seconds"))?;
+            if !value.is_finite() || value < 0.0 {
+                bail!("'{value}' is not a valid number of seconds");
+            }
+            Ok(Duration::from_secs_f64(value))
+        };
+        Ok(TimeControl {
+            base_ms: seconds(base)?,
+            increment_ms: seconds(increment)?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct PlayerClock {
+    // Set while the player owes a response, since the last message delivered to it
+    waiting_since: Option<Instant>,
+    // Time left on the clock, only tracked with a time control
+    remaining: Option<Duration>,
+    flagged: bool,
+    closed: bool,
+}
+
+// How long a player took to answer, and what's left on its clock afterwards
+#[derive(Clone, Copy, Debug)]
+pub struct ResponseTime {
+    pub elapsed: Duration,
+    pub remaining: Option<Duration>,
+}
+
+impl ResponseTime {
+    // Body of the `time` control message: `time <player> <elapsed_ms> [<remaining_ms>]`
+    pub fn control_message(&self, player: usize) -> String {
+        let mut message = format!("time {player} {}", self.elapsed.as_millis());
+        if let Some(remaining) = self.remaining {
+            message.push_str(&format!(" {}", remaining.as_millis()));
+        }
+        message
+    }
+}
+
+// Per player clocks shared by every routing task
+// A player's clock (re)starts whenever the manager sends it a message, and stops at its
+// next message. Restarting rather than accumulating means a player told its role and then
+// told the opponent's move is only charged from the move, which is what turn based games want
+#[derive(Clone)]
+pub struct Clocks {
+    time_control: Option<TimeControl>,
+    players: Arc<Mutex<Vec<PlayerClock>>>,
+    // Wakes the watchdog whenever a clock starts or stops
+    changed: Arc<Notify>,
+}
+
+impl Clocks {
+    pub fn new(num_players: usize, time_control: Option<TimeControl>) -> Clocks {
+        let players = (0..num_players)
+            .map(|_| PlayerClock {
+                remaining: time_control.map(|time_control| time_control.base_ms),
+                ..Default::default()
+            })
+            .collect();
+        Clocks {
+            time_control,
+            players: Arc::new(Mutex::new(players)),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn time_control(&self) -> Option<TimeControl> {
+        self.time_control
+    }
+
+    // The manager just sent player a message, start timing its response
+    pub fn delivered(&self, player: usize) {
+        if let Some(clock) = self.players.lock().unwrap().get_mut(player) {
+            clock.waiting_since = Some(Instant::now());
+        }
+        self.changed.notify_one();
+    }
+
+    // Player sent a message. None if nobody was waiting on it, e.g. it spoke unprompted
+    pub fn responded(&self, player: usize) -> Option<ResponseTime> {
+        let mut players = self.players.lock().unwrap();
+        let clock = players.get_mut(player)?;
+        let elapsed = clock.waiting_since.take()?.elapsed();
+        self.changed.notify_one();
+        if let (Some(remaining), Some(time_control)) = (clock.remaining.as_mut(), self.time_control)
+        {
+            *remaining = remaining.saturating_sub(elapsed);
+            if !clock.flagged {
+                *remaining += time_control.increment_ms;
+            }
+        }
+        Some(ResponseTime {
+            elapsed,
+            remaining: clock.remaining,
+        })
+    }
+
+    // Player closed its stdout, it won't answer anything anymore
+    pub fn closed(&self, player: usize) {
+        if let Some(clock) = self.players.lock().unwrap().get_mut(player) {
+            clock.waiting_since = None;
+            clock.closed = true;
+        }
+        self.changed.notify_one();
+    }
+
+    pub fn is_closed(&self, player: usize) -> bool {
+        self.players
+            .lock()
+            .unwrap()
+            .get(player)
+            .is_some_and(|clock| clock.closed)
+    }
+
+    fn all_closed(&self) -> bool {
+        self.players
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|clock| clock.closed)
+    }
+
+    // Players whose time just ran out, each player is only reported once, and when the
+    // next running clock runs out
+    fn newly_flagged(&self) -> (Vec<usize>, Option<Instant>) {
+        let now = Instant::now();
+        let mut flagged = Vec::new();
+        let mut next_deadline: Option<Instant> = None;
+        for (player, clock) in self.players.lock().unwrap().iter_mut().enumerate() {
+            if let (Some(since), Some(remaining), false) =
+                (clock.waiting_since, clock.remaining, clock.flagged)
+            {
+                let deadline = since + remaining;
+                if now >= deadline {
+                    clock.flagged = true;
+                    clock.remaining = Some(Duration::ZERO);
+                    flagged.push(player);
+                } else {
+                    next_deadline = Some(next_deadline.map_or(deadline, |next| next.min(deadline)));
+                }
+            }
+        }
+        (flagged, next_deadline)
+    }
+}
+
+// Tell the manager `timeout <player>` as soon as a player's clock runs out.
+// Runs until every player has closed its stdout, so it never keeps the manager's
+// stdin open on its own
+pub async fn clock_watchdog(clocks: Clocks, sender: Sender<Vec<u8>>, delim: char) -> Result<()> {
+    if clocks.time_control().is_none() {
+        return Ok(());
+    }
+    info!("Clock watchdog starting");
+    while !clocks.all_closed() {
+        let (flagged, next_deadline) = clocks.newly_flagged();
+        for player in flagged {
+            warn!("{player}: ran out of time");
+            if sender
+                .send(tagged_control(delim, &format!("timeout {player}")))
+                .await
+                .is_err()
+            {
+                info!("Manager stopped listening, clock watchdog done");
+                return Ok(());
+            }
+        }
+        // Sleep until the next clock runs out, or until one starts or stops and the next
+        // deadline has to be worked out again
+        match next_deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = sleep_until(deadline) => {}
+                    _ = clocks.changed.notified() => {}
+                }
+            }
+            None => clocks.changed.notified().await,
+        }
+    }
+    info!("Clock watchdog done");
+    Ok(())
+}
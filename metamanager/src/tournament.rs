@@ -0,0 +1,543 @@
+use crate::config::{MatchConfig, ProcessConfig, Routing};
+use crate::handshake::Handshake;
+use crate::matches::Match;
+use crate::protocol::Protocol;
+use crate::report::MatchResult;
+use crate::sandbox::SandboxConfig;
+use crate::timing::TimeControl;
+use crate::transport::Framing;
+use crate::usage::ProcessUsage;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Who plays whom
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    // Everyone plays everyone
+    #[default]
+    RoundRobin,
+    // One bot plays everyone else, see TournamentConfig::gauntlet
+    Gauntlet,
+    // A fixed number of rounds, each pairing bots with similar scores who haven't met yet
+    Swiss,
+}
+
+fn default_games_per_pairing() -> usize {
+    2
+}
+fn default_concurrency() -> usize {
+    1
+}
+fn default_delim() -> char {
+    MatchConfig::default().delim
+}
+
+// A referee plus the bots it referees, loaded from a tournament file and/or the command line
+// Every game is a two player match, the referee reports how it ended with `mm:result`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TournamentConfig {
+    #[serde(default)]
+    pub format: Format,
+    pub referee: ProcessConfig,
+    pub bots: Vec<ProcessConfig>,
+    // Games for every pair of bots, seats are swapped every other game
+    #[serde(default = "default_games_per_pairing")]
+    pub games_per_pairing: usize,
+    // How many games run at the same time
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    // Swiss rounds, by default enough to find a clear winner
+    pub rounds: Option<usize>,
+    // The bot a gauntlet is run for, by default the first one
+    pub gauntlet: Option<String>,
+    // If set, every game gets a match directory with a manifest, stderr logs and a
+    // transcript under here
+    pub dir: Option<PathBuf>,
+    #[serde(default = "default_delim")]
+    pub delim: char,
+    #[serde(default)]
+    pub routing: Routing,
+    #[serde(default)]
+    pub framing: Framing,
+    #[serde(default)]
+    pub protocol: Protocol,
+    // With the JSON protocol, what bot messages have to look like
+    pub schema: Option<PathBuf>,
+    pub time_control: Option<TimeControl>,
+    // If set, every game with a result is added to this rating store
+    pub ratings: Option<PathBuf>,
+    // If set, bots run sandboxed
+    pub sandbox: Option<SandboxConfig>,
+    // If set, bots have to answer a hello before every game
+    pub handshake: Option<Handshake>,
+    // How many times a bot that crashes may be restarted per game, see MatchConfig
+    #[serde(default)]
+    pub restarts: usize,
+}
+
+impl Default for TournamentConfig {
+    fn default() -> TournamentConfig {
+        TournamentConfig {
+            format: Format::default(),
+            referee: ProcessConfig::default(),
+            bots: Vec::new(),
+            games_per_pairing: default_games_per_pairing(),
+            concurrency: default_concurrency(),
+            rounds: None,
+            gauntlet: None,
+            dir: None,
+            delim: default_delim(),
+            routing: Routing::default(),
+            framing: Framing::default(),
+            protocol: Protocol::default(),
+            schema: None,
+            time_control: None,
+            ratings: None,
+            sandbox: None,
+            handshake: None,
+            restarts: 0,
+        }
+    }
+}
+
+impl TournamentConfig {
+    // Parse a tournament file, JSON if it ends in .json and TOML otherwise
+    // Relative paths are relative to the file, like in match files
+    pub fn from_file(path: &Path) -> Result<TournamentConfig> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read tournament file {}", path.display()))?;
+        let mut config: TournamentConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid JSON tournament file {}", path.display()))?,
+            _ => toml::from_str(&contents)
+                .with_context(|| format!("Invalid TOML tournament file {}", path.display()))?,
+        };
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for process in std::iter::once(&mut config.referee).chain(config.bots.iter_mut()) {
+            process.resolve(base)?;
+        }
+        for file in [
+            config.dir.as_mut(),
+            config.ratings.as_mut(),
+            config.schema.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if file.is_relative() {
+                *file = base.join(&file);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.referee.path.is_empty() {
+            bail!("A tournament needs a referee");
+        }
+        if self.bots.len() < 2 {
+            bail!("A tournament needs at least two bots");
+        }
+        let mut names = HashSet::new();
+        for bot in &self.bots {
+            if bot.path.is_empty() || bot.remote.is_some() {
+                bail!("Bot '{}' needs a local command", bot.display_name());
+            }
+            if !names.insert(bot.display_name()) {
+                bail!(
+                    "Two bots are called '{}', give them different names",
+                    bot.display_name()
+                );
+            }
+        }
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.validate()?;
+        }
+        if let Some(handshake) = &self.handshake {
+            handshake.validate()?;
+        }
+        if self.schema.is_some() && self.protocol != Protocol::Json {
+            bail!("A schema only works with the JSON protocol");
+        }
+        if self.games_per_pairing == 0 || self.concurrency == 0 {
+            bail!("Games per pairing and concurrency must be at least 1");
+        }
+        if let Some(gauntlet) = &self.gauntlet {
+            if !names.contains(gauntlet) {
+                bail!("The gauntlet bot '{gauntlet}' isn't one of the bots");
+            }
+        }
+        Ok(())
+    }
+
+    // The match a game is played as
+    pub(crate) fn game_config(&self, game: &Game) -> MatchConfig {
+        let bots = &self.bots;
+        let match_dir = self.dir.as_ref().map(|dir| {
+            dir.join(format!(
+                "game{:03}-{}-vs-{}",
+                game.number,
+                bots[game.seats[0]].display_name(),
+                bots[game.seats[1]].display_name()
+            ))
+        });
+        MatchConfig {
+            id: Some(format!("game{}", game.number)),
+            delim: self.delim,
+            routing: self.routing,
+            framing: self.framing,
+            protocol: self.protocol,
+            schema: self.schema.clone(),
+            time_control: self.time_control,
+            match_dir,
+            manager: self.referee.clone(),
+            players: game.seats.iter().map(|&bot| bots[bot].clone()).collect(),
+            sandbox: self.sandbox.clone(),
+            handshake: self.handshake.clone(),
+            restarts: self.restarts,
+            ..Default::default()
+        }
+    }
+
+    fn gauntlet_bot(&self) -> usize {
+        self.gauntlet
+            .as_ref()
+            .and_then(|name| self.bots.iter().position(|bot| &bot.display_name() == name))
+            .unwrap_or(0)
+    }
+
+    // Swiss needs about log2(bots) rounds to separate the field
+    fn swiss_rounds(&self) -> usize {
+        self.rounds
+            .unwrap_or_else(|| self.bots.len().next_power_of_two().trailing_zeros() as usize)
+            .max(1)
+    }
+}
+
+// One game, bots are indices into TournamentConfig::bots
+#[derive(Clone, Copy, Debug)]
+pub struct Game {
+    pub number: usize,
+    pub round: usize,
+    // seats[0] plays as player 0
+    pub seats: [usize; 2],
+}
+
+// A finished game. result is None if the game failed or the referee never reported one
+#[derive(Clone, Debug)]
+pub struct GameRecord {
+    pub game: Game,
+    pub names: [String; 2],
+    pub result: Option<MatchResult>,
+    // What each seat used, empty if the game never got going
+    pub usage: Vec<ProcessUsage>,
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Game {} (round {}): {} vs {}: ",
+            self.game.number, self.game.round, self.names[0], self.names[1]
+        )?;
+        match &self.result {
+            Some(result) => {
+                write!(f, "{}-{}", result.scores[0], result.scores[1])?;
+                if let Some(reason) = &result.reason {
+                    write!(f, " ({reason})")?;
+                }
+                Ok(())
+            }
+            None => write!(f, "no result"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Standing {
+    pub name: String,
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    // Sum of the scores the referee gave, byes count as winning every game of the pairing
+    pub points: f64,
+    // Games that crashed or ended without a result, they aren't scored
+    pub failed: usize,
+    pub byes: usize,
+    // CPU time over every game with a known usage, and how many games that was
+    pub cpu_ms: u64,
+    pub cpu_games: usize,
+    // Highest peak RSS in any game
+    pub peak_rss_kb: u64,
+    bye_points: f64,
+    opponents: HashSet<usize>,
+}
+
+impl Standing {
+    // Share of the points available in games actually played
+    fn score_percent(&self) -> f64 {
+        match self.games {
+            0 => 0.0,
+            games => 100.0 * (self.points - self.bye_points) / games as f64,
+        }
+    }
+}
+
+// Everyone's standing, in the order printed: most points first
+#[derive(Clone, Debug)]
+pub struct Standings(pub Vec<Standing>);
+
+impl fmt::Display for Standings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|standing| standing.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        writeln!(
+            f,
+            "{:>4}  {:<width$}  {:>5}  {:>4}  {:>5}  {:>6}  {:>6}  {:>6}  {:>6}  {:>8}  {:>8}",
+            "Rank",
+            "Bot",
+            "Games",
+            "Wins",
+            "Draws",
+            "Losses",
+            "Failed",
+            "Points",
+            "Score",
+            "CPU/game",
+            "Peak RSS"
+        )?;
+        for (rank, standing) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4}  {:<width$}  {:>5}  {:>4}  {:>5}  {:>6}  {:>6}  {:>6.1}  {:>5.1}%  {:>7.2}s  {:>5.1}MiB",
+                rank + 1,
+                standing.name,
+                standing.games,
+                standing.wins,
+                standing.draws,
+                standing.losses,
+                standing.failed,
+                standing.points,
+                standing.score_percent(),
+                standing.cpu_ms as f64 / 1000.0 / standing.cpu_games.max(1) as f64,
+                standing.peak_rss_kb as f64 / 1024.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Tournament<'a> {
+    config: &'a TournamentConfig,
+    standings: Vec<Standing>,
+    played: usize,
+}
+
+impl Tournament<'_> {
+    // Bot indices from most to fewest points, ties keep the order bots were given in
+    fn ranking(&self) -> Vec<usize> {
+        let mut ranking = (0..self.standings.len()).collect::<Vec<_>>();
+        ranking.sort_by(|&a, &b| {
+            self.standings[b]
+                .points
+                .total_cmp(&self.standings[a].points)
+        });
+        ranking
+    }
+
+    // Every game of a pairing, swapping seats each game so nobody always moves first
+    fn games_for(&mut self, round: usize, pairings: &[(usize, usize)]) -> Vec<Game> {
+        let mut games = Vec::new();
+        for &(a, b) in pairings {
+            for idx in 0..self.config.games_per_pairing {
+                self.played += 1;
+                games.push(Game {
+                    number: self.played,
+                    round,
+                    seats: if idx % 2 == 0 { [a, b] } else { [b, a] },
+                });
+            }
+        }
+        games
+    }
+
+    // Pair neighbours in the ranking, skipping rematches when there's another option
+    // With an odd number of bots the lowest ranked one without a bye yet sits out
+    fn swiss_pairings(&mut self) -> Vec<(usize, usize)> {
+        let mut unpaired = self.ranking();
+        if unpaired.len() % 2 == 1 {
+            let bye = unpaired
+                .iter()
+                .rposition(|&bot| self.standings[bot].byes == 0)
+                .unwrap_or(unpaired.len() - 1);
+            let bot = unpaired.remove(bye);
+            info!("{} gets a bye", self.standings[bot].name);
+            let standing = &mut self.standings[bot];
+            standing.byes += 1;
+            standing.bye_points += self.config.games_per_pairing as f64;
+            standing.points += self.config.games_per_pairing as f64;
+        }
+        let mut pairings = Vec::new();
+        while !unpaired.is_empty() {
+            let bot = unpaired.remove(0);
+            let opponent = unpaired
+                .iter()
+                .position(|other| !self.standings[bot].opponents.contains(other))
+                .unwrap_or(0);
+            pairings.push((bot, unpaired.remove(opponent)));
+        }
+        pairings
+    }
+
+    fn record(&mut self, record: &GameRecord) {
+        for (&bot, usage) in record.game.seats.iter().zip(&record.usage) {
+            let standing = &mut self.standings[bot];
+            if let Some(cpu_ms) = usage.cpu_ms {
+                standing.cpu_ms += cpu_ms;
+                standing.cpu_games += 1;
+            }
+            standing.peak_rss_kb = standing.peak_rss_kb.max(usage.peak_rss_kb.unwrap_or(0));
+        }
+        let [first, second] = record.game.seats;
+        self.standings[first].opponents.insert(second);
+        self.standings[second].opponents.insert(first);
+        let result = match &record.result {
+            Some(result) => result,
+            None => {
+                self.standings[first].failed += 1;
+                self.standings[second].failed += 1;
+                return;
+            }
+        };
+        for (seat, &bot) in record.game.seats.iter().enumerate() {
+            let (mine, theirs) = (result.scores[seat], result.scores[1 - seat]);
+            let standing = &mut self.standings[bot];
+            standing.games += 1;
+            standing.points += mine;
+            if mine > theirs {
+                standing.wins += 1;
+            } else if mine < theirs {
+                standing.losses += 1;
+            } else {
+                standing.draws += 1;
+            }
+        }
+    }
+
+    // Run games, at most config.concurrency at a time, and score them as they finish
+    async fn play(&mut self, games: Vec<Game>, on_game: &mut impl FnMut(&GameRecord)) {
+        let configs = games
+            .into_iter()
+            .map(|game| (game, self.config.game_config(&game)))
+            .collect::<Vec<_>>();
+        let mut finished = stream::iter(configs)
+            .map(|(game, config)| async move { (game, play_game(game, config).await) })
+            .buffer_unordered(self.config.concurrency);
+        while let Some((game, (result, usage))) = finished.next().await {
+            let record = GameRecord {
+                game,
+                names: game.seats.map(|bot| self.standings[bot].name.clone()),
+                result,
+                usage,
+            };
+            self.record(&record);
+            on_game(&record);
+        }
+    }
+}
+
+// Play one game. The result is None if it failed or the referee never reported one
+pub(crate) async fn play_game(
+    game: Game,
+    config: MatchConfig,
+) -> (Option<MatchResult>, Vec<ProcessUsage>) {
+    let report = Match::run_config(&config).await;
+    match report {
+        Ok(report) => {
+            if report.result.is_none() {
+                warn!("Game {}: the referee never reported a result", game.number);
+            }
+            (report.result, report.players)
+        }
+        Err(err) => {
+            warn!("Game {} failed: {err:#}", game.number);
+            (None, Vec::new())
+        }
+    }
+}
+
+// Play every game of the tournament, calling on_game as each one finishes
+pub async fn run_tournament(
+    config: &TournamentConfig,
+    mut on_game: impl FnMut(&GameRecord),
+) -> Result<Standings> {
+    config.validate()?;
+    if let Some(dir) = &config.dir {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut tournament = Tournament {
+        config,
+        standings: config
+            .bots
+            .iter()
+            .map(|bot| Standing {
+                name: bot.display_name(),
+                ..Default::default()
+            })
+            .collect(),
+        played: 0,
+    };
+    let num_bots = config.bots.len();
+    match config.format {
+        Format::RoundRobin => {
+            let pairings = (0..num_bots)
+                .flat_map(|a| (a + 1..num_bots).map(move |b| (a, b)))
+                .collect::<Vec<_>>();
+            let games = tournament.games_for(1, &pairings);
+            tournament.play(games, &mut on_game).await;
+        }
+        Format::Gauntlet => {
+            let champion = config.gauntlet_bot();
+            let pairings = (0..num_bots)
+                .filter(|&bot| bot != champion)
+                .map(|bot| (champion, bot))
+                .collect::<Vec<_>>();
+            let games = tournament.games_for(1, &pairings);
+            tournament.play(games, &mut on_game).await;
+        }
+        Format::Swiss => {
+            // Each round is paired from the results of the ones before it
+            for round in 1..=config.swiss_rounds() {
+                let pairings = tournament.swiss_pairings();
+                let games = tournament.games_for(round, &pairings);
+                tournament.play(games, &mut on_game).await;
+            }
+        }
+    }
+    let ranking = tournament.ranking();
+    let mut standings = tournament
+        .standings
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<_>>();
+    Ok(Standings(
+        ranking
+            .into_iter()
+            .map(|bot| standings[bot].take().unwrap())
+            .collect(),
+    ))
+}
@@ -0,0 +1,169 @@
+use crate::spectators::Spectators;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    ManagerToPlayer,
+    PlayerToManager,
+}
+
+impl Direction {
+    // How it's serialized, also used as a log field
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::ManagerToPlayer => "manager_to_player",
+            Direction::PlayerToManager => "player_to_manager",
+        }
+    }
+}
+
+// One routed message, written as a single JSON line
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    // Order in which the metamanager routed messages, starting at 0
+    pub seq: u64,
+    // Milliseconds since the unix epoch
+    pub timestamp_ms: u64,
+    pub direction: Direction,
+    // The player the message went to or came from
+    pub player: usize,
+    // The message without its tag or framing, as text. Lossy if it isn't UTF-8, see
+    // payload_b64
+    pub payload: String,
+    // The exact message, for payloads that aren't UTF-8 text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_b64: Option<String>,
+    // For player messages, how long the player took since its last message from the manager
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+}
+
+impl TranscriptEntry {
+    // The message exactly as it was routed
+    pub fn payload_bytes(&self) -> Result<Vec<u8>> {
+        match &self.payload_b64 {
+            Some(encoded) => BASE64
+                .decode(encoded)
+                .with_context(|| format!("Invalid base64 payload in message {}", self.seq)),
+            None => Ok(self.payload.clone().into_bytes()),
+        }
+    }
+}
+
+struct TranscriptWriter {
+    writer: Option<BufWriter<File>>,
+    spectators: Option<Spectators>,
+    next_seq: u64,
+}
+
+// Handle shared by every routing task, recording does nothing if there's no file
+// and nobody spectating
+#[derive(Clone, Default)]
+pub struct Transcript {
+    inner: Option<Arc<Mutex<TranscriptWriter>>>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Transcript {
+    pub async fn create(path: &Path) -> Result<Transcript> {
+        let file = File::create(path)
+            .await
+            .with_context(|| format!("Could not create transcript {}", path.display()))?;
+        Ok(Transcript {
+            inner: Some(Arc::new(Mutex::new(TranscriptWriter {
+                writer: Some(BufWriter::new(file)),
+                spectators: None,
+                next_seq: 0,
+            }))),
+        })
+    }
+
+    // Also show every entry to spectators, as the same JSON lines the file gets
+    pub async fn spectated_by(self, spectators: Spectators) -> Transcript {
+        let inner = self.inner.unwrap_or_else(|| {
+            Arc::new(Mutex::new(TranscriptWriter {
+                writer: None,
+                spectators: None,
+                next_seq: 0,
+            }))
+        });
+        inner.lock().await.spectators = Some(spectators);
+        Transcript { inner: Some(inner) }
+    }
+
+    // Append a message to the transcript. Flushed right away so a crash still leaves
+    // a transcript of everything up to it
+    pub async fn record(
+        &self,
+        direction: Direction,
+        player: usize,
+        payload: &[u8],
+        elapsed_ms: Option<u64>,
+    ) -> Result<()> {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+        let mut transcript = inner.lock().await;
+        let entry = TranscriptEntry {
+            seq: transcript.next_seq,
+            timestamp_ms: now_ms(),
+            direction,
+            player,
+            payload: String::from_utf8_lossy(payload).into_owned(),
+            payload_b64: std::str::from_utf8(payload)
+                .is_err()
+                .then(|| BASE64.encode(payload)),
+            elapsed_ms,
+        };
+        transcript.next_seq += 1;
+        if let Some(spectators) = &transcript.spectators {
+            spectators.routed(&entry)?;
+        }
+        if let Some(writer) = &mut transcript.writer {
+            let mut line = serde_json::to_string(&entry)?;
+            line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+// Read back every entry of a transcript file, in the order they were routed
+pub fn read_transcript(path: &Path) -> Result<Vec<TranscriptEntry>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read transcript {}", path.display()))?;
+    let mut entries = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            serde_json::from_str::<TranscriptEntry>(line).with_context(|| {
+                format!(
+                    "Invalid transcript entry on line {} of {}",
+                    idx + 1,
+                    path.display()
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.seq);
+    Ok(entries)
+}
@@ -0,0 +1,184 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    BufWriter,
+};
+
+pub type BoxedReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxedWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+// Anything a participant can be reached through: a child's stdio, a socket, or an
+// in-memory tokio::io::duplex for tests. Reads are the participant's messages, writes
+// are messages to it, framed as the match's Framing says. The router reads and writes from different tasks,
+// so a transport is used as separate halves. When the router is done with the writer it
+// shuts it down and drops it, either of which has to show up as EOF on the other side
+pub trait Transport: Send + 'static {
+    fn into_halves(self: Box<Self>) -> (BoxedReader, BoxedWriter);
+}
+
+// Any buffered bidirectional stream works, e.g. BufReader<TcpStream> or BufReader<DuplexStream>
+impl<T> Transport for T
+where
+    T: AsyncBufRead + AsyncWrite + Send + Unpin + 'static,
+{
+    fn into_halves(self: Box<Self>) -> (BoxedReader, BoxedWriter) {
+        let (reader, writer) = tokio::io::split(*self);
+        (Box::new(reader), Box::new(writer))
+    }
+}
+
+pub type BoxedTransport = Box<dyn Transport>;
+
+// Largest message accepted, so a broken player can't make the metamanager allocate
+// whatever it likes
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+
+// How messages are told apart on the wire, the same for everyone in a match
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Framing {
+    // One UTF-8 message per line
+    #[default]
+    Lines,
+    // A 4 byte big-endian length, then that many bytes of anything, newlines and delims
+    // included
+    Length,
+}
+
+impl Framing {
+    // As given to --framing, also how local processes are told, see FRAMING_VAR
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Framing::Lines => "lines",
+            Framing::Length => "length",
+        }
+    }
+}
+
+// Reads whole messages, without their framing
+pub(crate) struct MessageReader {
+    reader: BufReader<BoxedReader>,
+    framing: Framing,
+}
+
+impl MessageReader {
+    // The next message, None once the other side closed
+    pub(crate) async fn next_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.framing {
+            Framing::Lines => {
+                // Capped like frames, a line never ending can't take all our memory
+                let mut line = Vec::new();
+                let mut capped = (&mut self.reader).take(MAX_FRAME_LEN as u64 + 1);
+                if capped.read_until(b'\n', &mut line).await? == 0 {
+                    return Ok(None);
+                }
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                }
+                if line.len() > MAX_FRAME_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line is over the {MAX_FRAME_LEN} byte limit"),
+                    ));
+                }
+                // Lines have always had to be text
+                std::str::from_utf8(&line)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Ok(Some(line))
+            }
+            Framing::Length => {
+                // Closing between messages is the end, closing in the middle of a length
+                // is a broken frame
+                let mut len = [0; 4];
+                let mut filled = 0;
+                while filled < len.len() {
+                    match self.reader.read(&mut len[filled..]).await? {
+                        0 if filled == 0 => return Ok(None),
+                        0 => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("closed {filled} bytes into a 4 byte frame length"),
+                            ))
+                        }
+                        read => filled += read,
+                    }
+                }
+                let len = u32::from_be_bytes(len) as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{len} byte message is over the {MAX_FRAME_LEN} byte limit"),
+                    ));
+                }
+                let mut message = vec![0; len];
+                self.reader.read_exact(&mut message).await?;
+                Ok(Some(message))
+            }
+        }
+    }
+
+    // The next message as text, for what's text in either framing like the handshake
+    pub(crate) async fn next_line(&mut self) -> io::Result<Option<String>> {
+        match self.next_message().await? {
+            Some(message) => String::from_utf8(message)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Writes whole messages, framing them
+pub(crate) struct MessageWriter {
+    writer: BufWriter<BoxedWriter>,
+    framing: Framing,
+}
+
+impl MessageWriter {
+    // Frame message and flush it
+    pub(crate) async fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self.framing {
+            Framing::Lines => {
+                self.writer.write_all(message).await?;
+                self.writer.write_all(b"\n").await?;
+            }
+            Framing::Length => {
+                let len = u32::try_from(message.len())
+                    .ok()
+                    .filter(|&len| len as usize <= MAX_FRAME_LEN)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "message is too long")
+                    })?;
+                self.writer.write_all(&len.to_be_bytes()).await?;
+                self.writer.write_all(message).await?;
+            }
+        }
+        self.writer.flush().await
+    }
+
+    pub(crate) async fn shutdown(&mut self) -> io::Result<()> {
+        self.writer.shutdown().await
+    }
+}
+
+pub(crate) fn split_transport(
+    transport: BoxedTransport,
+    framing: Framing,
+) -> (MessageReader, MessageWriter) {
+    let (reader, writer) = transport.into_halves();
+    (
+        MessageReader {
+            reader: BufReader::new(reader),
+            framing,
+        },
+        MessageWriter {
+            writer: BufWriter::new(writer),
+            framing,
+        },
+    )
+}
@@ -0,0 +1,314 @@
+use crate::transport::{BoxedReader, BoxedTransport, BoxedWriter, Transport};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::pin::Pin;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::Child;
+use tokio::sync::watch;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+// What one participant used over a match. CPU and memory are only known for local processes
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ProcessUsage {
+    // User plus system time, including children the process waited for
+    pub cpu_ms: Option<u64>,
+    // Largest resident set of the process itself
+    pub peak_rss_kb: Option<u64>,
+    // Bytes it wrote to the metamanager, and the metamanager wrote to it
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{bytes} B"),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+impl fmt::Display for ProcessUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(cpu_ms) = self.cpu_ms {
+            write!(f, "cpu {:.2}s, ", cpu_ms as f64 / 1000.0)?;
+        }
+        if let Some(peak_rss_kb) = self.peak_rss_kb {
+            write!(f, "peak rss {}, ", format_bytes(peak_rss_kb * 1024))?;
+        }
+        write!(
+            f,
+            "sent {}, received {}",
+            format_bytes(self.bytes_sent),
+            format_bytes(self.bytes_received)
+        )
+    }
+}
+
+// Bytes going through a transport, counted as the router reads and writes them
+#[derive(Clone, Default)]
+pub(crate) struct ByteCounts {
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+struct Counted<T> {
+    inner: T,
+    count: Arc<AtomicU64>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Counted<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Counted<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.count.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+struct CountedTransport {
+    inner: BoxedTransport,
+    counts: ByteCounts,
+}
+
+impl Transport for CountedTransport {
+    fn into_halves(self: Box<Self>) -> (BoxedReader, BoxedWriter) {
+        let (reader, writer) = self.inner.into_halves();
+        (
+            Box::new(Counted {
+                inner: reader,
+                count: self.counts.sent,
+            }),
+            Box::new(Counted {
+                inner: writer,
+                count: self.counts.received,
+            }),
+        )
+    }
+}
+
+impl ByteCounts {
+    pub(crate) fn count(&self, transport: BoxedTransport) -> BoxedTransport {
+        Box::new(CountedTransport {
+            inner: transport,
+            counts: self.clone(),
+        })
+    }
+
+    fn usage(&self) -> ProcessUsage {
+        ProcessUsage {
+            bytes_sent: self.sent.load(Ordering::Relaxed),
+            bytes_received: self.received.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Sample {
+    pub(crate) cpu_ms: Option<u64>,
+    pub(crate) peak_rss_kb: Option<u64>,
+    // Largest the address space got, what a sandbox memory limit caps
+    pub(crate) peak_vm_kb: Option<u64>,
+}
+
+// CPU time and peak memory of a local process, sampled from /proc until it exits.
+// The last sample is taken once the process is a zombie and before anyone reaps it, so the
+// CPU time is final. Memory is gone by then, so the peak is only as recent as the sample
+// before that. A restarted player's processes are all tracked here, one after the other
+#[derive(Clone)]
+pub(crate) struct ProcessStats {
+    // One per process tracked so far, the latest last
+    processes: Arc<Mutex<Vec<Sample>>>,
+    // How many of them have exited
+    exited: Arc<watch::Sender<usize>>,
+}
+
+// Whether pid has exited, without reaping it. A pid we can't wait on any more counts too
+fn has_exited(pid: u32) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    if unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) } != 0 {
+        return true;
+    }
+    unsafe { info.si_pid() != 0 }
+}
+
+// A kB field of /proc/<pid>/status
+fn status_kb(status: &str, field: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+// From /proc/<pid>/stat and /proc/<pid>/status
+fn read_sample(pid: u32) -> Sample {
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    // The command name can contain anything, so fields are counted from its closing paren.
+    // utime, stime, cutime and cstime are fields 14 to 17, the state (field 3) comes first
+    let cpu_ms = std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .ok()
+        .and_then(|stat| {
+            let fields = stat
+                .rsplit_once(')')?
+                .1
+                .split_whitespace()
+                .collect::<Vec<_>>();
+            let ticks = fields
+                .get(11..15)?
+                .iter()
+                .map(|field| field.parse::<u64>().ok())
+                .sum::<Option<u64>>()?;
+            Some(ticks * 1000 / ticks_per_second)
+        });
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).unwrap_or_default();
+    Sample {
+        cpu_ms,
+        peak_rss_kb: status_kb(&status, "VmHWM"),
+        peak_vm_kb: status_kb(&status, "VmPeak"),
+    }
+}
+
+impl ProcessStats {
+    pub(crate) fn track(pid: u32) -> ProcessStats {
+        let stats = ProcessStats {
+            processes: Arc::default(),
+            exited: Arc::new(watch::Sender::new(0)),
+        };
+        stats.track_next(pid);
+        stats
+    }
+
+    // Start tracking the process that took over from the previous one
+    pub(crate) fn track_next(&self, pid: u32) {
+        let processes = self.processes.clone();
+        let idx = {
+            let mut processes = processes.lock().unwrap();
+            processes.push(Sample::default());
+            processes.len() - 1
+        };
+        let exited_count = self.exited.clone();
+        tokio::spawn(async move {
+            loop {
+                let exited = has_exited(pid);
+                let latest = read_sample(pid);
+                {
+                    let sample = &mut processes.lock().unwrap()[idx];
+                    sample.cpu_ms = latest.cpu_ms.or(sample.cpu_ms);
+                    sample.peak_rss_kb = latest.peak_rss_kb.max(sample.peak_rss_kb);
+                    sample.peak_vm_kb = latest.peak_vm_kb.max(sample.peak_vm_kb);
+                }
+                if exited {
+                    break;
+                }
+                tokio::time::sleep(SAMPLE_INTERVAL).await;
+            }
+            exited_count.send_modify(|exited| *exited += 1);
+        });
+    }
+
+    // Resolves once every tracked process has exited and its last sample is in.
+    // Only then may the latest process be reaped
+    pub(crate) async fn exited(&self) {
+        let tracked = self.processes.lock().unwrap().len();
+        let mut exited = self.exited.subscribe();
+        let _ = exited.wait_for(|exited| *exited >= tracked).await;
+    }
+
+    // What has been measured of the latest process, final once it has exited
+    pub(crate) fn latest(&self) -> Sample {
+        self.processes
+            .lock()
+            .unwrap()
+            .last()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // CPU time adds up over every process, memory is the highest any of them reached
+    fn usage(&self, counts: &ByteCounts) -> ProcessUsage {
+        let processes = self.processes.lock().unwrap();
+        let cpu_ms = processes
+            .iter()
+            .filter_map(|sample| sample.cpu_ms)
+            .reduce(|total, cpu_ms| total + cpu_ms);
+        ProcessUsage {
+            cpu_ms,
+            peak_rss_kb: processes
+                .iter()
+                .filter_map(|sample| sample.peak_rss_kb)
+                .max(),
+            ..counts.usage()
+        }
+    }
+}
+
+// Reaping a process loses its last usage sample, so let that be taken first
+pub(crate) async fn wait_for_exit(
+    child: &mut Child,
+    stats: Option<&ProcessStats>,
+) -> std::io::Result<ExitStatus> {
+    if let Some(stats) = stats {
+        stats.exited().await;
+    }
+    child.wait().await
+}
+
+// Usage of everyone in a match. Processes get a moment to exit after the match so their
+// final CPU time is known
+pub(crate) async fn collect_usage(
+    participants: &[(ByteCounts, Option<ProcessStats>)],
+    grace: Duration,
+) -> Vec<ProcessUsage> {
+    let all_exited = futures::future::join_all(
+        participants
+            .iter()
+            .filter_map(|(_, stats)| stats.as_ref())
+            .map(|stats| stats.exited()),
+    );
+    let _ = tokio::time::timeout(grace, all_exited).await;
+    participants
+        .iter()
+        .map(|(counts, stats)| match stats {
+            Some(stats) => stats.usage(counts),
+            None => counts.usage(),
+        })
+        .collect()
+}
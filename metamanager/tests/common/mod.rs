@@ -0,0 +1,181 @@
+// Shared harness for metamanager integration tests: participants are played by the test
+// itself through in-memory pipes, so every line in and out can be scripted and checked
+#![allow(dead_code)]
+
+use anyhow::Result;
+use metamanager::report::MatchReport;
+use metamanager::{Framing, Match, MatchBuilder, Routing, Transport};
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{
+    duplex, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf,
+    WriteHalf,
+};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+pub const ROUTINGS: [Routing; 2] = [Routing::Direct, Routing::Channels];
+
+// Long enough for a slow CI machine, short enough that a hang fails the test quickly
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Fail the test instead of hanging forever
+pub async fn within<F: Future>(what: &str, future: F) -> F::Output {
+    timeout(TIMEOUT, future)
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting for {what}"))
+}
+
+// The test's end of a participant's pipe
+pub struct Fake {
+    name: String,
+    framing: Framing,
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: Option<WriteHalf<DuplexStream>>,
+}
+
+impl Fake {
+    // Write bytes as the participant, without any framing
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        let writer = self.writer.as_mut().expect("send after close");
+        writer.write_all(bytes).await.unwrap();
+        writer.flush().await.unwrap();
+    }
+
+    // Write one message as the participant, framed however the match expects
+    pub async fn send_bytes(&mut self, message: &[u8]) {
+        let framed = match self.framing {
+            Framing::Lines => [message, b"\n"].concat(),
+            Framing::Length => [&(message.len() as u32).to_be_bytes(), message].concat(),
+        };
+        self.send_raw(&framed).await;
+    }
+
+    // Write one line as the participant
+    pub async fn send(&mut self, line: &str) {
+        self.send_bytes(line.as_bytes()).await;
+    }
+
+    // Next message the metamanager sent the participant, None once it closed the pipe
+    pub async fn recv_bytes(&mut self) -> Option<Vec<u8>> {
+        let what = format!("a message for {}", self.name);
+        let reader = &mut self.reader;
+        let framing = self.framing;
+        within(&what, async move {
+            let mut message = Vec::new();
+            match framing {
+                Framing::Lines => {
+                    if reader.read_until(b'\n', &mut message).await.unwrap() == 0 {
+                        return None;
+                    }
+                    assert_eq!(message.pop(), Some(b'\n'), "unterminated line");
+                }
+                Framing::Length => {
+                    let mut len = [0; 4];
+                    if reader.read_exact(&mut len).await.is_err() {
+                        return None;
+                    }
+                    message.resize(u32::from_be_bytes(len) as usize, 0);
+                    reader.read_exact(&mut message).await.unwrap();
+                }
+            }
+            Some(message)
+        })
+        .await
+    }
+
+    // Next line the metamanager sent the participant, None once it closed the pipe
+    pub async fn recv(&mut self) -> Option<String> {
+        self.recv_bytes()
+            .await
+            .map(|line| String::from_utf8(line).unwrap())
+    }
+
+    pub async fn expect(&mut self, expected: &str) {
+        assert_eq!(
+            self.recv().await.as_deref(),
+            Some(expected),
+            "{}",
+            self.name
+        );
+    }
+
+    pub async fn expect_eof(&mut self) {
+        assert_eq!(self.recv().await, None, "{} expected EOF", self.name);
+    }
+
+    // Drop both ends of the pipe, like a process that crashed: writes to it fail from now on
+    pub fn hang_up(&mut self) {
+        let (dead, _) = framed_fake(&self.name, self.framing);
+        *self = dead;
+    }
+
+    // Close the participant's output, like a process exiting
+    pub async fn close(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            writer.shutdown().await.unwrap();
+        }
+    }
+}
+
+pub fn fake(name: &str) -> (Fake, impl Transport) {
+    framed_fake(name, Framing::Lines)
+}
+
+pub fn framed_fake(name: &str, framing: Framing) -> (Fake, impl Transport) {
+    let (ours, theirs) = duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(ours);
+    (
+        Fake {
+            name: name.to_string(),
+            framing,
+            reader: BufReader::new(reader),
+            writer: Some(writer),
+        },
+        BufReader::new(theirs),
+    )
+}
+
+pub struct Running {
+    pub manager: Fake,
+    pub players: Vec<Fake>,
+    pub handle: JoinHandle<Result<MatchReport>>,
+}
+
+impl Running {
+    // Close everything that's still open and wait for the match to finish
+    pub async fn finish(mut self) -> Result<MatchReport> {
+        self.manager.close().await;
+        for player in self.players.iter_mut() {
+            player.close().await;
+        }
+        within("the match to finish", self.handle).await.unwrap()
+    }
+}
+
+// Start a match with fake participants on top of whatever builder settings a test wants
+pub fn start_with(builder: MatchBuilder, num_players: usize) -> Running {
+    start_framed(builder, Framing::Lines, num_players)
+}
+
+// Same, for a match with framing
+pub fn start_framed(builder: MatchBuilder, framing: Framing, num_players: usize) -> Running {
+    let (manager, manager_transport) = framed_fake("manager", framing);
+    let mut builder = builder.framing(framing).manager(manager_transport);
+    let mut players = Vec::new();
+    for idx in 0..num_players {
+        let (player, transport) = framed_fake(&format!("player{idx}"), framing);
+        builder = builder.player(transport);
+        players.push(player);
+    }
+    let game = builder.build().unwrap();
+    Running {
+        manager,
+        players,
+        handle: tokio::spawn(game.run()),
+    }
+}
+
+pub fn start(routing: Routing, num_players: usize) -> Running {
+    start_with(Match::builder().routing(routing), num_players)
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{framed_fake, start_framed, start_with, ROUTINGS};
+use metamanager::replay::{replay, RecordedMatch};
+use metamanager::spectators::SpectatorFeed;
+use metamanager::transcript::{read_transcript, Transcript};
+use metamanager::transport::MAX_FRAME_LEN;
+use metamanager::{Framing, Match, ProcessConfig};
+use std::time::Duration;
+
+// Newlines, delims and bytes that aren't UTF-8, none of which survive line framing
+const PAYLOAD: &[u8] = b"a:b\nc\r\n\xff\x00";
+
+fn tagged(tag: &str, payload: &[u8]) -> Vec<u8> {
+    [format!("{tag}:").as_bytes(), payload].concat()
+}
+
+#[tokio::test]
+async fn binary_payloads_round_trip() {
+    for routing in ROUTINGS {
+        let mut game = start_framed(Match::builder().routing(routing), Framing::Length, 2);
+        game.manager.send_bytes(&tagged("1", PAYLOAD)).await;
+        assert_eq!(game.players[1].recv_bytes().await.unwrap(), PAYLOAD);
+        game.players[0].send_bytes(PAYLOAD).await;
+        assert_eq!(
+            game.manager.recv_bytes().await.unwrap(),
+            tagged("0", PAYLOAD)
+        );
+        // Empty messages are messages too
+        game.manager.send_bytes(b"*:").await;
+        assert_eq!(game.players[0].recv_bytes().await.unwrap(), b"");
+        assert_eq!(game.players[1].recv_bytes().await.unwrap(), b"");
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn control_messages_are_frames_too() {
+    for routing in ROUTINGS {
+        let builder = Match::builder().routing(routing).report_times(true);
+        let mut game = start_framed(builder, Framing::Length, 1);
+        game.manager.send("0:your move").await;
+        game.players[0].expect("your move").await;
+        game.players[0].send_bytes(PAYLOAD).await;
+        assert_eq!(
+            game.manager.recv_bytes().await.unwrap(),
+            tagged("0", PAYLOAD)
+        );
+        let time = game.manager.recv().await.unwrap();
+        assert!(time.starts_with("mm:time 0 "), "{time}");
+        game.manager.send("mm:result 1 framed").await;
+        let result = game.finish().await.unwrap().result.unwrap();
+        assert_eq!(result.scores, [1.0]);
+        assert_eq!(result.reason.as_deref(), Some("framed"));
+    }
+}
+
+#[tokio::test]
+async fn oversized_frames_fail_the_match() {
+    let mut game = start_framed(Match::builder(), Framing::Length, 1);
+    // Only the length is sent, a 4 GiB message would never be read anyway
+    game.players[0].send_raw(&u32::MAX.to_be_bytes()).await;
+    let err = game.finish().await.unwrap_err();
+    assert!(format!("{err:#}").contains("limit"), "{err:#}");
+}
+
+#[tokio::test]
+async fn frames_cut_off_in_their_length_fail_the_match() {
+    let mut game = start_framed(Match::builder(), Framing::Length, 1);
+    game.players[0].send_raw(&[0, 0]).await;
+    let err = game.finish().await.unwrap_err();
+    assert!(format!("{err:#}").contains("frame length"), "{err:#}");
+}
+
+#[tokio::test]
+async fn overlong_lines_fail_the_match() {
+    let mut game = start_with(Match::builder(), 1);
+    game.players[0]
+        .send_raw(&vec![b'a'; MAX_FRAME_LEN + 1])
+        .await;
+    let err = game.finish().await.unwrap_err();
+    assert!(format!("{err:#}").contains("limit"), "{err:#}");
+}
+
+#[tokio::test]
+async fn binary_payloads_are_recorded_replayed_and_spectated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("transcript.jsonl");
+    let (mut spectator, transport) = framed_fake("spectator", Framing::Length);
+    let builder = Match::builder()
+        .transcript(Transcript::create(&path).await.unwrap())
+        .spectator_feed(SpectatorFeed::Public)
+        .spectator(transport);
+    let mut game = start_framed(builder, Framing::Length, 1);
+    game.manager.send_bytes(&tagged("0", PAYLOAD)).await;
+    assert_eq!(game.players[0].recv_bytes().await.unwrap(), PAYLOAD);
+    game.players[0].send_bytes(PAYLOAD).await;
+    game.manager.recv_bytes().await.unwrap();
+    game.manager.send_bytes(&tagged("spec", PAYLOAD)).await;
+    assert_eq!(spectator.recv_bytes().await.unwrap(), PAYLOAD);
+    spectator.close().await;
+    game.finish().await.unwrap();
+
+    let entries = read_transcript(&path).unwrap();
+    assert_eq!(entries.len(), 2);
+    for entry in &entries {
+        assert!(entry.payload_b64.is_some());
+        assert_eq!(entry.payload_bytes().unwrap(), PAYLOAD);
+    }
+    // Echoing frames back as they come is exactly what the recorded player did
+    let cat = ProcessConfig::from_command_line("cat").unwrap();
+    let recorded = RecordedMatch {
+        framing: Framing::Length,
+        ..Default::default()
+    };
+    let matched = replay(&path, 0, &cat, Duration::from_secs(5), recorded)
+        .await
+        .unwrap();
+    assert_eq!(matched, 1);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::{start_with, ROUTINGS};
+use metamanager::handshake::{Handshake, PlayerHello};
+use metamanager::replay::{replay, RecordedMatch};
+use metamanager::transcript::Transcript;
+use metamanager::{Match, ProcessConfig};
+use std::time::Duration;
+
+#[tokio::test]
+async fn players_are_greeted_and_introduced_to_the_manager() {
+    for routing in ROUTINGS {
+        let builder = Match::builder()
+            .routing(routing)
+            .time_control(Some("60+0.5".parse().unwrap()))
+            .handshake(Some(Handshake::new("tictactoe")));
+        let mut game = start_with(builder, 2);
+        game.players[0]
+            .expect("hello game=tictactoe protocol=1 player=0 players=2 time=60+0.5")
+            .await;
+        game.players[1]
+            .expect("hello game=tictactoe protocol=1 player=1 players=2 time=60+0.5")
+            .await;
+        game.players[1]
+            .send("hello name=second version=2.0 game=tictactoe protocol=1")
+            .await;
+        game.players[0].send("hello name=first version=1").await;
+        game.manager
+            .expect("mm:player 0 name=first version=1")
+            .await;
+        game.manager
+            .expect("mm:player 1 name=second version=2.0")
+            .await;
+        // Then the match goes on as usual
+        game.manager.send("0:go").await;
+        game.players[0].expect("go").await;
+        game.players[0].send("moved").await;
+        game.manager.expect("0:moved").await;
+        let report = game.finish().await.unwrap();
+        let names = report.hellos.iter().map(|hello| hello.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["first", "second"]);
+    }
+}
+
+#[tokio::test]
+async fn the_handshake_is_recorded_and_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("transcript.jsonl");
+    let builder = Match::builder()
+        .transcript(Transcript::create(&path).await.unwrap())
+        .handshake(Some(Handshake::new("tictactoe")));
+    let mut game = start_with(builder, 1);
+    game.players[0]
+        .expect("hello game=tictactoe protocol=1 player=0 players=1")
+        .await;
+    game.players[0].send("hello name=bot version=1").await;
+    game.manager.expect("mm:player 0 name=bot version=1").await;
+    game.manager.send("0:go").await;
+    game.players[0].expect("go").await;
+    game.players[0].send("moved").await;
+    game.manager.expect("0:moved").await;
+    game.manager.send("mm:result 1 moved").await;
+    game.finish().await.unwrap();
+
+    // Only answers once it's been greeted
+    let bot = r#"sh -c 'read hello; echo "hello name=bot version=1"; read go; echo moved'"#;
+    let bot = ProcessConfig::from_command_line(bot).unwrap();
+    let matched = replay(
+        &path,
+        0,
+        &bot,
+        Duration::from_secs(5),
+        RecordedMatch::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(matched, 2);
+}
+
+#[tokio::test]
+async fn mismatched_players_fail_the_match() {
+    let cases = [
+        ("hello name=bot version=1 game=chess", "plays chess"),
+        ("hello name=bot version=1 protocol=2", "speaks protocol 2"),
+        ("error not ready", "refused: not ready"),
+        ("0", "does it speak the handshake?"),
+    ];
+    for (reply, expected) in cases {
+        let builder = Match::builder().handshake(Some(Handshake::new("tictactoe")));
+        let mut game = start_with(builder, 2);
+        game.players[0].recv().await;
+        game.players[1].recv().await;
+        game.players[0].send("hello name=good version=1").await;
+        game.players[1].send(reply).await;
+        let err = format!("{:#}", game.finish().await.unwrap_err());
+        assert!(err.starts_with("Player 1 failed the handshake"), "{err}");
+        assert!(err.contains(expected), "{err}");
+    }
+}
+
+#[tokio::test]
+async fn silent_players_time_out() {
+    let handshake = Handshake {
+        timeout_ms: 100,
+        ..Handshake::new("tictactoe")
+    };
+    let mut game = start_with(Match::builder().handshake(Some(handshake)), 1);
+    game.players[0].recv().await;
+    let err = format!("{:#}", game.handle.await.unwrap().unwrap_err());
+    assert!(
+        err.contains("didn't answer the hello within 100ms"),
+        "{err}"
+    );
+}
+
+#[test]
+fn replies_need_a_name_and_version() {
+    let handshake = Handshake::new("tictactoe");
+    assert_eq!(
+        PlayerHello::parse("hello version=3 name=bot future=key", &handshake).unwrap(),
+        PlayerHello {
+            name: "bot".to_string(),
+            version: "3".to_string()
+        }
+    );
+    assert!(PlayerHello::parse("hello name=bot", &handshake).is_err());
+    assert!(PlayerHello::parse("hello name=bot version", &handshake).is_err());
+    assert!(Handshake::new("tic tac toe").validate().is_err());
+}
@@ -0,0 +1,71 @@
+use log::kv::Value as KvValue;
+use log::{Level, Record};
+use metamanager::logging::{current_match_id, json_line};
+use serde_json::{json, Value};
+
+#[test]
+fn records_become_json_with_their_fields() {
+    let fields = [
+        ("process", KvValue::from(2u64)),
+        ("direction", KvValue::from("player_to_manager")),
+        ("payload", KvValue::from("4")),
+    ];
+    let line = json_line(
+        &Record::builder()
+            .args(format_args!("Message from 1: 4"))
+            .level(Level::Trace)
+            .target("metamanager::routing")
+            .key_values(&fields)
+            .build(),
+    );
+    let mut parsed: Value = serde_json::from_str(&line).unwrap();
+    assert!(parsed["time"].as_str().unwrap().ends_with('Z'), "{line}");
+    parsed.as_object_mut().unwrap().remove("time");
+    // No match is running, so there's no match id
+    assert_eq!(current_match_id(), None);
+    assert_eq!(
+        parsed,
+        json!({
+            "level": "TRACE",
+            "target": "metamanager::routing",
+            "message": "Message from 1: 4",
+            "process": 2,
+            "direction": "player_to_manager",
+            "payload": "4",
+        })
+    );
+}
+
+#[test]
+fn everything_a_match_logs_carries_its_id() {
+    // The spectator's stderr and the players' are echoed from tasks of their own
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_metamanager"))
+        .args(["--log-format", "json", "--log-level", "info"])
+        .args([
+            "--match-id",
+            "logged",
+            "--spectator",
+            "sh -c 'cat > /dev/null; echo bye >&2'",
+        ])
+        .args([
+            r#"sh -c 'echo 0:hi; read answer; echo "mm:result 1"'"#,
+            "sh -c 'read line; echo back; echo oops >&2'",
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    let records = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .collect::<Vec<_>>();
+    assert!(
+        records
+            .iter()
+            .any(|record| record["message"].as_str().unwrap().contains("spectator0")),
+        "{stderr}"
+    );
+    for record in records {
+        assert_eq!(record["match_id"], "logged", "{record}");
+    }
+}
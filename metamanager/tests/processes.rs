@@ -0,0 +1,297 @@
+// End to end runs of the metamanager binary with small sh scripts as participants
+
+use metamanager::manifest::Manifest;
+use metamanager::report::MatchResult;
+use metamanager::transcript::{read_transcript, Direction};
+use metamanager::transport::MAX_FRAME_LEN;
+use metamanager::ProcessConfig;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+// Sends player 0 a ping, waits for the answer and exits
+const MANAGER: &str = r#"sh -c 'echo 0:ping; read answer; echo "manager got $answer" >&2'"#;
+// Answers one message, then exits without waiting for anything else
+const PLAYER: &str = r#"sh -c 'read message; echo "pong $message"; echo "player done" >&2'"#;
+
+fn metamanager(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_metamanager"))
+        .args(args)
+        .output()
+        .expect("metamanager should start")
+}
+
+fn run_match(dir: &Path, routing: &str) -> Output {
+    let output = metamanager(&[
+        "--routing",
+        routing,
+        "--match-dir",
+        dir.to_str().unwrap(),
+        "--transcript",
+        dir.join("transcript.jsonl").to_str().unwrap(),
+        MANAGER,
+        PLAYER,
+    ]);
+    assert!(
+        output.status.success(),
+        "{routing}: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn routes_between_processes_and_exits() {
+    for routing in ["direct", "channels"] {
+        let dir = tempfile::tempdir().unwrap();
+        let output = run_match(dir.path(), routing);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("manager got 0:pong ping"),
+            "{routing}: {stderr}"
+        );
+        assert!(stderr.contains("player done"), "{routing}: {stderr}");
+    }
+}
+
+#[test]
+fn players_see_eof_when_the_manager_exits() {
+    // Keeps answering until its stdin closes, so the match only ends if that happens
+    let echo = r#"sh -c 'while read message; do echo "pong $message"; done'"#;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_metamanager"))
+        .args([MANAGER, echo])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(5) {
+            child.kill().unwrap();
+            panic!("metamanager kept running after the manager exited");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn records_the_transcript_and_stderr_logs() {
+    let dir = tempfile::tempdir().unwrap();
+    run_match(dir.path(), "direct");
+    let entries = read_transcript(&dir.path().join("transcript.jsonl")).unwrap();
+    let messages: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.direction, entry.player, entry.payload.as_str()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (Direction::ManagerToPlayer, 0, "ping"),
+            (Direction::PlayerToManager, 0, "pong ping"),
+        ]
+    );
+    let manager_log = std::fs::read_to_string(dir.path().join("manager.stderr.log")).unwrap();
+    assert_eq!(manager_log.trim(), "manager got 0:pong ping");
+    let player_log = std::fs::read_to_string(dir.path().join("player0.stderr.log")).unwrap();
+    assert_eq!(player_log.trim(), "player done");
+}
+
+#[test]
+fn stderr_that_isnt_utf8_doesnt_fail_the_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let player = r#"sh -c 'printf "bad \377 byte\n" >&2; read message; echo "pong $message"'"#;
+    let output = metamanager(&["--match-dir", dir.path().to_str().unwrap(), MANAGER, player]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("manager got 0:pong ping"), "{stderr}");
+    let player_log = std::fs::read_to_string(dir.path().join("player0.stderr.log")).unwrap();
+    assert_eq!(player_log, "bad \u{fffd} byte\n");
+}
+
+#[test]
+fn endless_stderr_lines_are_split() {
+    let dir = tempfile::tempdir().unwrap();
+    let length = MAX_FRAME_LEN + 10;
+    // Nul bytes turned into 'a's, without a newline
+    let player =
+        format!("sh -c 'head -c {length} /dev/zero | tr -c x a >&2; read message; echo pong'");
+    let output = metamanager(&[
+        "--match-dir",
+        dir.path().to_str().unwrap(),
+        MANAGER,
+        &player,
+    ]);
+    assert!(output.status.success());
+    let player_log = std::fs::read_to_string(dir.path().join("player0.stderr.log")).unwrap();
+    let lengths = player_log.lines().map(str::len).collect::<Vec<_>>();
+    assert_eq!(lengths, [MAX_FRAME_LEN, 10]);
+}
+
+#[test]
+fn writes_a_manifest_describing_the_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let manager =
+        r#"sh -c 'echo "seed $MM_SEED" >&2; echo 0:ping; read answer; echo "mm:result 1 pong"'"#;
+    let output = metamanager(&[
+        "--match-dir",
+        dir.path().to_str().unwrap(),
+        "--seed",
+        "42",
+        manager,
+        PLAYER,
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let manifest = Manifest::read(dir.path()).unwrap();
+    assert_eq!(manifest.seed, 42);
+    assert_eq!(
+        manifest.result,
+        Some(MatchResult {
+            scores: vec![1.0],
+            reason: Some("pong".to_string()),
+        })
+    );
+    assert_eq!(manifest.error, None);
+    let participants: Vec<_> = manifest
+        .participants
+        .iter()
+        .map(|participant| (participant.label.as_str(), participant.command.clone()))
+        .collect();
+    let command = |line| {
+        ProcessConfig::from_command_line(line)
+            .unwrap()
+            .command_line()
+    };
+    assert_eq!(
+        participants,
+        [("manager", command(manager)), ("player0", command(PLAYER))]
+    );
+    // Everything it points at is in the directory
+    let transcript = dir.path().join(manifest.transcript.unwrap());
+    assert_eq!(read_transcript(&transcript).unwrap().len(), 2);
+    let manager_log = &manifest.participants[0].stderr_log.as_ref().unwrap();
+    let manager_log = std::fs::read_to_string(dir.path().join(manager_log)).unwrap();
+    assert_eq!(manager_log.trim(), "seed 42");
+    assert!(manifest.participants[1].usage.is_some());
+}
+
+#[test]
+fn processes_are_told_the_delim_and_framing() {
+    let dir = tempfile::tempdir().unwrap();
+    let manager = r#"sh -c 'echo "$MM_DELIM $MM_FRAMING" >&2; echo "0${MM_DELIM}ping"; read answer; echo "mm${MM_DELIM}result 1 $answer"'"#;
+    let player = r#"sh -c 'echo "$MM_DELIM $MM_FRAMING" >&2; read message; echo pong'"#;
+    let output = metamanager(&[
+        "--match-dir",
+        dir.path().to_str().unwrap(),
+        "--delim",
+        "|",
+        manager,
+        player,
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    let manifest = Manifest::read(dir.path()).unwrap();
+    assert_eq!(manifest.result.unwrap().reason.as_deref(), Some("0|pong"));
+    for log in ["manager.stderr.log", "player0.stderr.log"] {
+        let log = std::fs::read_to_string(dir.path().join(log)).unwrap();
+        assert_eq!(log.trim(), "| lines");
+    }
+}
+
+#[test]
+fn failed_matches_get_a_manifest_too() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = metamanager(&[
+        "--match-dir",
+        dir.path().to_str().unwrap(),
+        "--handshake",
+        "ping",
+        MANAGER,
+        PLAYER,
+    ]);
+    assert!(!output.status.success());
+    let manifest = Manifest::read(dir.path()).unwrap();
+    assert_eq!(manifest.result, None);
+    let error = manifest.error.unwrap();
+    assert!(error.contains("Player 0 failed the handshake"), "{error}");
+}
+
+#[test]
+fn replays_a_recorded_player() {
+    let dir = tempfile::tempdir().unwrap();
+    run_match(dir.path(), "direct");
+    let transcript = dir.path().join("transcript.jsonl");
+    let transcript = transcript.to_str().unwrap();
+    let output = metamanager(&["replay", transcript, PLAYER, "--player", "0"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let different = r#"sh -c 'read message; echo "something else"'"#;
+    let output = metamanager(&["replay", transcript, different, "--player", "0"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn replays_are_set_up_like_the_recorded_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let match_dir = dir.path().to_str().unwrap();
+    // Answers with what it was told, so it only replays if it's told the same
+    let player = r#"sh -c 'read message; echo "$MM_SEED $MM_DELIM $MM_FRAMING"'"#;
+    let manager = r#"sh -c 'echo "0${MM_DELIM}ping"; read answer'"#;
+    let output = metamanager(&[
+        "--match-dir",
+        match_dir,
+        "--seed",
+        "42",
+        "--delim",
+        "|",
+        manager,
+        player,
+    ]);
+    assert!(output.status.success());
+    let transcript = dir.path().join("transcript.jsonl");
+    let transcript = transcript.to_str().unwrap();
+    let output = metamanager(&["replay", transcript, player, "--player", "0"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = metamanager(&["replay", transcript, player, "--player", "0", "--seed", "7"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn reports_processes_that_fail_to_spawn() {
+    let output = metamanager(&["./definitely-not-a-bot", PLAYER]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to spawn manager"), "{stderr}");
+}
+
+#[test]
+fn spectator_processes_watch_the_whole_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let seen = dir.path().join("seen.txt");
+    let spectator = format!("sh -c 'cat > {}'", seen.display());
+    let manager = r#"sh -c 'echo 0:ping; echo spec:hello; read answer'"#;
+    let output = metamanager(&[
+        "--spectator-feed",
+        "public",
+        "--spectator",
+        &spectator,
+        manager,
+        PLAYER,
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(seen).unwrap(), "hello\n");
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::{start_with, ROUTINGS};
+use metamanager::protocol::{GameSchema, Protocol};
+use metamanager::replay::{replay, RecordedMatch};
+use metamanager::transcript::{read_transcript, Transcript};
+use metamanager::{Match, ProcessConfig};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn tictactoe() -> GameSchema {
+    let players = serde_json::from_value(json!({
+        "move": {"type": "integer", "minimum": 0, "maximum": 8},
+        "resign": {"type": "null"},
+    }))
+    .unwrap();
+    GameSchema::new(players).unwrap()
+}
+
+fn json_match(schema: Option<GameSchema>) -> metamanager::MatchBuilder {
+    Match::builder().protocol(Protocol::Json).schema(schema)
+}
+
+async fn recv_json(fake: &mut common::Fake) -> Value {
+    serde_json::from_str(&fake.recv().await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn objects_are_addressed_with_to_and_from() {
+    for routing in ROUTINGS {
+        let mut game = start_with(json_match(None).routing(routing), 3);
+        game.manager
+            .send(r#"{"to":[0,2],"type":"board","data":"x:o\n"}"#)
+            .await;
+        for player in [0, 2] {
+            let board = recv_json(&mut game.players[player]).await;
+            assert_eq!(board, json!({"type": "board", "data": "x:o\n"}));
+        }
+        game.manager.send(r#"{"to":1,"type":"go"}"#).await;
+        assert_eq!(recv_json(&mut game.players[1]).await, json!({"type": "go"}));
+        game.players[1]
+            .send(r#"{"type":"move","data":{"x":1}}"#)
+            .await;
+        assert_eq!(
+            recv_json(&mut game.manager).await,
+            json!({"from": 1, "type": "move", "data": {"x": 1}})
+        );
+        game.manager
+            .send(r#"{"to":"mm","type":"result","data":"1 0 0 three in a row"}"#)
+            .await;
+        let result = game.finish().await.unwrap().result.unwrap();
+        assert_eq!(result.scores, [1.0, 0.0, 0.0]);
+        assert_eq!(result.reason.as_deref(), Some("three in a row"));
+    }
+}
+
+#[tokio::test]
+async fn invalid_player_messages_are_reported_instead_of_routed() {
+    for routing in ROUTINGS {
+        let mut game = start_with(json_match(Some(tictactoe())).routing(routing), 1);
+        let player = &mut game.players[0];
+        player.send("4").await;
+        player.send(r#"{"type":"move","data":9}"#).await;
+        player.send(r#"{"type":"castle"}"#).await;
+        player.send(r#"{"type":"move","data":4,"to":0}"#).await;
+        let mut invalid = Vec::new();
+        for _ in 0..4 {
+            let control = recv_json(&mut game.manager).await;
+            assert_eq!(control["from"], "mm", "{control}");
+            assert_eq!(control["type"], "invalid", "{control}");
+            invalid.push(control["data"].as_str().unwrap().to_string());
+        }
+        assert!(
+            invalid[0].starts_with("0 not a JSON object"),
+            "{}",
+            invalid[0]
+        );
+        assert_eq!(
+            invalid[1],
+            "0 'move' data: 9 is greater than the maximum of 8"
+        );
+        assert_eq!(invalid[2], "0 unknown message type 'castle'");
+        assert_eq!(invalid[3], "0 players can't set 'from' or 'to'");
+        // Reports take the same path as routed messages, so they arrive in order
+        let player = &mut game.players[0];
+        player.send(r#"{"type":"move","data":4}"#).await;
+        player.send(r#"{"type":"move","data":-1}"#).await;
+        player.send(r#"{"type":"resign"}"#).await;
+        assert_eq!(
+            recv_json(&mut game.manager).await,
+            json!({"from": 0, "type": "move", "data": 4})
+        );
+        let control = recv_json(&mut game.manager).await;
+        assert_eq!(control["type"], "invalid", "{control}");
+        assert_eq!(
+            recv_json(&mut game.manager).await,
+            json!({"from": 0, "type": "resign"})
+        );
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn player_messages_are_recorded_as_sent_and_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("transcript.jsonl");
+    let builder =
+        json_match(Some(tictactoe())).transcript(Transcript::create(&path).await.unwrap());
+    let mut game = start_with(builder, 1);
+    game.manager.send(r#"{"to":0,"type":"go"}"#).await;
+    game.players[0].expect(r#"{"type":"go"}"#).await;
+    let sent = r#"{"type": "move", "data": 4}"#;
+    game.players[0].send(sent).await;
+    assert_eq!(
+        recv_json(&mut game.manager).await,
+        json!({"from": 0, "type": "move", "data": 4})
+    );
+    game.manager
+        .send(r#"{"to":"mm","type":"result","data":"1 moved"}"#)
+        .await;
+    game.finish().await.unwrap();
+
+    let entries = read_transcript(&path).unwrap();
+    assert_eq!(entries[1].payload, sent);
+    // Answers the same way, spacing and key order included
+    let answer = dir.path().join("answer");
+    std::fs::write(&answer, format!("{sent}\n")).unwrap();
+    let player = format!("sh -c 'read go; cat {}'", answer.display());
+    let player = ProcessConfig::from_command_line(&player).unwrap();
+    let matched = replay(
+        &path,
+        0,
+        &player,
+        Duration::from_secs(5),
+        RecordedMatch::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(matched, 1);
+}
+
+#[tokio::test]
+async fn malformed_manager_messages_are_dropped() {
+    for routing in ROUTINGS {
+        let mut game = start_with(json_match(None).routing(routing), 1);
+        game.manager.send("0:not json").await;
+        game.manager.send(r#"{"type":"board"}"#).await;
+        game.manager.send(r#"{"to":5,"type":"board"}"#).await;
+        game.manager.send(r#"{"to":"0","type":"board"}"#).await;
+        assert_eq!(
+            recv_json(&mut game.players[0]).await,
+            json!({"type": "board"})
+        );
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn unreadable_manager_output_fails_the_match() {
+    for routing in ROUTINGS {
+        let mut game = start_with(json_match(None).routing(routing), 1);
+        // Not UTF-8, so not a line
+        game.manager.send_raw(b"\xff\n").await;
+        let err = game.finish().await.unwrap_err();
+        assert!(format!("{err:#}").contains("the manager"), "{err:#}");
+    }
+}
@@ -0,0 +1,152 @@
+// Python players written with middleman-sdk/python/middleman.py, run through the metamanager
+// binary so the helpers stay in sync with what the metamanager actually speaks
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+// Sends both players a number and reports what came back, skipping control messages
+const REFEREE: &str = r#"sh -c '
+echo 0:10; echo 1:20
+while read line; do
+    case $line in
+        0:*) p0=${line#0:} ;;
+        1:*) p1=${line#1:} ;;
+    esac
+    [ -n "$p0" ] && [ -n "$p1" ] && break
+done
+echo "mm:result 0.5 0.5 got $p0 and $p1"
+'"#;
+
+// The same with length framing, where a message can't be read a line at a time
+const FRAMED_REFEREE: &str = r#"python3 -c '
+import struct, sys
+
+def send(message):
+    data = message.encode()
+    sys.stdout.buffer.write(struct.pack(">I", len(data)) + data)
+    sys.stdout.buffer.flush()
+
+def recv():
+    (length,) = struct.unpack(">I", sys.stdin.buffer.read(4))
+    return sys.stdin.buffer.read(length).decode()
+
+send("0:10")
+send("1:20")
+answers = {}
+while len(answers) < 2:
+    tag, _, message = recv().partition(":")
+    if tag != "mm":
+        answers[tag] = message
+send("mm:result 0.5 0.5 got %s and %s" % (answers["0"], answers["1"]))
+'"#;
+
+// Answers with the number plus its player index, through the Player class
+const ADDER: &str = r#"
+from middleman import Player, run_player
+
+
+class Adder(Player):
+    name = "adder"
+    version = "1.0"
+    game = "adding"
+    role = 0
+
+    def parse(self, line):
+        return int(line)
+
+    def on_hello(self, hello):
+        self.role = hello.player
+
+    def on_message(self, message):
+        return message + self.role
+
+
+run_player(Adder())
+"#;
+
+// The same, driving a Connection itself
+const LOOP_ADDER: &str = r#"
+from middleman import Connection
+
+connection = Connection()
+hello = connection.handshake("loop_adder", "2", game="adding")
+role = hello.player if hello else 0
+connection.send(connection.recv(int) + role)
+"#;
+
+fn metamanager(dir: &Path, args: &[&str]) -> Output {
+    metamanager_with(dir, args, REFEREE)
+}
+
+fn metamanager_with(dir: &Path, args: &[&str], referee: &str) -> Output {
+    let sdk = Path::new(env!("CARGO_MANIFEST_DIR")).join("../middleman-sdk/python");
+    for (name, script) in [("adder.py", ADDER), ("loop_adder.py", LOOP_ADDER)] {
+        std::fs::write(dir.join(name), script).unwrap();
+    }
+    let adder = format!("python3 {}", dir.join("adder.py").display());
+    let loop_adder = format!("python3 {}", dir.join("loop_adder.py").display());
+    Command::new(env!("CARGO_BIN_EXE_metamanager"))
+        .args(args)
+        .args([referee, &adder, &loop_adder])
+        .env("PYTHONPATH", sdk)
+        .output()
+        .expect("metamanager should start")
+}
+
+#[test]
+fn python_players_answer_the_handshake_and_play() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = metamanager(dir.path(), &["--handshake", "adding"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("Result: 0.5 0.5 (got 10 and 21)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("player 0 (adder 1.0)"), "{stdout}");
+    assert!(stdout.contains("player 1 (loop_adder 2)"), "{stdout}");
+}
+
+#[test]
+fn python_players_play_without_a_handshake() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = metamanager(dir.path(), &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Result: 0.5 0.5 (got 10 and 20)"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn python_players_refuse_other_games() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = metamanager(dir.path(), &["--handshake", "chess"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("refused: plays adding, not chess"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn python_players_use_the_matchs_framing() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = ["--framing", "length", "--handshake", "adding"];
+    let output = metamanager_with(dir.path(), &args, FRAMED_REFEREE);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("Result: 0.5 0.5 (got 10 and 21)"),
+        "{stdout}"
+    );
+}
@@ -0,0 +1,135 @@
+use metamanager::ratings::{Glicko, RatedGame, RatingStore, Ratings};
+use metamanager::report::MatchResult;
+use metamanager::{MatchConfig, ProcessConfig};
+
+fn game(players: [&str; 2], scores: [f64; 2]) -> RatedGame {
+    RatedGame {
+        time: 1_700_000_000,
+        players: players.map(String::from).to_vec(),
+        scores: scores.to_vec(),
+        reason: None,
+    }
+}
+
+fn close(a: f64, b: f64, tolerance: f64) -> bool {
+    (a - b).abs() < tolerance
+}
+
+#[test]
+fn glicko_matches_the_worked_example() {
+    // From section 3 of http://www.glicko.net/glicko/glicko2.pdf
+    let player = Glicko {
+        rating: 1500.0,
+        deviation: 200.0,
+        volatility: 0.06,
+    };
+    let opponent = |rating, deviation| Glicko {
+        rating,
+        deviation,
+        volatility: 0.06,
+    };
+    let updated = player.update(&[
+        (opponent(1400.0, 30.0), 1.0),
+        (opponent(1550.0, 100.0), 0.0),
+        (opponent(1700.0, 300.0), 0.0),
+    ]);
+    assert!(close(updated.rating, 1464.06, 0.01), "{updated:?}");
+    assert!(close(updated.deviation, 151.52, 0.01), "{updated:?}");
+    assert!(close(updated.volatility, 0.05999, 0.00001), "{updated:?}");
+}
+
+#[test]
+fn first_win_moves_elo_by_half_of_k() {
+    let ratings = Ratings::from_games(&[game(["a", "b"], [1.0, 0.0])]);
+    let (a, b) = (ratings.get("a").unwrap(), ratings.get("b").unwrap());
+    assert_eq!((a.elo, b.elo), (1516.0, 1484.0));
+    assert_eq!((a.wins, b.losses), (1, 1));
+    assert!(a.glicko.rating > 1500.0 && b.glicko.rating < 1500.0);
+    assert!(a.glicko.deviation < 350.0);
+}
+
+#[test]
+fn draws_between_equals_change_nothing() {
+    let ratings = Ratings::from_games(&[game(["a", "b"], [0.5, 0.5])]);
+    let a = ratings.get("a").unwrap();
+    assert_eq!(a.elo, 1500.0);
+    assert!(close(a.glicko.rating, 1500.0, 0.000001));
+    assert_eq!(a.draws, 1);
+}
+
+#[test]
+fn more_games_narrow_the_interval() {
+    let games = (0..30)
+        .map(|_| game(["strong", "weak"], [1.0, 0.0]))
+        .collect::<Vec<_>>();
+    let ratings = Ratings::from_games(&games);
+    let leaderboard = ratings.leaderboard();
+    assert_eq!(leaderboard.0[0].name, "strong");
+    let strong = ratings.get("strong").unwrap();
+    assert_eq!(strong.history.len(), 30);
+    let width = |glicko: &Glicko| glicko.interval().1 - glicko.interval().0;
+    assert!(width(&strong.history[29].glicko) < width(&strong.history[0].glicko));
+    assert!(strong
+        .history
+        .windows(2)
+        .all(|pair| pair[0].elo < pair[1].elo));
+    assert_eq!(strong.history[0].opponents, ["weak"]);
+}
+
+#[test]
+fn multiplayer_games_rate_every_pair() {
+    let ratings = Ratings::from_games(&[RatedGame {
+        time: 0,
+        players: vec!["first".into(), "second".into(), "third".into()],
+        scores: vec![3.0, 2.0, 1.0],
+        reason: None,
+    }]);
+    let elo = |name| ratings.get(name).unwrap().elo;
+    assert!(elo("first") > elo("second") && elo("second") > elo("third"));
+    assert!(close(elo("second"), 1500.0, 0.000001));
+    assert!(close(elo("first") - 1500.0, 16.0, 0.000001));
+}
+
+#[test]
+fn store_keeps_games_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = RatingStore::open(&dir.path().join("nested/ratings.jsonl"));
+    assert!(store.games().unwrap().is_empty());
+    let result = MatchResult::parse("0 1 checkmate", 2).unwrap();
+    let first = RatedGame::new(vec!["a".into(), "b".into()], &result);
+    store.record(&first).unwrap();
+    store.record(&game(["b", "c"], [0.5, 0.5])).unwrap();
+    let games = store.games().unwrap();
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[0], first);
+    assert_eq!(games[0].reason.as_deref(), Some("checkmate"));
+    assert!(store
+        .record(&RatedGame {
+            scores: vec![1.0],
+            ..first
+        })
+        .is_err());
+}
+
+#[test]
+fn bots_arent_rated_against_themselves() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = RatingStore::open(&dir.path().join("ratings.jsonl"));
+    let self_play = game(["main", "main"], [1.0, 0.0]);
+    assert!(store.record(&self_play).is_err());
+    // A store edited by hand can still have one, it isn't rated
+    let ratings = Ratings::from_games(&[self_play, game(["a", "b"], [1.0, 0.0])]);
+    assert!(ratings.get("main").is_none());
+    assert_eq!(ratings.get("a").unwrap().games, 1);
+
+    let bot = ProcessConfig::from_command_line("./main").unwrap();
+    let mut config = MatchConfig {
+        manager: ProcessConfig::from_command_line("./referee").unwrap(),
+        players: vec![bot.clone(), bot],
+        ratings: Some(dir.path().join("ratings.jsonl")),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+    config.players[1].name = Some("main-old".to_string());
+    config.validate().unwrap();
+}
@@ -0,0 +1,121 @@
+// Remote players joining a match on localhost, with the metamanager binary on both ends:
+// one run listening for the match, and `connect` running the bot
+
+use futures::{SinkExt, StreamExt};
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpStream;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+// Sends player 0 a ping and reports the answer as the reason of its result
+const MANAGER: &str = r#"sh -c 'echo 0:ping; read answer; echo "mm:result 1 $answer"'"#;
+const PLAYER: &str = r#"sh -c 'read message; echo "pong $message"'"#;
+
+struct Listening {
+    child: Child,
+    addr: String,
+    // Keeps draining stderr after the address was read from it
+    stderr: JoinHandle<String>,
+}
+
+impl Listening {
+    // Start a match with one remote player, listening with flag on a free port
+    fn start(flag: &str, seat: &str) -> Listening {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_metamanager"))
+            .args(["--log-level", "info", "--connect-timeout-ms", "5000"])
+            .args([flag, "127.0.0.1:0", MANAGER, seat])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut seen = String::new();
+        let addr = loop {
+            let mut line = String::new();
+            if stderr.read_line(&mut line).unwrap() == 0 {
+                panic!("metamanager exited before listening: {seen}");
+            }
+            if let Some((_, addr)) = line.split_once(" players on ") {
+                break addr.trim().to_string();
+            }
+            seen.push_str(&line);
+        };
+        let stderr = std::thread::spawn(move || {
+            let mut rest = String::new();
+            stderr.read_to_string(&mut rest).unwrap();
+            seen + &rest
+        });
+        Listening {
+            child,
+            addr,
+            stderr,
+        }
+    }
+
+    // Wait for the match to end, returning its stdout
+    fn finish(self) -> String {
+        let output = self.child.wait_with_output().unwrap();
+        let stderr = self.stderr.join().unwrap();
+        assert!(output.status.success(), "{stderr}");
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+fn connect(url: &str) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_metamanager"))
+        .args(["connect", url, PLAYER])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn tcp_players_join_through_connect() {
+    let listening = Listening::start("--listen", "@tcp");
+    connect(&format!("tcp://{}", listening.addr));
+    let stdout = listening.finish();
+    assert!(stdout.contains("Result: 1 (0:pong ping)"), "{stdout}");
+}
+
+#[test]
+fn websocket_players_join_through_connect() {
+    let listening = Listening::start("--ws-listen", "@ws");
+    connect(&format!("ws://{}", listening.addr));
+    let stdout = listening.finish();
+    assert!(stdout.contains("Result: 1 (0:pong ping)"), "{stdout}");
+}
+
+#[test]
+fn stalled_websocket_upgrades_dont_hold_up_other_players() {
+    let listening = Listening::start("--ws-listen", "@ws");
+    // Connects but never sends the upgrade request
+    let _stalled = TcpStream::connect(&listening.addr).unwrap();
+    connect(&format!("ws://{}", listening.addr));
+    let stdout = listening.finish();
+    assert!(stdout.contains("Result: 1 (0:pong ping)"), "{stdout}");
+}
+
+#[tokio::test]
+async fn binary_websocket_frames_are_read_as_text_lines() {
+    let listening = Listening::start("--ws-listen", "@ws");
+    let url = format!("ws://{}", listening.addr);
+    let (mut websocket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    let ping = websocket.next().await.unwrap().unwrap();
+    assert_eq!(ping, Message::Text("ping".to_string()));
+    websocket
+        .send(Message::Binary(b"pong ping".to_vec()))
+        .await
+        .unwrap();
+    // Done playing, like a bot exiting
+    websocket.close(None).await.unwrap();
+    let stdout = tokio::task::spawn_blocking(|| listening.finish())
+        .await
+        .unwrap();
+    assert!(stdout.contains("Result: 1 (0:pong ping)"), "{stdout}");
+}
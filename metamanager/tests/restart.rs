@@ -0,0 +1,122 @@
+mod common;
+
+use common::ROUTINGS;
+use metamanager::report::MatchReport;
+use metamanager::{Match, MatchConfig, ProcessConfig, Routing};
+use std::path::Path;
+
+// Answers every line with `got <line>`, but crashes after its first answer unless marker
+// exists, which it creates
+fn flaky_player(marker: &Path) -> ProcessConfig {
+    let script = "read a; echo got $a; read b; \
+                  if [ ! -e \"$0\" ]; then touch \"$0\"; exit 3; fi; \
+                  echo got $b; read c";
+    let mut player = ProcessConfig::from_command_line("sh").unwrap();
+    player.args = vec![
+        "-c".to_string(),
+        script.to_string(),
+        marker.display().to_string(),
+    ];
+    player
+}
+
+fn config(routing: Routing, manager: &str, player: ProcessConfig) -> MatchConfig {
+    let mut manager_config = ProcessConfig::from_command_line("sh").unwrap();
+    manager_config.args = vec!["-c".to_string(), manager.to_string()];
+    MatchConfig {
+        routing,
+        manager: manager_config,
+        players: vec![player],
+        restarts: 1,
+        ..Default::default()
+    }
+}
+
+async fn run(config: &MatchConfig) -> MatchReport {
+    common::within("the match", Match::from_config(config).await.unwrap().run())
+        .await
+        .unwrap()
+}
+
+// The manager reports what it heard as the reason of its result
+async fn play(routing: Routing, manager: &str, player: ProcessConfig) -> MatchReport {
+    run(&config(routing, manager, player)).await
+}
+
+fn heard(report: &MatchReport) -> String {
+    report.result.as_ref().unwrap().reason.clone().unwrap()
+}
+
+#[tokio::test]
+async fn crashed_players_are_restarted_and_caught_up() {
+    for routing in ROUTINGS {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = "echo 0:x; read r1; echo 0:y; read ctl; echo mm:restart 0; \
+                       read l1; read l2; echo \"mm:result 1 $r1 $ctl $l1 $l2\"";
+        let report = play(routing, manager, flaky_player(&dir.path().join("marker"))).await;
+        // The answer to y and the restart notice race each other
+        let heard = heard(&report);
+        assert!(heard.starts_with("0:got x mm:disconnect 0 "), "{heard}");
+        assert!(heard.contains("mm:restarted 0"), "{heard}");
+        assert!(heard.contains("0:got y"), "{heard}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sandboxed_players_are_restarted_sandboxed() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut player = flaky_player(&dir.path().join("marker"));
+    // Says which temp dir it got on every start, for the stderr log to show
+    player.args[1] = format!("echo \"$TMPDIR\" >&2; {}", player.args[1]);
+    let manager = "echo 0:x; read r1; echo 0:y; read ctl; echo mm:restart 0; \
+                   read l1; read l2; echo \"mm:result 1 $r1 $ctl $l1 $l2\"";
+    let mut config = config(Routing::Channels, manager, player);
+    config.sandbox = Some(Default::default());
+    config.match_dir = Some(dir.path().join("match"));
+    let report = run(&config).await;
+    let heard = heard(&report);
+    assert!(heard.contains("mm:restarted 0"), "{heard}");
+    // Both processes logged to the same file, each from a private temp dir
+    let log = std::fs::read_to_string(dir.path().join("match/player0.stderr.log")).unwrap();
+    let tmps = log.lines().collect::<Vec<_>>();
+    assert_eq!(tmps.len(), 2, "{log}");
+    assert!(tmps.iter().all(|tmp| tmp.contains("metamanager-")), "{log}");
+    assert_ne!(tmps[0], tmps[1]);
+    assert!(report.players[0].cpu_ms.is_some());
+}
+
+#[tokio::test]
+async fn players_are_only_restarted_if_the_manager_asks() {
+    for routing in ROUTINGS {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = "echo 0:x; read r1; echo 0:y; read ctl; echo \"mm:result 0 $ctl\"";
+        let report = play(routing, manager, flaky_player(&dir.path().join("marker"))).await;
+        assert_eq!(heard(&report), "mm:disconnect 0");
+    }
+}
+
+#[tokio::test]
+async fn players_that_cant_continue_forfeit() {
+    for routing in ROUTINGS {
+        // Crashes every time, and the second time around doesn't remember its first answer
+        let dir = tempfile::tempdir().unwrap();
+        let mut player = flaky_player(&dir.path().join("marker"));
+        player.args[1] = "read a; if [ -e \"$0\" ]; then echo forgot; else echo got $a; fi; \
+                          touch \"$0\"; read b; exit 3"
+            .to_string();
+        let manager = "echo 0:x; read r1; echo 0:y; read ctl; echo mm:restart 0; \
+                       read l1; echo \"mm:result 0 $l1\"";
+        let report = play(routing, manager, player).await;
+        assert_eq!(heard(&report), "mm:forfeit 0 diverged on replay");
+
+        // Crashes again after catching up, with no restarts left
+        let dir = tempfile::tempdir().unwrap();
+        let mut player = flaky_player(&dir.path().join("marker"));
+        player.args[1] = "read a; echo got $a; read b; exit 3".to_string();
+        let manager = "echo 0:x; read r1; echo 0:y; read ctl; echo mm:restart 0; \
+                       read l1; read l2; echo \"mm:result 0 $l1 $l2\"";
+        let report = play(routing, manager, player).await;
+        assert_eq!(heard(&report), "mm:restarted 0 mm:forfeit 0 crashed");
+    }
+}
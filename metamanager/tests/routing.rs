@@ -0,0 +1,257 @@
+mod common;
+
+use common::{start, start_with, within, ROUTINGS};
+use metamanager::timing::TimeControl;
+use metamanager::transcript::{read_transcript, Transcript};
+use metamanager::Match;
+use std::time::Duration;
+
+#[tokio::test]
+async fn manager_lines_reach_the_tagged_player() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 3);
+        game.manager.send("2:for two").await;
+        game.manager.send("0:for zero").await;
+        game.manager.send("1:for one").await;
+        game.players[0].expect("for zero").await;
+        game.players[1].expect("for one").await;
+        game.players[2].expect("for two").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn messages_may_contain_the_delim() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 1);
+        game.manager.send("0:a:b:c").await;
+        game.players[0].expect("a:b:c").await;
+        game.players[0].send("x:y").await;
+        game.manager.expect("0:x:y").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn player_lines_reach_the_manager_tagged_in_order() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        game.players[1].send("first").await;
+        game.manager.expect("1:first").await;
+        game.players[0].send("a").await;
+        game.players[0].send("b").await;
+        game.manager.expect("0:a").await;
+        game.manager.expect("0:b").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn malformed_manager_lines_are_dropped() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        for line in [
+            "no delim",
+            "x:not a number",
+            "2:no such player",
+            ":",
+            "-1:negative",
+        ] {
+            game.manager.send(line).await;
+        }
+        game.manager.send("1:still routing").await;
+        game.players[1].expect("still routing").await;
+        game.manager.send("0:").await;
+        game.players[0].expect("").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn custom_delim() {
+    for routing in ROUTINGS {
+        let mut game = start_with(Match::builder().routing(routing).delim('|'), 1);
+        game.manager.send("0|a:b").await;
+        game.players[0].expect("a:b").await;
+        game.players[0].send("c").await;
+        game.manager.expect("0|c").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn player_eof_does_not_stop_the_others() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        game.players[0].close().await;
+        game.manager.send("1:still here").await;
+        game.players[1].expect("still here").await;
+        game.players[1].send("me too").await;
+        game.manager.expect("1:me too").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn manager_sees_eof_once_every_player_is_done() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        game.players[0].send("bye").await;
+        game.players[0].close().await;
+        game.manager.expect("0:bye").await;
+        game.players[1].close().await;
+        game.manager.expect_eof().await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn players_see_eof_once_the_manager_is_done() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        game.manager.send("0:last words").await;
+        game.manager.close().await;
+        game.players[0].expect("last words").await;
+        game.players[0].expect_eof().await;
+        game.players[1].expect_eof().await;
+        // Players can still have their say after the manager stops talking
+        game.players[1].send("late").await;
+        game.manager.expect("1:late").await;
+        game.finish().await.unwrap();
+    }
+}
+
+// The match must end once everyone closed. In channel mode a stray clone of the
+// player to manager sender would keep the manager's stdin open and hang join_all forever
+#[tokio::test]
+async fn finishes_once_everyone_closes() {
+    for routing in ROUTINGS {
+        let game = start(routing, 4);
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn reports_response_times() {
+    for routing in ROUTINGS {
+        let mut game = start_with(Match::builder().routing(routing).report_times(true), 1);
+        // Unprompted messages don't get a time
+        game.players[0].send("hello").await;
+        game.manager.expect("0:hello").await;
+        game.manager.send("0:your move").await;
+        game.players[0].expect("your move").await;
+        game.players[0].send("move").await;
+        game.manager.expect("0:move").await;
+        let time = game.manager.recv().await.unwrap();
+        let fields = time.split(' ').collect::<Vec<_>>();
+        assert_eq!(fields[..2], ["mm:time", "0"], "{time}");
+        assert!(fields[2].parse::<u64>().is_ok(), "{time}");
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn tells_the_manager_when_a_clock_runs_out() {
+    for routing in ROUTINGS {
+        let time_control = TimeControl {
+            base_ms: Duration::from_millis(50),
+            increment_ms: Duration::ZERO,
+        };
+        let builder = Match::builder()
+            .routing(routing)
+            .time_control(Some(time_control));
+        let mut game = start_with(builder, 2);
+        game.manager.send("1:think forever").await;
+        game.players[1].expect("think forever").await;
+        within("the timeout", game.manager.expect("mm:timeout 1")).await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn player_that_hangs_up_does_not_stop_the_others() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        game.players[0].hang_up();
+        game.manager.send("0:anyone there?").await;
+        game.manager.send("1:your move").await;
+        game.players[1].expect("your move").await;
+        game.players[1].send("move").await;
+        game.manager.expect("1:move").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn messages_to_players_that_are_gone_arent_recorded() {
+    for routing in ROUTINGS {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        let builder = Match::builder()
+            .routing(routing)
+            .transcript(Transcript::create(&path).await.unwrap());
+        let mut game = start_with(builder, 2);
+        game.players[0].hang_up();
+        game.manager.send("0:anyone there?").await;
+        game.manager.send("1:your move").await;
+        game.players[1].expect("your move").await;
+        game.finish().await.unwrap();
+        let entries = read_transcript(&path).unwrap();
+        let recipients = entries.iter().map(|entry| entry.player).collect::<Vec<_>>();
+        assert_eq!(recipients, [1], "{routing:?}");
+    }
+}
+
+#[tokio::test]
+async fn players_are_drained_after_the_manager_hangs_up() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 1);
+        game.manager.hang_up();
+        // Way more than fits in any buffer, so this blocks forever if nobody reads it
+        let chatter = "x".repeat(1024);
+        for _ in 0..1024 {
+            game.players[0].send(&chatter).await;
+        }
+        game.players[0].expect_eof().await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn broadcasts_and_multicasts() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 3);
+        game.manager.send("*:everyone").await;
+        game.manager.send("2,0:some:of you").await;
+        game.manager.send("1,1:only once").await;
+        for player in game.players.iter_mut() {
+            player.expect("everyone").await;
+        }
+        game.players[0].expect("some:of you").await;
+        game.players[2].expect("some:of you").await;
+        game.players[1].expect("only once").await;
+        game.manager.send("1:next").await;
+        game.players[1].expect("next").await;
+        game.finish().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn malformed_multicasts_are_dropped() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        for line in [
+            "0,3:no such player",
+            "0,:empty",
+            ",1:empty",
+            "*,0:mixed",
+            "0 ,1:spaces",
+        ] {
+            game.manager.send(line).await;
+        }
+        game.manager.send("0,1:still routing").await;
+        game.players[0].expect("still routing").await;
+        game.players[1].expect("still routing").await;
+        game.finish().await.unwrap();
+    }
+}
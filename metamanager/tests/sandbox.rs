@@ -0,0 +1,111 @@
+#![cfg(target_os = "linux")]
+
+use metamanager::sandbox::SandboxConfig;
+use metamanager::{Match, MatchConfig, ProcessConfig, Routing};
+use std::time::Duration;
+
+// Reports the first line it's sent as the reason of a result
+const ECHOING_MANAGER: &str = r#"sh -c 'read line; echo "mm:result 1 $line"'"#;
+
+fn config(player: &str, sandbox: SandboxConfig) -> MatchConfig {
+    MatchConfig {
+        manager: ProcessConfig::from_command_line(ECHOING_MANAGER).unwrap(),
+        players: vec![ProcessConfig::from_command_line(player).unwrap()],
+        sandbox: Some(sandbox),
+        ..Default::default()
+    }
+}
+
+// What the player's first message (or a control message) was, according to the manager
+async fn first_line(config: &MatchConfig) -> String {
+    let game = async { Match::from_config(config).await?.run().await };
+    let report = tokio::time::timeout(Duration::from_secs(20), game)
+        .await
+        .expect("match timed out")
+        .unwrap();
+    report.result.unwrap().reason.unwrap()
+}
+
+#[tokio::test]
+async fn cpu_hogs_forfeit() {
+    let sandbox = SandboxConfig {
+        cpu_seconds: Some(1),
+        ..Default::default()
+    };
+    for routing in [Routing::Direct, Routing::Channels] {
+        let mut config = config("sh -c 'while :; do :; done'", sandbox.clone());
+        config.routing = routing;
+        assert_eq!(first_line(&config).await, "mm:forfeit 0 cpu limit");
+    }
+}
+
+#[tokio::test]
+async fn writing_huge_files_forfeits() {
+    let sandbox = SandboxConfig {
+        file_size_mb: Some(1),
+        ..Default::default()
+    };
+    let player = r#"sh -c 'exec head -c 2000000 /dev/zero > "$TMPDIR/big"'"#;
+    let line = first_line(&config(player, sandbox)).await;
+    assert_eq!(line, "mm:forfeit 0 file size limit");
+}
+
+#[tokio::test]
+async fn players_only_see_loopback() {
+    let player = "sh -c 'echo $(tail -n +3 /proc/net/dev | cut -d: -f1)'";
+    let line = first_line(&config(player, SandboxConfig::default())).await;
+    assert_eq!(line, "0:lo");
+    let sandbox = SandboxConfig {
+        network: true,
+        ..Default::default()
+    };
+    let line = first_line(&config(player, sandbox)).await;
+    assert_ne!(line, "0:lo");
+}
+
+#[tokio::test]
+async fn temp_dir_is_private_and_cleaned_up() {
+    let player = r#"sh -c 'touch "$TMPDIR/scratch" && echo "$TMPDIR"'"#;
+    let line = first_line(&config(player, SandboxConfig::default())).await;
+    let tmp = line.strip_prefix("0:").unwrap();
+    assert_ne!(tmp, std::env::temp_dir().to_str().unwrap());
+    assert!(tmp.contains("metamanager-"), "{tmp}");
+    assert!(!std::path::Path::new(tmp).exists());
+}
+
+#[tokio::test]
+async fn leftover_processes_are_killed() {
+    let player = "sh -c 'sleep 1000 > /dev/null & echo $!'";
+    let line = first_line(&config(player, SandboxConfig::default())).await;
+    let pid = line.strip_prefix("0:").unwrap();
+    // SIGKILL takes a moment to land, and killed orphans may linger as zombies until
+    // something reaps them
+    let mut stat = String::new();
+    for _ in 0..50 {
+        stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+        if stat.is_empty() || stat.contains(") Z ") {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{stat}");
+}
+
+#[tokio::test]
+async fn crashes_arent_blamed_on_limits_they_didnt_reach() {
+    let sandbox = SandboxConfig {
+        cpu_seconds: Some(10),
+        memory_mb: Some(1024),
+        ..Default::default()
+    };
+    // Reports everything it heard once the player is gone
+    let manager = r#"sh -c 'heard=; while read line; do heard="$heard $line"; done; echo "mm:result 1$heard"'"#;
+    for signal in ["KILL", "SEGV"] {
+        let mut config = config(
+            &format!("sh -c 'echo hi; kill -{signal} $$'"),
+            sandbox.clone(),
+        );
+        config.manager = ProcessConfig::from_command_line(manager).unwrap();
+        assert_eq!(first_line(&config).await, "0:hi");
+    }
+}
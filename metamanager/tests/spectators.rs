@@ -0,0 +1,141 @@
+mod common;
+
+use common::{fake, start_with, within, ROUTINGS};
+use metamanager::spectators::SpectatorFeed;
+use metamanager::transcript::{Direction, TranscriptEntry};
+use metamanager::Match;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UnixStream;
+
+fn entry(line: &str) -> (Direction, usize, String) {
+    let entry: TranscriptEntry = serde_json::from_str(line).unwrap();
+    (entry.direction, entry.player, entry.payload)
+}
+
+#[tokio::test]
+async fn spectators_see_every_routed_message() {
+    for routing in ROUTINGS {
+        let (mut spectator, transport) = fake("spectator");
+        let builder = Match::builder().routing(routing).spectator(transport);
+        let mut game = start_with(builder, 1);
+        game.manager.send("0:your move").await;
+        game.players[0].expect("your move").await;
+        game.players[0].send("e4").await;
+        game.manager.expect("0:e4").await;
+        let first = spectator.recv().await.unwrap();
+        assert_eq!(
+            entry(&first),
+            (Direction::ManagerToPlayer, 0, "your move".into())
+        );
+        let second = spectator.recv().await.unwrap();
+        assert_eq!(entry(&second), (Direction::PlayerToManager, 0, "e4".into()));
+        spectator.close().await;
+        game.finish().await.unwrap();
+        spectator.expect_eof().await;
+    }
+}
+
+#[tokio::test]
+async fn spectators_are_not_players() {
+    for routing in ROUTINGS {
+        let (mut spectator, transport) = fake("spectator");
+        let builder = Match::builder()
+            .routing(routing)
+            .spectator_feed(SpectatorFeed::Public)
+            .spectator(transport);
+        let mut game = start_with(builder, 1);
+        // Nothing a spectator says reaches the manager
+        spectator.send("resign").await;
+        // And there's still only one player to address
+        game.manager.send("1:nobody").await;
+        game.players[0].send("hello").await;
+        game.manager.expect("0:hello").await;
+        game.manager.send("spec:board").await;
+        spectator.expect("board").await;
+        game.manager.close().await;
+        game.players[0].expect_eof().await;
+        game.players[0].close().await;
+        assert_eq!(game.manager.recv().await, None);
+        spectator.close().await;
+        within("the match to finish", game.handle)
+            .await
+            .unwrap()
+            .unwrap();
+        spectator.expect_eof().await;
+    }
+}
+
+#[tokio::test]
+async fn public_feed_only_shows_spectator_lines() {
+    for routing in ROUTINGS {
+        let (mut spectator, transport) = fake("spectator");
+        let builder = Match::builder()
+            .routing(routing)
+            .spectator_feed(SpectatorFeed::Public)
+            .spectator(transport);
+        let mut game = start_with(builder, 2);
+        game.manager.send("0:your cards are AK").await;
+        game.players[0].expect("your cards are AK").await;
+        game.players[0].send("raise").await;
+        game.manager.expect("0:raise").await;
+        game.manager.send("spec:0 raises").await;
+        game.manager.send("*:0 raises").await;
+        game.players[1].expect("0 raises").await;
+        spectator.expect("0 raises").await;
+        spectator.close().await;
+        game.finish().await.unwrap();
+        spectator.expect_eof().await;
+    }
+}
+
+async fn connect(path: &Path) -> tokio::io::Lines<BufReader<UnixStream>> {
+    // The socket is bound once the match starts running
+    for _ in 0..100 {
+        if let Ok(stream) = UnixStream::connect(path).await {
+            return BufReader::new(stream).lines();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("could not connect to {}", path.display());
+}
+
+#[tokio::test]
+async fn spectators_can_join_a_running_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("spectate.sock");
+    let builder = Match::builder()
+        .spectator_feed(SpectatorFeed::Public)
+        .spectate_socket(Some(path.clone()));
+    let mut game = start_with(builder, 1);
+    game.manager.send("spec:before anyone joined").await;
+    let mut late = connect(&path).await;
+    // Connecting and subscribing aren't the same moment, so poke until something arrives
+    let mut subscribed = false;
+    for _ in 0..100 {
+        game.manager.send("spec:ping").await;
+        let line = tokio::time::timeout(Duration::from_millis(50), late.next_line()).await;
+        if let Ok(line) = line {
+            assert_eq!(line.unwrap().as_deref(), Some("ping"));
+            subscribed = true;
+            break;
+        }
+    }
+    assert!(subscribed);
+    game.manager.send("spec:after").await;
+    loop {
+        let line = within("the last line", late.next_line()).await.unwrap();
+        match line.as_deref() {
+            Some("ping") => continue,
+            other => {
+                assert_eq!(other, Some("after"));
+                break;
+            }
+        }
+    }
+    game.finish().await.unwrap();
+    let line = within("EOF", late.next_line()).await.unwrap();
+    assert_eq!(line, None);
+    assert!(!path.exists());
+}
@@ -0,0 +1,93 @@
+use metamanager::sprt::{run_sprt, Sprt, SprtConfig, Verdict};
+use metamanager::tournament::TournamentConfig;
+use metamanager::ProcessConfig;
+
+// Asks both players for a number, the higher one wins
+const REFEREE: &str = r#"sh -c '
+echo "*:go"
+read first; read second
+case $first in
+    0:*) p0=${first#0:}; p1=${second#1:} ;;
+    *) p1=${first#1:}; p0=${second#0:} ;;
+esac
+if [ "$p0" -gt "$p1" ]; then echo "mm:result 1 0"
+elif [ "$p0" -lt "$p1" ]; then echo "mm:result 0 1"
+else echo "mm:result 0.5 0.5"; fi
+'"#;
+
+fn bot(name: &str, number: u32) -> ProcessConfig {
+    let mut bot =
+        ProcessConfig::from_command_line(&format!("sh -c 'read go; echo {number}'")).unwrap();
+    bot.name = Some(name.to_string());
+    bot
+}
+
+fn config(new: u32, old: u32) -> SprtConfig {
+    SprtConfig {
+        games: TournamentConfig {
+            referee: ProcessConfig::from_command_line(REFEREE).unwrap(),
+            bots: vec![bot("new", new), bot("old", old)],
+            concurrency: 4,
+            ..Default::default()
+        },
+        elo1: 50.0,
+        max_games: Some(200),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn stronger_version_is_accepted() {
+    let mut seats = Vec::new();
+    let sprt = run_sprt(&config(2, 1), |game, _| seats.push(game.names.clone()))
+        .await
+        .unwrap();
+    assert_eq!(sprt.verdict, Some(Verdict::H1), "{sprt}");
+    assert_eq!((sprt.draws, sprt.losses), (0, 0));
+    assert!(sprt.llr() >= sprt.upper);
+    assert!(sprt.games() < 20, "{sprt}");
+    // Both versions get to play as player 0
+    assert!(seats.iter().any(|names| names[0] == "new"));
+    assert!(seats.iter().any(|names| names[0] == "old"));
+}
+
+#[tokio::test]
+async fn equal_versions_are_rejected() {
+    let sprt = run_sprt(&config(1, 1), |_, _| {}).await.unwrap();
+    assert_eq!(sprt.verdict, Some(Verdict::H0), "{sprt}");
+    assert_eq!(sprt.draws, sprt.games());
+    assert!(sprt.llr() <= sprt.lower);
+}
+
+#[tokio::test]
+async fn running_out_of_games_is_inconclusive() {
+    let mut config = config(2, 1);
+    config.max_games = Some(2);
+    let sprt = run_sprt(&config, |_, _| {}).await.unwrap();
+    assert_eq!(sprt.verdict, Some(Verdict::Inconclusive));
+    assert_eq!(sprt.games(), 2);
+}
+
+#[test]
+fn llr_follows_the_score() {
+    let config = SprtConfig::default();
+    let mut sprt = Sprt::new(&config);
+    assert_eq!(sprt.llr(), 0.0);
+    assert!((sprt.upper - 2.944).abs() < 0.001 && (sprt.lower + 2.944).abs() < 0.001);
+    (sprt.wins, sprt.draws, sprt.losses) = (400, 200, 400);
+    assert!(sprt.llr() < 0.0);
+    assert_eq!(sprt.elo(), 0.0);
+    (sprt.wins, sprt.draws, sprt.losses) = (550, 200, 250);
+    assert!(sprt.llr() > sprt.upper, "{sprt}");
+    assert!((sprt.elo() - 107.5).abs() < 0.1, "{sprt}");
+}
+
+#[test]
+fn bounds_need_to_make_sense() {
+    let mut config = config(2, 1);
+    config.elo1 = -5.0;
+    assert!(config.validate().is_err());
+    let mut config = self::config(2, 1);
+    config.alpha = 0.0;
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,139 @@
+use metamanager::tournament::{run_tournament, Format, Standings, TournamentConfig};
+use metamanager::ProcessConfig;
+
+// Asks both players for a number, the higher one wins
+const REFEREE: &str = r#"sh -c '
+echo "*:go"
+read first; read second
+case $first in
+    0:*) p0=${first#0:}; p1=${second#1:} ;;
+    *) p1=${first#1:}; p0=${second#0:} ;;
+esac
+if [ "$p0" -gt "$p1" ]; then echo "mm:result 1 0 higher"
+elif [ "$p0" -lt "$p1" ]; then echo "mm:result 0 1 higher"
+else echo "mm:result 0.5 0.5 same"; fi
+'"#;
+
+fn bot(name: &str, number: u32) -> ProcessConfig {
+    let mut bot =
+        ProcessConfig::from_command_line(&format!("sh -c 'read go; echo {number}'")).unwrap();
+    bot.name = Some(name.to_string());
+    bot
+}
+
+fn config(format: Format, bots: Vec<ProcessConfig>) -> TournamentConfig {
+    TournamentConfig {
+        format,
+        referee: ProcessConfig::from_command_line(REFEREE).unwrap(),
+        bots,
+        concurrency: 4,
+        ..Default::default()
+    }
+}
+
+fn summary(standings: &Standings) -> Vec<(&str, usize, f64)> {
+    standings
+        .0
+        .iter()
+        .map(|standing| (standing.name.as_str(), standing.games, standing.points))
+        .collect()
+}
+
+#[tokio::test]
+async fn round_robin_plays_every_pairing_both_ways() {
+    let bots = vec![bot("weak", 1), bot("strong", 3), bot("medium", 2)];
+    let mut seats = Vec::new();
+    let standings = run_tournament(&config(Format::RoundRobin, bots), |game| {
+        seats.push(game.names.clone())
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        summary(&standings),
+        [("strong", 4, 4.0), ("medium", 4, 2.0), ("weak", 4, 0.0)]
+    );
+    assert_eq!(seats.len(), 6);
+    for pair in [["weak", "strong"], ["strong", "weak"], ["medium", "strong"]] {
+        assert!(seats.iter().any(|names| names == &pair), "{pair:?}");
+    }
+    let strong = &standings.0[0];
+    assert_eq!((strong.wins, strong.draws, strong.losses), (4, 0, 0));
+}
+
+#[tokio::test]
+async fn draws_are_half_points() {
+    let bots = vec![bot("a", 2), bot("b", 2)];
+    let standings = run_tournament(&config(Format::RoundRobin, bots), |_| {})
+        .await
+        .unwrap();
+    assert_eq!(summary(&standings), [("a", 2, 1.0), ("b", 2, 1.0)]);
+    assert_eq!(standings.0[0].draws, 2);
+}
+
+#[tokio::test]
+async fn gauntlet_only_plays_the_chosen_bot() {
+    let bots = vec![bot("weak", 1), bot("strong", 3), bot("medium", 2)];
+    let mut config = config(Format::Gauntlet, bots);
+    config.gauntlet = Some("medium".to_string());
+    config.games_per_pairing = 1;
+    let mut games = Vec::new();
+    let standings = run_tournament(&config, |game| games.push(game.names.clone()))
+        .await
+        .unwrap();
+    assert_eq!(games.len(), 2);
+    assert!(games.iter().all(|names| names[0] == "medium"));
+    assert_eq!(
+        summary(&standings),
+        [("strong", 1, 1.0), ("medium", 2, 1.0), ("weak", 1, 0.0)]
+    );
+}
+
+#[tokio::test]
+async fn swiss_pairs_by_score_and_hands_out_byes() {
+    let bots = vec![
+        bot("a", 1),
+        bot("b", 4),
+        bot("c", 3),
+        bot("d", 2),
+        bot("e", 5),
+    ];
+    let mut config = config(Format::Swiss, bots);
+    config.rounds = Some(3);
+    config.games_per_pairing = 1;
+    let mut rounds = Vec::new();
+    let standings = run_tournament(&config, |game| rounds.push(game.game.round))
+        .await
+        .unwrap();
+    // Two games a round, the fifth bot sits out
+    assert_eq!(rounds.len(), 6);
+    assert_eq!(standings.0[0].name, "e");
+    assert_eq!(
+        standings
+            .0
+            .iter()
+            .map(|standing| standing.byes)
+            .sum::<usize>(),
+        3
+    );
+    assert!(standings.0.iter().all(|standing| standing.byes <= 1));
+    // Round two is paired from round one's results, so rounds finish in order
+    assert!(rounds.windows(2).all(|pair| pair[0] <= pair[1]));
+}
+
+#[tokio::test]
+async fn games_without_a_result_are_not_scored() {
+    let mut config = config(Format::RoundRobin, vec![bot("a", 1), bot("b", 2)]);
+    config.referee = ProcessConfig::from_command_line("sh -c 'echo *:go'").unwrap();
+    let standings = run_tournament(&config, |game| assert!(game.result.is_none()))
+        .await
+        .unwrap();
+    assert_eq!(summary(&standings), [("a", 0, 0.0), ("b", 0, 0.0)]);
+    assert_eq!(standings.0[0].failed, 2);
+}
+
+#[tokio::test]
+async fn bots_need_different_names() {
+    let config = config(Format::RoundRobin, vec![bot("a", 1), bot("a", 2)]);
+    let err = run_tournament(&config, |_| {}).await.unwrap_err();
+    assert!(err.to_string().contains("Two bots are called 'a'"), "{err}");
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{start, ROUTINGS};
+use metamanager::{Match, MatchConfig, ProcessConfig};
+
+#[tokio::test]
+async fn bytes_are_counted_both_ways() {
+    for routing in ROUTINGS {
+        let mut game = start(routing, 2);
+        game.manager.send("0:hello").await;
+        game.players[0].expect("hello").await;
+        game.manager.send("*:all").await;
+        game.players[0].expect("all").await;
+        game.players[1].expect("all").await;
+        game.players[1].send("hi there").await;
+        game.manager.expect("1:hi there").await;
+        let report = game.finish().await.unwrap();
+        // Manager lines count with their tags, players see untagged lines
+        assert_eq!(report.manager.bytes_sent, 8 + 6);
+        assert_eq!(report.manager.bytes_received, 11);
+        assert_eq!(report.players[0].bytes_received, 6 + 4);
+        assert_eq!(report.players[1].bytes_received, 4);
+        assert_eq!(report.players[1].bytes_sent, 9);
+        // Nothing to measure CPU time of
+        assert_eq!(report.players[0].cpu_ms, None);
+    }
+}
+
+#[tokio::test]
+async fn processes_report_cpu_and_memory() {
+    let busy = "sh -c 'read go; i=0; while [ $i -lt 300000 ]; do i=$((i+1)); done; echo done'";
+    let config = MatchConfig {
+        manager: ProcessConfig::from_command_line("sh -c 'echo 0:go; echo 1:go; read a; read b'")
+            .unwrap(),
+        players: vec![
+            ProcessConfig::from_command_line(busy).unwrap(),
+            ProcessConfig::from_command_line("sh -c 'read go; echo done'").unwrap(),
+        ],
+        ..Default::default()
+    };
+    let report = Match::from_config(&config)
+        .await
+        .unwrap()
+        .run()
+        .await
+        .unwrap();
+    let (busy, idle) = (&report.players[0], &report.players[1]);
+    assert!(busy.cpu_ms.unwrap() >= 100, "{busy:?}");
+    assert!(idle.cpu_ms.unwrap() < busy.cpu_ms.unwrap(), "{idle:?}");
+    assert!(busy.peak_rss_kb.unwrap() > 0, "{busy:?}");
+    assert_eq!((busy.bytes_sent, busy.bytes_received), (5, 3));
+    assert!(report.manager.cpu_ms.is_some());
+}
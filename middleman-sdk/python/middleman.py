@@ -0,0 +1,216 @@
+#!/usr/bin/env python3
+"""
+Helpers for writing players in Python, speaking the same protocol as the Rust
+middleman-sdk crate. Subclass Player with the game's logic and hand it to run_player, or
+use a Connection directly for bots that would rather drive their own loop:
+
+    connection = Connection()
+    hello = connection.handshake("my_bot", "1", game="tictactoe")
+    role = connection.recv(int)
+    connection.send(4)
+
+Messages are framed the way the metamanager says the match is in MM_FRAMING, one per
+line or each behind a 4 byte big-endian length. Nothing here prints to stdout besides
+messages for the manager, debug output belongs on stderr.
+"""
+
+import os
+import struct
+import sys
+from dataclasses import dataclass
+from typing import Any, Callable, Optional, Tuple
+
+# Version of the metamanager's protocol this module speaks
+PROTOCOL_VERSION = 1
+
+# Environment variable the metamanager tells every process the match's --framing in
+FRAMING_VAR = "MM_FRAMING"
+FRAMINGS = ("lines", "length")
+
+# Largest message read or sent with length framing, the same as the metamanager's
+MAX_FRAME_LEN = 16 << 20
+
+
+class Closed(Exception):
+    """The metamanager closed stdin, the match is over for this player."""
+
+
+class HandshakeError(Exception):
+    """The hello couldn't be read or this player refused it."""
+
+
+@dataclass
+class Hello:
+    """
+    What the metamanager opens with when it runs with --handshake:
+      hello game=<game> protocol=<version> player=<index> players=<count> [time=<base>+<inc>]
+    """
+
+    game: str
+    protocol: int
+    # This player's index and how many play
+    player: int
+    players: int
+    # Chess clock base time and increment in seconds, if the match has a time control
+    time: Optional[Tuple[float, float]] = None
+
+    @staticmethod
+    def parse(line: str) -> "Hello":
+        words = line.split()
+        if not words or words[0] != "hello":
+            raise HandshakeError(f"'{line}' isn't a hello")
+        values = {}
+        for word in words[1:]:
+            # Unknown keys are fine, newer metamanagers may send more
+            key, _, value = word.partition("=")
+            values[key] = value
+        try:
+            time = None
+            if "time" in values:
+                base, _, increment = values["time"].partition("+")
+                time = (float(base), float(increment))
+            return Hello(
+                game=values["game"],
+                protocol=int(values["protocol"]),
+                player=int(values["player"]),
+                players=int(values["players"]),
+                time=time,
+            )
+        except (KeyError, ValueError) as err:
+            raise HandshakeError(f"'{line}' is missing a key or has a bad value: {err}")
+
+
+class Connection:
+    """
+    Messages to and from the metamanager, framed like the match. Without a framing it's
+    taken from MM_FRAMING, and an unknown one raises ValueError rather than misreading
+    every message. Length framing reads and writes bytes, so it needs binary streams.
+    """
+
+    def __init__(self, reader=None, writer=None, framing: Optional[str] = None):
+        self.framing = framing or os.environ.get(FRAMING_VAR, "lines")
+        if self.framing not in FRAMINGS:
+            raise ValueError(f"unknown framing '{self.framing}'")
+        binary = self.framing == "length"
+        self.reader = reader or (sys.stdin.buffer if binary else sys.stdin)
+        self.writer = writer or (sys.stdout.buffer if binary else sys.stdout)
+        # A line read while looking for a hello that wasn't one
+        self.pending = None
+
+    def _read_exactly(self, count: int) -> bytes:
+        data = b""
+        while len(data) < count:
+            chunk = self.reader.read(count - len(data))
+            if not chunk:
+                break
+            data += chunk
+        return data
+
+    def _recv_frame(self) -> str:
+        header = self._read_exactly(4)
+        # Closing between messages is the end, anywhere else it's a broken frame
+        if not header:
+            raise Closed()
+        if len(header) < 4:
+            raise EOFError(f"closed {len(header)} bytes into a 4 byte frame length")
+        (length,) = struct.unpack(">I", header)
+        if length > MAX_FRAME_LEN:
+            raise ValueError(f"{length} byte frame is over the {MAX_FRAME_LEN} byte limit")
+        message = self._read_exactly(length)
+        if len(message) < length:
+            raise EOFError(f"closed {len(message)} bytes into a {length} byte frame")
+        return message.decode()
+
+    def recv_line(self) -> str:
+        """
+        The next message without its line ending or length, raises Closed once stdin is
+        closed.
+        """
+        if self.pending is not None:
+            line, self.pending = self.pending, None
+            return line
+        if self.framing == "length":
+            return self._recv_frame()
+        line = self.reader.readline()
+        if not line:
+            raise Closed()
+        return line.rstrip("\r\n")
+
+    def recv(self, parse: Callable[[str], Any] = str) -> Any:
+        """The next line, parsed, e.g. recv(int)."""
+        return parse(self.recv_line().strip())
+
+    def send(self, message: Any):
+        """Send one message, flushed right away so the manager isn't kept waiting."""
+        if self.framing == "length":
+            data = str(message).encode()
+            if len(data) > MAX_FRAME_LEN:
+                raise ValueError(f"message is over the {MAX_FRAME_LEN} byte limit")
+            self.writer.write(struct.pack(">I", len(data)) + data)
+            self.writer.flush()
+        else:
+            print(message, file=self.writer, flush=True)
+
+    def handshake(
+        self, name: str, version: str, game: Optional[str] = None
+    ) -> Optional[Hello]:
+        """
+        Answer the hello if the metamanager opens with one, returns None if it didn't.
+        With a game, a hello for any other game is refused with HandshakeError.
+        """
+        line = self.recv_line()
+        if not line.startswith("hello "):
+            self.pending = line
+            return None
+        hello = Hello.parse(line)
+        refusal = None
+        if hello.protocol != PROTOCOL_VERSION:
+            refusal = f"speaks protocol {PROTOCOL_VERSION}, not {hello.protocol}"
+        elif game is not None and game != hello.game:
+            refusal = f"plays {game}, not {hello.game}"
+        if refusal is not None:
+            self.send(f"error {refusal}")
+            raise HandshakeError(refusal)
+        answer = f"hello name={name} version={version} protocol={PROTOCOL_VERSION}"
+        if game is not None:
+            answer += f" game={game}"
+        self.send(answer)
+        return hello
+
+
+class Player:
+    """
+    A bot. It's sent whatever the manager says to it, one message at a time, and answers
+    some of them. Set name and version, and game to refuse hellos for other games.
+    """
+
+    name = "player"
+    version = "0"
+    game: Optional[str] = None
+
+    def parse(self, line: str) -> Any:
+        """Turn a message from the manager into whatever on_message takes."""
+        return line
+
+    def on_hello(self, hello: Hello):
+        """The metamanager's hello, only sent when the match has a handshake."""
+
+    def on_message(self, message: Any) -> Optional[Any]:
+        """Answer a message from the manager, or don't by returning None."""
+        raise NotImplementedError
+
+
+def run_player(player: Player, connection: Optional[Connection] = None):
+    """Play over stdin and stdout until the manager is done with the player."""
+    connection = connection or Connection()
+    try:
+        hello = connection.handshake(player.name, player.version, player.game)
+        if hello is not None:
+            player.on_hello(hello)
+        while True:
+            answer = player.on_message(player.parse(connection.recv_line().strip()))
+            if answer is not None:
+                connection.send(answer)
+    except Closed:
+        # The metamanager closes stdin once the match is over
+        pass
@@ -0,0 +1,222 @@
+use crate::error::{Error, Result};
+use crate::referee::DELIM;
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Write};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// Environment variables the metamanager tells every process the match's --delim and
+// --framing in
+pub const DELIM_VAR: &str = "MM_DELIM";
+pub const FRAMING_VAR: &str = "MM_FRAMING";
+
+// Largest message read or sent with length framing, the same as the metamanager's
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+
+// How messages are told apart, like the metamanager's --framing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    // One message per line
+    #[default]
+    Lines,
+    // A 4 byte big-endian length, then that many bytes
+    Length,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(framing: &str) -> std::result::Result<Framing, String> {
+        match framing {
+            "lines" => Ok(Framing::Lines),
+            "length" => Ok(Framing::Length),
+            _ => Err(format!("unknown framing '{framing}'")),
+        }
+    }
+}
+
+// A setting the metamanager passed in var, default if it didn't
+fn from_env<T>(var: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(var) {
+        Ok(value) => value
+            .parse()
+            .map_err(|err: T::Err| Error::Config(format!("{var}={value}: {err}"))),
+        Err(_) => Ok(default),
+    }
+}
+
+// One message, without its framing
+fn read_message(reader: &mut impl BufRead, framing: Framing) -> Option<io::Result<String>> {
+    let bytes = match framing {
+        Framing::Lines => {
+            let mut line = String::new();
+            return match reader.read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    if line.ends_with('\n') {
+                        line.pop();
+                    }
+                    Some(Ok(line))
+                }
+                Err(err) => Some(Err(err)),
+            };
+        }
+        Framing::Length => {
+            // Closing between messages is the end, anywhere else it's a broken frame
+            let mut len = [0; 4];
+            let mut filled = 0;
+            while filled < len.len() {
+                match reader.read(&mut len[filled..]) {
+                    Ok(0) if filled == 0 => return None,
+                    Ok(0) => return Some(Err(io::ErrorKind::UnexpectedEof.into())),
+                    Ok(read) => filled += read,
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            let len = u32::from_be_bytes(len) as usize;
+            if len > MAX_FRAME_LEN {
+                let err = format!("{len} byte frame is over the {MAX_FRAME_LEN} byte limit");
+                return Some(Err(io::Error::new(io::ErrorKind::InvalidData, err)));
+            }
+            let mut message = vec![0; len];
+            if let Err(err) = reader.read_exact(&mut message) {
+                return Some(Err(err));
+            }
+            message
+        }
+    };
+    Some(String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+// Messages to and from the metamanager, framed like the match. Messages are read on a
+// thread of their own, so reads can time out
+pub struct Connection {
+    messages: Receiver<io::Result<String>>,
+    writer: Box<dyn Write + Send>,
+    timeout: Option<Duration>,
+    framing: Framing,
+    delim: char,
+}
+
+impl Connection {
+    // Talk over stdin and stdout, like every process the metamanager runs, with the
+    // delim and framing it says the match uses
+    pub fn stdio() -> Result<Connection> {
+        let framing = from_env(FRAMING_VAR, Framing::Lines)?;
+        let delim = from_env(DELIM_VAR, DELIM)?;
+        Ok(
+            Connection::framed(BufReader::new(io::stdin()), io::stdout(), framing)
+                .with_delim(delim),
+        )
+    }
+
+    // A connection with line framing and the default delim
+    pub fn new(
+        reader: impl BufRead + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> Connection {
+        Connection::framed(reader, writer, Framing::Lines)
+    }
+
+    pub fn framed(
+        mut reader: impl BufRead + Send + 'static,
+        writer: impl Write + Send + 'static,
+        framing: Framing,
+    ) -> Connection {
+        let (sender, messages) = channel();
+        thread::spawn(move || {
+            while let Some(message) = read_message(&mut reader, framing) {
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Connection {
+            messages,
+            writer: Box::new(writer),
+            timeout: None,
+            framing,
+            delim: DELIM,
+        }
+    }
+
+    // What separates a referee's tags from messages, DELIM unless set
+    pub fn with_delim(mut self, delim: char) -> Connection {
+        self.delim = delim;
+        self
+    }
+
+    pub fn delim(&self) -> char {
+        self.delim
+    }
+
+    // How long a read waits before failing with Error::Timeout, None waits forever
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    // The next message, without its line ending or length
+    pub fn recv_line(&mut self) -> Result<String> {
+        let line = match self.timeout {
+            Some(timeout) => match self.messages.recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout(timeout)),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::Closed),
+            },
+            None => self.messages.recv().map_err(|_| Error::Closed)?,
+        };
+        let mut line = line?;
+        if line.ends_with('\r') {
+            line.pop();
+        }
+        Ok(line)
+    }
+
+    // The next line, parsed
+    pub fn recv<T>(&mut self) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        parse(&self.recv_line()?)
+    }
+
+    // Send one message, framed
+    pub fn send_line(&mut self, line: &str) -> Result<()> {
+        match self.framing {
+            Framing::Lines => writeln!(self.writer, "{line}")?,
+            Framing::Length => {
+                if line.len() > MAX_FRAME_LEN {
+                    let err = format!("message is over the {MAX_FRAME_LEN} byte limit");
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, err).into());
+                }
+                self.writer.write_all(&(line.len() as u32).to_be_bytes())?;
+                self.writer.write_all(line.as_bytes())?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn send(&mut self, message: &impl Display) -> Result<()> {
+        self.send_line(&message.to_string())
+    }
+}
+
+pub(crate) fn parse<T>(message: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    message.trim().parse().map_err(|err: T::Err| Error::Parse {
+        message: message.to_string(),
+        reason: err.to_string(),
+    })
+}
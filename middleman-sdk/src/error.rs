@@ -0,0 +1,44 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+// Everything that can go wrong talking to the metamanager
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The other side closed the pipe, for a player that means the match is over
+    Closed,
+    // Nothing arrived in time
+    Timeout(Duration),
+    // A message didn't parse as what it was read as
+    Parse { message: String, reason: String },
+    // The metamanager's hello didn't fit, e.g. it's for another game
+    Handshake(String),
+    // How the metamanager said the match is set up makes no sense, e.g. an unknown framing
+    Config(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Closed => write!(f, "the metamanager closed the connection"),
+            Error::Timeout(timeout) => {
+                write!(f, "nothing arrived within {}ms", timeout.as_millis())
+            }
+            Error::Parse { message, reason } => write!(f, "couldn't parse '{message}': {reason}"),
+            Error::Handshake(reason) => write!(f, "handshake failed: {reason}"),
+            Error::Config(reason) => write!(f, "bad match settings: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
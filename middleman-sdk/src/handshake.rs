@@ -0,0 +1,114 @@
+use crate::connection::parse;
+use crate::error::{Error, Result};
+use std::time::Duration;
+
+// Version of the metamanager's protocol this crate speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// What the metamanager opens with when it runs with --handshake:
+//   hello game=<game> protocol=<version> player=<index> players=<count> [time=<base>+<inc>]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub game: String,
+    pub protocol: u32,
+    // This player's index and how many play
+    pub player: usize,
+    pub players: usize,
+    // Chess clock base time and increment, if the match has a time control
+    pub time: Option<(Duration, Duration)>,
+}
+
+fn seconds(value: &str) -> Result<Duration> {
+    let seconds: f64 = parse(value)?;
+    Duration::try_from_secs_f64(seconds).map_err(|err| Error::Parse {
+        message: value.to_string(),
+        reason: err.to_string(),
+    })
+}
+
+impl Hello {
+    pub fn parse(line: &str) -> Result<Hello> {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("hello") {
+            return Err(Error::Handshake(format!("'{line}' isn't a hello")));
+        }
+        let (mut game, mut protocol, mut player, mut players, mut time) =
+            (None, None, None, None, None);
+        for word in words {
+            // Unknown keys are fine, newer metamanagers may send more
+            match word.split_once('=') {
+                Some(("game", value)) => game = Some(value.to_string()),
+                Some(("protocol", value)) => protocol = Some(parse(value)?),
+                Some(("player", value)) => player = Some(parse(value)?),
+                Some(("players", value)) => players = Some(parse(value)?),
+                Some(("time", value)) => {
+                    let (base, increment) = value.split_once('+').ok_or_else(|| {
+                        Error::Handshake(format!("'{value}' isn't a time control"))
+                    })?;
+                    time = Some((seconds(base)?, seconds(increment)?));
+                }
+                _ => {}
+            }
+        }
+        match (game, protocol, player, players) {
+            (Some(game), Some(protocol), Some(player), Some(players)) => Ok(Hello {
+                game,
+                protocol,
+                player,
+                players,
+                time,
+            }),
+            _ => Err(Error::Handshake(format!("'{line}' is missing a key"))),
+        }
+    }
+}
+
+// Who a player says it is when the metamanager asks
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub version: String,
+    // The game it plays, a hello for any other game is refused
+    pub game: Option<String>,
+}
+
+impl Identity {
+    pub fn new(name: &str, version: &str) -> Identity {
+        Identity {
+            name: name.to_string(),
+            version: version.to_string(),
+            game: None,
+        }
+    }
+
+    pub fn game(mut self, game: &str) -> Identity {
+        self.game = Some(game.to_string());
+        self
+    }
+
+    // Why this player can't take part in the match hello is for, if it can't
+    pub(crate) fn refusal(&self, hello: &Hello) -> Option<String> {
+        if hello.protocol != PROTOCOL_VERSION {
+            return Some(format!(
+                "speaks protocol {PROTOCOL_VERSION}, not {}",
+                hello.protocol
+            ));
+        }
+        match &self.game {
+            Some(game) if *game != hello.game => Some(format!("plays {game}, not {}", hello.game)),
+            _ => None,
+        }
+    }
+
+    // The answer to a hello
+    pub(crate) fn answer(&self) -> String {
+        let mut answer = format!(
+            "hello name={} version={} protocol={PROTOCOL_VERSION}",
+            self.name, self.version
+        );
+        if let Some(game) = &self.game {
+            answer.push_str(&format!(" game={game}"));
+        }
+        answer
+    }
+}
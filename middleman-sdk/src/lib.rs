@@ -0,0 +1,14 @@
+// Helpers for writing players and referees (managers) that the metamanager runs. Implement
+// Player or Referee with the game's logic and hand it to run_player or run_referee, which
+// take care of stdin/stdout, the handshake, tags, control messages and timeouts
+mod connection;
+mod error;
+mod handshake;
+mod player;
+mod referee;
+
+pub use connection::{Connection, Framing, DELIM_VAR, FRAMING_VAR, MAX_FRAME_LEN};
+pub use error::{Error, Result};
+pub use handshake::{Hello, Identity, PROTOCOL_VERSION};
+pub use player::{run_player, run_player_on, Player};
+pub use referee::{run_referee, run_referee_on, Control, Outbox, Outcome, Referee, DELIM};
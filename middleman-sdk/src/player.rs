@@ -0,0 +1,80 @@
+use crate::connection::{parse, Connection};
+use crate::error::{Error, Result};
+use crate::handshake::{Hello, Identity};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+// A bot. It's sent whatever the manager says to it, one message at a time, and answers
+// some of them
+pub trait Player {
+    // What the manager sends it
+    type Incoming: FromStr;
+    // What it answers with
+    type Outgoing: Display;
+
+    // Who to say it is if the metamanager asks
+    fn identity(&self) -> Identity;
+
+    // The metamanager's hello, only sent when the match has a handshake
+    fn on_hello(&mut self, _hello: &Hello) {}
+
+    // Answer a message from the manager, or don't
+    fn on_message(&mut self, message: Self::Incoming) -> Option<Self::Outgoing>;
+
+    // How long to wait for the next message before on_timeout, None waits forever
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    // Nothing arrived within timeout, whatever it returns is sent
+    fn on_timeout(&mut self) -> Option<Self::Outgoing> {
+        None
+    }
+}
+
+// Play over stdin and stdout until the manager is done with the player
+pub fn run_player<P>(player: P) -> Result<()>
+where
+    P: Player,
+    <P::Incoming as FromStr>::Err: Display,
+{
+    run_player_on(player, Connection::stdio()?)
+}
+
+pub fn run_player_on<P>(mut player: P, mut connection: Connection) -> Result<()>
+where
+    P: Player,
+    <P::Incoming as FromStr>::Err: Display,
+{
+    let mut first = true;
+    loop {
+        connection.set_timeout(player.timeout());
+        let line = match connection.recv_line() {
+            Ok(line) => line,
+            Err(Error::Timeout(_)) => {
+                if let Some(answer) = player.on_timeout() {
+                    connection.send(&answer)?;
+                }
+                continue;
+            }
+            // The metamanager closes stdin once the match is over
+            Err(Error::Closed) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if std::mem::take(&mut first) && line.starts_with("hello ") {
+            let hello = Hello::parse(&line)?;
+            let identity = player.identity();
+            if let Some(reason) = identity.refusal(&hello) {
+                connection.send_line(&format!("error {reason}"))?;
+                return Err(Error::Handshake(reason));
+            }
+            connection.send_line(&identity.answer())?;
+            player.on_hello(&hello);
+            continue;
+        }
+        if let Some(answer) = player.on_message(parse(&line)?) {
+            connection.send(&answer)?;
+        }
+    }
+}
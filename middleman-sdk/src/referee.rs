@@ -0,0 +1,283 @@
+use crate::connection::{parse, Connection};
+use crate::error::{Error, Result};
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::time::Duration;
+
+// What separates a player tag from the message, the metamanager's default --delim.
+// Referees use the one the match was started with, see Connection::delim
+pub const DELIM: char = ':';
+
+// Tag of the metamanager's own messages
+const CONTROL_TAG: &str = "mm";
+
+// What the metamanager tells the referee, besides player messages
+#[derive(Clone, Debug, PartialEq)]
+pub enum Control {
+    // A player's clock ran out
+    Timeout(usize),
+    // How long a player took, after each of its messages with --report-times
+    Time {
+        player: usize,
+        elapsed_ms: u64,
+        remaining_ms: Option<u64>,
+    },
+    // A player lost outside the game, e.g. by breaking a sandbox limit or crashing
+    Forfeit {
+        player: usize,
+        reason: String,
+    },
+    // A player crashed, Outbox::restart brings it back if the match allows restarts
+    Disconnect(usize),
+    // A crashed player is back and caught up
+    Restarted(usize),
+    // Who a player said it was in the handshake
+    Player {
+        player: usize,
+        name: String,
+        version: String,
+    },
+    // Anything this version of the crate doesn't know about
+    Other(String),
+}
+
+impl Control {
+    pub fn parse(message: &str) -> Control {
+        let words = message.split_whitespace().collect::<Vec<_>>();
+        let number = |idx: usize| words.get(idx).and_then(|word| word.parse().ok());
+        let value = |idx: usize, key: &str| {
+            let word = words.get(idx)?.strip_prefix(key)?;
+            word.strip_prefix('=').map(str::to_string)
+        };
+        let control = match words.first().copied() {
+            Some("timeout") => number(1).map(Control::Timeout),
+            Some("time") => number(1)
+                .zip(number(2))
+                .map(|(player, elapsed_ms)| Control::Time {
+                    player,
+                    elapsed_ms: elapsed_ms as u64,
+                    remaining_ms: number(3).map(|remaining| remaining as u64),
+                }),
+            Some("forfeit") => number(1).map(|player| Control::Forfeit {
+                player,
+                reason: words[2..].join(" "),
+            }),
+            Some("disconnect") => number(1).map(Control::Disconnect),
+            Some("restarted") => number(1).map(Control::Restarted),
+            Some("player") => number(1).and_then(|player| {
+                Some(Control::Player {
+                    player,
+                    name: value(2, "name")?,
+                    version: value(3, "version")?,
+                })
+            }),
+            _ => None,
+        };
+        control.unwrap_or_else(|| Control::Other(message.to_string()))
+    }
+}
+
+// How a match ended, one score per player and why
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub scores: Vec<f64>,
+    pub reason: String,
+}
+
+impl Outcome {
+    // winner scores 1, everyone else 0
+    pub fn win(winner: usize, players: usize, reason: &str) -> Outcome {
+        let mut scores = vec![0.0; players];
+        scores[winner] = 1.0;
+        Outcome {
+            scores,
+            reason: reason.to_string(),
+        }
+    }
+
+    // loser scores 0, everyone else 1
+    pub fn loss(loser: usize, players: usize, reason: &str) -> Outcome {
+        let mut scores = vec![1.0; players];
+        scores[loser] = 0.0;
+        Outcome {
+            scores,
+            reason: reason.to_string(),
+        }
+    }
+
+    // Everyone gets an equal share
+    pub fn draw(players: usize, reason: &str) -> Outcome {
+        Outcome {
+            scores: vec![1.0 / players as f64; players],
+            reason: reason.to_string(),
+        }
+    }
+}
+
+// As the result report without its tag, `result <scores> <reason>`
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "result")?;
+        for score in &self.scores {
+            write!(f, " {score}")?;
+        }
+        if !self.reason.is_empty() {
+            write!(f, " {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+// Messages a referee sends while handling something, they go out once it returns
+pub struct Outbox<T> {
+    delim: char,
+    lines: Vec<String>,
+    message: PhantomData<T>,
+}
+
+impl<T: Display> Outbox<T> {
+    fn new(delim: char) -> Outbox<T> {
+        Outbox {
+            delim,
+            lines: Vec::new(),
+            message: PhantomData,
+        }
+    }
+
+    pub fn send(&mut self, player: usize, message: &T) {
+        self.lines.push(format!("{player}{}{message}", self.delim));
+    }
+
+    pub fn send_to(&mut self, players: &[usize], message: &T) {
+        let players = players.iter().map(usize::to_string).collect::<Vec<_>>();
+        self.lines
+            .push(format!("{}{}{message}", players.join(","), self.delim));
+    }
+
+    pub fn broadcast(&mut self, message: &T) {
+        self.lines.push(format!("*{}{message}", self.delim));
+    }
+
+    // Shown to spectators watching the public feed
+    pub fn spectators(&mut self, message: &impl Display) {
+        self.lines.push(format!("spec{}{message}", self.delim));
+    }
+
+    // Ask for a crashed player to be brought back, see Control::Disconnect
+    pub fn restart(&mut self, player: usize) {
+        self.lines
+            .push(format!("{CONTROL_TAG}{}restart {player}", self.delim));
+    }
+}
+
+// The manager of a game. It's told about every player message and control message, and
+// decides when the match is over by returning an Outcome
+pub trait Referee {
+    // What players send
+    type Incoming: FromStr;
+    // What players are sent
+    type Outgoing: Display;
+
+    fn players(&self) -> usize;
+
+    // Send the opening messages
+    fn start(&mut self, out: &mut Outbox<Self::Outgoing>) -> Option<Outcome>;
+
+    fn on_message(
+        &mut self,
+        player: usize,
+        message: Self::Incoming,
+        out: &mut Outbox<Self::Outgoing>,
+    ) -> Option<Outcome>;
+
+    // A player sent something that doesn't parse, they lose by default
+    fn on_invalid(
+        &mut self,
+        player: usize,
+        _error: &Error,
+        _out: &mut Outbox<Self::Outgoing>,
+    ) -> Option<Outcome> {
+        Some(Outcome::loss(player, self.players(), "invalid input"))
+    }
+
+    // By default running out of time or forfeiting loses, and crashed players are
+    // restarted if the match allows it
+    fn on_control(
+        &mut self,
+        control: Control,
+        out: &mut Outbox<Self::Outgoing>,
+    ) -> Option<Outcome> {
+        match control {
+            Control::Timeout(player) => Some(Outcome::loss(player, self.players(), "timeout")),
+            Control::Forfeit { player, reason } => {
+                Some(Outcome::loss(player, self.players(), &reason))
+            }
+            Control::Disconnect(player) => {
+                out.restart(player);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // How long to wait for the next message before on_timeout, None waits forever
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn on_timeout(&mut self, _out: &mut Outbox<Self::Outgoing>) -> Option<Outcome> {
+        None
+    }
+}
+
+// Referee a match over stdin and stdout, with the delim and framing the metamanager
+// says the match uses. Returns the outcome once the referee decides
+// one, which is also reported to the metamanager, or None if every player left first
+pub fn run_referee<R>(referee: R) -> Result<Option<Outcome>>
+where
+    R: Referee,
+    <R::Incoming as FromStr>::Err: Display,
+{
+    run_referee_on(referee, Connection::stdio()?)
+}
+
+pub fn run_referee_on<R>(mut referee: R, mut connection: Connection) -> Result<Option<Outcome>>
+where
+    R: Referee,
+    <R::Incoming as FromStr>::Err: Display,
+{
+    let delim = connection.delim();
+    let mut out = Outbox::new(delim);
+    let mut outcome = referee.start(&mut out);
+    loop {
+        for line in out.lines.drain(..) {
+            connection.send_line(&line)?;
+        }
+        if let Some(outcome) = outcome {
+            connection.send_line(&format!("{CONTROL_TAG}{delim}{outcome}"))?;
+            return Ok(Some(outcome));
+        }
+        connection.set_timeout(referee.timeout());
+        let line = match connection.recv_line() {
+            Ok(line) => line,
+            Err(Error::Timeout(_)) => {
+                outcome = referee.on_timeout(&mut out);
+                continue;
+            }
+            Err(Error::Closed) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        outcome = match line.split_once(delim) {
+            Some((CONTROL_TAG, control)) => referee.on_control(Control::parse(control), &mut out),
+            Some((tag, message)) => match tag.parse::<usize>() {
+                Ok(player) => match parse(message) {
+                    Ok(message) => referee.on_message(player, message, &mut out),
+                    Err(err) => referee.on_invalid(player, &err, &mut out),
+                },
+                Err(_) => None,
+            },
+            None => None,
+        };
+    }
+}
@@ -0,0 +1,99 @@
+// In-memory stand-ins for the metamanager's end of stdin and stdout
+#![allow(dead_code)]
+
+use middleman_sdk::{Connection, Framing};
+use std::io::{BufReader, Cursor, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Everything written to it, shared with the test
+#[derive(Clone, Default)]
+pub struct Written(Arc<Mutex<Vec<u8>>>);
+
+impl Written {
+    pub fn lines(&self) -> Vec<String> {
+        let written = self.0.lock().unwrap();
+        String::from_utf8(written.clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    // What was written, split at each 4 byte length
+    pub fn frames(&self) -> Vec<String> {
+        let written = self.0.lock().unwrap();
+        let mut rest = &written[..];
+        let mut frames = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            frames.push(String::from_utf8(rest[4..4 + len].to_vec()).unwrap());
+            rest = &rest[4 + len..];
+        }
+        frames
+    }
+}
+
+impl Write for Written {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Gives one line at a time, each after a delay, like a manager taking its time
+struct Delayed {
+    lines: Vec<Vec<u8>>,
+    delay: Duration,
+}
+
+impl Read for Delayed {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.lines.is_empty() {
+            return Ok(0);
+        }
+        std::thread::sleep(self.delay);
+        let line = self.lines.remove(0);
+        buf[..line.len()].copy_from_slice(&line);
+        Ok(line.len())
+    }
+}
+
+// A connection reading input's lines, each after delay, then hitting EOF
+pub fn delayed(input: &[&str], delay: Duration) -> (Connection, Written) {
+    let lines = input.iter().map(|line| format!("{line}\n").into_bytes());
+    let reader = Delayed {
+        lines: lines.collect(),
+        delay,
+    };
+    let written = Written::default();
+    (
+        Connection::new(BufReader::new(reader), written.clone()),
+        written,
+    )
+}
+
+// A connection reading input's lines, then hitting EOF
+pub fn scripted(input: &[&str]) -> (Connection, Written) {
+    let mut script = input.join("\n");
+    script.push('\n');
+    let written = Written::default();
+    let connection = Connection::new(Cursor::new(script.into_bytes()), written.clone());
+    (connection, written)
+}
+
+// Like scripted, with every message behind its length
+pub fn length_framed(input: &[&str]) -> (Connection, Written) {
+    let mut script = Vec::new();
+    for message in input {
+        script.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        script.extend_from_slice(message.as_bytes());
+    }
+    let written = Written::default();
+    let connection = Connection::framed(Cursor::new(script), written.clone(), Framing::Length);
+    (connection, written)
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{delayed, scripted};
+use middleman_sdk::{run_player_on, Error, Hello, Identity, Player};
+use std::time::Duration;
+
+// Answers every number with the next one
+#[derive(Default)]
+struct Counter {
+    hello: Option<Hello>,
+}
+
+impl Player for Counter {
+    type Incoming = u32;
+    type Outgoing = u32;
+
+    fn identity(&self) -> Identity {
+        Identity::new("counter", "1.2").game("counting")
+    }
+
+    fn on_hello(&mut self, hello: &Hello) {
+        self.hello = Some(hello.clone());
+    }
+
+    fn on_message(&mut self, message: u32) -> Option<u32> {
+        (message < 10).then_some(message + 1)
+    }
+}
+
+#[test]
+fn answers_messages_until_the_manager_is_done() {
+    let (connection, written) = scripted(&["1", "5", "10"]);
+    run_player_on(Counter::default(), connection).unwrap();
+    assert_eq!(written.lines(), ["2", "6"]);
+}
+
+#[test]
+fn answers_the_hello() {
+    let hello = "hello game=counting protocol=1 player=1 players=2 time=60+0.5";
+    let (connection, written) = scripted(&[hello, "3"]);
+    run_player_on(Counter::default(), connection).unwrap();
+    assert_eq!(
+        written.lines(),
+        [
+            "hello name=counter version=1.2 protocol=1 game=counting",
+            "4"
+        ]
+    );
+    assert_eq!(
+        Hello::parse(hello).unwrap(),
+        Hello {
+            game: "counting".to_string(),
+            protocol: 1,
+            player: 1,
+            players: 2,
+            time: Some((Duration::from_secs(60), Duration::from_millis(500))),
+        }
+    );
+}
+
+#[test]
+fn refuses_other_games() {
+    let hello = "hello game=chess protocol=1 player=0 players=2";
+    let (connection, written) = scripted(&[hello, "3"]);
+    let err = run_player_on(Counter::default(), connection).unwrap_err();
+    assert!(matches!(err, Error::Handshake(_)), "{err}");
+    assert_eq!(written.lines(), ["error plays counting, not chess"]);
+}
+
+#[test]
+fn messages_that_dont_parse_are_errors() {
+    let (connection, written) = scripted(&["1", "two"]);
+    let err = run_player_on(Counter::default(), connection).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "couldn't parse 'two': invalid digit found in string"
+    );
+    assert_eq!(written.lines(), ["2"]);
+}
+
+// Says 0 the first time the manager keeps it waiting
+#[derive(Default)]
+struct Impatient {
+    waited: bool,
+}
+
+impl Player for Impatient {
+    type Incoming = u32;
+    type Outgoing = u32;
+
+    fn identity(&self) -> Identity {
+        Identity::new("impatient", "1.0")
+    }
+
+    fn on_message(&mut self, message: u32) -> Option<u32> {
+        Some(message + 1)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(10))
+    }
+
+    fn on_timeout(&mut self) -> Option<u32> {
+        (!std::mem::replace(&mut self.waited, true)).then_some(0)
+    }
+}
+
+#[test]
+fn times_out_waiting_for_the_manager() {
+    let (connection, written) = delayed(&["1"], Duration::from_millis(200));
+    run_player_on(Impatient::default(), connection).unwrap();
+    assert_eq!(written.lines(), ["0", "2"]);
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use common::{length_framed, scripted};
+use middleman_sdk::{
+    run_referee_on, Connection, Control, Error, Framing, Outbox, Outcome, Referee, MAX_FRAME_LEN,
+};
+use std::io::Cursor;
+use std::time::Duration;
+
+// Two players take turns saying numbers, whoever says 3 first wins
+#[derive(Default)]
+struct FirstToThree {
+    turn: usize,
+    controls: Vec<Control>,
+}
+
+impl Referee for FirstToThree {
+    type Incoming = u32;
+    type Outgoing = String;
+
+    fn players(&self) -> usize {
+        2
+    }
+
+    fn start(&mut self, out: &mut Outbox<String>) -> Option<Outcome> {
+        out.broadcast(&"go".to_string());
+        out.send(0, &"your turn".to_string());
+        None
+    }
+
+    fn on_message(
+        &mut self,
+        player: usize,
+        message: u32,
+        out: &mut Outbox<String>,
+    ) -> Option<Outcome> {
+        if player != self.turn {
+            return Some(Outcome::loss(player, 2, "out of turn"));
+        }
+        if message == 3 {
+            return Some(Outcome::win(player, 2, "said three"));
+        }
+        self.turn = 1 - player;
+        out.send(self.turn, &format!("{player} said {message}"));
+        None
+    }
+
+    fn on_control(&mut self, control: Control, _out: &mut Outbox<String>) -> Option<Outcome> {
+        self.controls.push(control);
+        None
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(5))
+    }
+}
+
+#[test]
+fn referees_until_there_is_an_outcome() {
+    let (connection, written) = scripted(&["0:1", "mm:time 0 12 3400", "1:2", "0:3", "1:4"]);
+    let outcome = run_referee_on(FirstToThree::default(), connection).unwrap();
+    assert_eq!(outcome, Some(Outcome::win(0, 2, "said three")));
+    assert_eq!(
+        written.lines(),
+        [
+            "*:go",
+            "0:your turn",
+            "1:0 said 1",
+            "0:1 said 2",
+            "mm:result 1 0 said three"
+        ]
+    );
+}
+
+#[test]
+fn invalid_input_loses_by_default() {
+    let (connection, written) = scripted(&["0:one"]);
+    let outcome = run_referee_on(FirstToThree::default(), connection).unwrap();
+    assert_eq!(outcome, Some(Outcome::loss(0, 2, "invalid input")));
+    assert_eq!(
+        written.lines().last().unwrap(),
+        "mm:result 0 1 invalid input"
+    );
+}
+
+#[test]
+fn uses_the_matchs_delim() {
+    let (connection, written) = scripted(&["0|1", "mm|time 0 12", "1|3"]);
+    let connection = connection.with_delim('|');
+    let outcome = run_referee_on(FirstToThree::default(), connection).unwrap();
+    assert_eq!(outcome, Some(Outcome::win(1, 2, "said three")));
+    assert_eq!(
+        written.lines(),
+        [
+            "*|go",
+            "0|your turn",
+            "1|0 said 1",
+            "mm|result 0 1 said three"
+        ]
+    );
+}
+
+#[test]
+fn uses_the_matchs_framing() {
+    let (connection, written) = length_framed(&["0:1", "1:3"]);
+    let outcome = run_referee_on(FirstToThree::default(), connection).unwrap();
+    assert_eq!(outcome, Some(Outcome::win(1, 2, "said three")));
+    assert_eq!(
+        written.frames(),
+        [
+            "*:go",
+            "0:your turn",
+            "1:0 said 1",
+            "mm:result 0 1 said three"
+        ]
+    );
+}
+
+#[test]
+fn frames_over_the_limit_are_errors() {
+    let len = (MAX_FRAME_LEN as u32 + 1).to_be_bytes();
+    let connection = Connection::framed(Cursor::new(len.to_vec()), Vec::new(), Framing::Length);
+    let err = run_referee_on(FirstToThree::default(), connection).unwrap_err();
+    assert!(matches!(err, Error::Io(_)), "{err}");
+    assert!(err.to_string().contains("limit"), "{err}");
+}
+
+#[test]
+fn players_leaving_ends_without_an_outcome() {
+    let (connection, _) = scripted(&["0:1"]);
+    assert_eq!(
+        run_referee_on(FirstToThree::default(), connection).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn control_messages_are_parsed() {
+    let cases = [
+        ("timeout 1", Control::Timeout(1)),
+        (
+            "time 0 12",
+            Control::Time {
+                player: 0,
+                elapsed_ms: 12,
+                remaining_ms: None,
+            },
+        ),
+        (
+            "forfeit 1 cpu limit",
+            Control::Forfeit {
+                player: 1,
+                reason: "cpu limit".to_string(),
+            },
+        ),
+        ("disconnect 0", Control::Disconnect(0)),
+        ("restarted 0", Control::Restarted(0)),
+        (
+            "player 1 name=bot version=2",
+            Control::Player {
+                player: 1,
+                name: "bot".to_string(),
+                version: "2".to_string(),
+            },
+        ),
+        ("timeout x", Control::Other("timeout x".to_string())),
+    ];
+    for (message, control) in cases {
+        assert_eq!(Control::parse(message), control, "{message}");
+    }
+}
+
+// The defaults a referee gets without overriding on_control
+struct Defaults;
+
+impl Referee for Defaults {
+    type Incoming = u32;
+    type Outgoing = u32;
+
+    fn players(&self) -> usize {
+        2
+    }
+
+    fn start(&mut self, _out: &mut Outbox<u32>) -> Option<Outcome> {
+        None
+    }
+
+    fn on_message(
+        &mut self,
+        _player: usize,
+        _message: u32,
+        _out: &mut Outbox<u32>,
+    ) -> Option<Outcome> {
+        None
+    }
+}
+
+#[test]
+fn crashed_players_are_restarted_and_forfeits_lose() {
+    let (connection, written) = scripted(&["mm:disconnect 1", "mm:forfeit 1 crashed"]);
+    let outcome = run_referee_on(Defaults, connection).unwrap();
+    assert_eq!(outcome, Some(Outcome::loss(1, 2, "crashed")));
+    assert_eq!(written.lines(), ["mm:restart 1", "mm:result 1 0 crashed"]);
+}
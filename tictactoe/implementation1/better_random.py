@@ -1,6 +1,16 @@
 #!/usr/bin/env python3
+import os
 import sys
 
+# The Python helpers live next to the Rust SDK
+sys.path.insert(
+    0,
+    os.path.join(
+        os.path.dirname(os.path.realpath(__file__)), "../../middleman-sdk/python"
+    ),
+)
+from middleman import Connection  # noqa: E402
+
 
 def main():
     empty_positions, my_positions, their_positions = set(range(9)), set(), set()
@@ -36,7 +46,11 @@ def main():
                 return move
         return -1
 
-    my_turn = not bool(int(input()))
+    # The metamanager may open with a hello if it was run with --handshake, answer it and
+    # carry on with the player index that follows
+    connection = Connection()
+    connection.handshake("better_random", "1", game="tictactoe")
+    my_turn = not bool(connection.recv(int))
     while True:
         print_board()
         if has_winning_position(my_positions):
@@ -49,7 +63,7 @@ def main():
             print("Tie :o", file=sys.stderr)
             sys.exit(0)
         if not my_turn:
-            opponent_move = int(input())
+            opponent_move = connection.recv(int)
             assert opponent_move in empty_positions
             empty_positions.remove(opponent_move)
             their_positions.add(opponent_move)
@@ -58,7 +72,7 @@ def main():
             if move < 0:  # when no move that wins, get arbitrary move
                 move = empty_positions.pop()
             my_positions.add(move)
-            print(move, flush=True)
+            connection.send(move)
         my_turn = not my_turn
 
 
@@ -1,27 +1,7 @@
+use middleman_sdk::{run_player, Identity, Player};
 use rand::Rng;
 use std::collections::HashSet;
-use std::io::{self, BufRead};
-
-fn get_input_as_u8() -> u8 {
-    let stdin = io::stdin();
-    return stdin
-        .lock()
-        .lines()
-        .next()
-        .unwrap()
-        .unwrap()
-        .parse::<u8>()
-        .unwrap();
-}
-
-fn update_positions_and_moves(
-    possible_moves: &mut HashSet<u8>,
-    positions: &mut HashSet<u8>,
-    position: u8,
-) {
-    possible_moves.remove(&position);
-    positions.insert(position);
-}
+use std::process;
 
 fn contains_winning_combination(positions: &HashSet<u8>) -> bool {
     let winning_combinations: [HashSet<u8>; 8] = [
@@ -42,40 +22,67 @@ fn contains_winning_combination(positions: &HashSet<u8>) -> bool {
     return false;
 }
 
-fn main() {
-    let mut possible_moves: HashSet<u8> = HashSet::from([0, 1, 2, 3, 4, 5, 6, 7, 8]);
-    let mut my_positions: HashSet<u8> = HashSet::new();
-    let mut opponent_positions: HashSet<u8> = HashSet::new();
+struct RandomPlayer {
+    // The first message is our player index, every one after it an opponent move
+    player_index: Option<u8>,
+    possible_moves: HashSet<u8>,
+    my_positions: HashSet<u8>,
+    opponent_positions: HashSet<u8>,
+}
 
-    let player_index = get_input_as_u8();
-    if player_index == 1 {
-        update_positions_and_moves(
-            &mut possible_moves,
-            &mut opponent_positions,
-            get_input_as_u8(),
-        );
+impl RandomPlayer {
+    fn game_over(&self) -> bool {
+        self.possible_moves.is_empty()
+            || contains_winning_combination(&self.my_positions)
+            || contains_winning_combination(&self.opponent_positions)
     }
-    let mut rng = rand::thread_rng();
-    while possible_moves.len() != 0 {
-        let mut my_move: u8 = rng.gen_range(0..9);
-        while !possible_moves.contains(&my_move) {
-            my_move = rng.gen_range(0..9);
-        }
-        println!("{}", my_move);
-        update_positions_and_moves(&mut possible_moves, &mut my_positions, my_move);
-        if contains_winning_combination(&my_positions) {
-            // println!("I win!!");
-            break;
+}
+
+impl Player for RandomPlayer {
+    type Incoming = u8;
+    type Outgoing = u8;
+
+    fn identity(&self) -> Identity {
+        Identity::new("implementation2", "1").game("tictactoe")
+    }
+
+    fn on_message(&mut self, message: u8) -> Option<u8> {
+        match self.player_index {
+            None => {
+                self.player_index = Some(message);
+                // Player 1 waits for the opponent's first move.
+                if message == 1 {
+                    return None;
+                }
+            }
+            Some(_) => {
+                self.possible_moves.remove(&message);
+                self.opponent_positions.insert(message);
+            }
         }
-        if possible_moves.len() == 0 {
-            break;
+        if self.game_over() {
+            return None;
         }
-        let opponent_move: u8 = get_input_as_u8();
-        update_positions_and_moves(&mut possible_moves, &mut opponent_positions, opponent_move);
-        if contains_winning_combination(&opponent_positions) {
-            // println!("I lost :(");
-            break;
+        let mut rng = rand::thread_rng();
+        let mut my_move: u8 = rng.gen_range(0..9);
+        while !self.possible_moves.contains(&my_move) {
+            my_move = rng.gen_range(0..9);
         }
+        self.possible_moves.remove(&my_move);
+        self.my_positions.insert(my_move);
+        Some(my_move)
+    }
+}
+
+fn main() {
+    let player = RandomPlayer {
+        player_index: None,
+        possible_moves: HashSet::from([0, 1, 2, 3, 4, 5, 6, 7, 8]),
+        my_positions: HashSet::new(),
+        opponent_positions: HashSet::new(),
+    };
+    if let Err(err) = run_player(player) {
+        eprintln!("{}", err);
+        process::exit(1);
     }
-    // println!("Tie :o");
 }
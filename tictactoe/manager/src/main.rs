@@ -1,32 +1,8 @@
+use middleman_sdk::{run_referee, Error, Outbox, Outcome, Referee};
 use rand::Rng;
 use std::collections::HashSet;
-use std::fmt;
-use std::result::Result::Ok;
+use std::process;
 use std::time::Duration;
-use tokio::io;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::time::timeout;
-
-struct Message {
-    user_pid: u8,
-    position: u8,
-}
-
-impl fmt::Display for Message {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}:{}", self.user_pid, self.position)
-    }
-}
-
-fn build_message(line: String) -> Option<Message> {
-    let mut split = line.split(":");
-    let user_pid = split.next()?.parse::<u8>().unwrap();
-    let message = split.next()?.trim();
-    match message.parse::<u8>() {
-        Ok(position) => Some(Message { user_pid, position }),
-        Err(_) => None,
-    }
-}
 
 fn contains_winning_combination(moves: &HashSet<u8>) -> bool {
     let winning_combinations: [HashSet<u8>; 8] = [
@@ -44,81 +20,84 @@ fn contains_winning_combination(moves: &HashSet<u8>) -> bool {
             return true;
         }
     }
-    return false;
+    false
 }
 
-// TODO: this is commented out because maybe players don't need
-//       to know about how to deal with the game end, we can just
-//       shut down their programs gracefully and save the result
-//       in the manager's log (TODO).
-//
-//       also it was throwing a bunch of broken pipe errors lmao
-// Send result to both
-fn print_decisive_result(loser_pid: u8) {
-    // println!("{}:{} {}", loser_pid, get_next_pid(loser_pid), loser_pid);
-    // println!(
-    //     "{}:{} {}",
-    //     get_next_pid(loser_pid),
-    //     get_next_pid(loser_pid),
-    //     loser_pid
-    // );
+fn get_next_pid(pid: usize) -> usize {
+    (pid + 1) % 2
 }
 
-fn get_next_pid(pid: u8) -> u8 {
-    return (pid + 1) % 2;
+// Players don't need to know about the game end, the metamanager closes their stdin
+// once we report the result and keeps it for the match report.
+struct TicTacToe {
+    current_pid: usize,
+    possible_moves: HashSet<u8>,
+    player_moves: [HashSet<u8>; 2],
 }
 
-#[tokio::main]
-async fn main() {
-    let mut lines = BufReader::new(io::stdin()).lines();
-    let mut current_pid: u8 = rand::thread_rng().gen_range(0..2);
-    let mut possible_moves: HashSet<u8> = HashSet::from([0, 1, 2, 3, 4, 5, 6, 7, 8]);
-    let mut player_moves: [HashSet<u8>; 2] = [HashSet::new(), HashSet::new()];
+impl Referee for TicTacToe {
+    type Incoming = u8;
+    type Outgoing = u8;
 
-    // Let the players know who's moving first.
-    println!("{}:0", current_pid);
-    current_pid = get_next_pid(current_pid);
-    println!("{}:1", current_pid);
-    current_pid = get_next_pid(current_pid);
+    fn players(&self) -> usize {
+        2
+    }
 
-    while !possible_moves.is_empty() {
-        if let Ok(Ok(Some(line))) = timeout(Duration::from_secs(20), lines.next_line()).await {
-            if let Some(message) = build_message(line) {
-                // Player played out of turn which is invalid, user auto-loses.
-                if message.user_pid != current_pid {
-                    print_decisive_result(message.user_pid);
-                    return;
-                }
-                // Player played an impossible move, user auto-loses.
-                if !possible_moves.remove(&message.position) {
-                    print_decisive_result(current_pid);
-                    return;
-                }
-                let _ = &player_moves[current_pid as usize].insert(message.position);
+    fn start(&mut self, out: &mut Outbox<u8>) -> Option<Outcome> {
+        // Let the players know who's moving first.
+        out.send(self.current_pid, &0);
+        out.send(get_next_pid(self.current_pid), &1);
+        None
+    }
 
-                // Player current_pid played a winning move.
-                if contains_winning_combination(&player_moves[current_pid as usize]) {
-                    print_decisive_result(get_next_pid(current_pid));
-                }
+    fn on_message(&mut self, pid: usize, position: u8, out: &mut Outbox<u8>) -> Option<Outcome> {
+        // Player played out of turn which is invalid, user auto-loses.
+        if pid != self.current_pid {
+            return Some(Outcome::loss(pid, 2, "out of turn"));
+        }
+        // Player played an impossible move, user auto-loses.
+        if !self.possible_moves.remove(&position) {
+            return Some(Outcome::loss(pid, 2, "illegal move"));
+        }
+        self.player_moves[pid].insert(position);
 
-                // Notify the next player of the move.
-                current_pid = get_next_pid(current_pid);
-                println!(
-                    "{}",
-                    Message {
-                        user_pid: current_pid,
-                        position: message.position
-                    }
-                );
-            } else {
-                // Player current_pid provided invalid input.
-                print_decisive_result(current_pid);
-                return;
-            }
-        } else {
-            // Player current_pid failed to produce output in time, user auto-loses.
-            print_decisive_result(current_pid);
-            return;
+        // Player pid played a winning move.
+        if contains_winning_combination(&self.player_moves[pid]) {
+            return Some(Outcome::win(pid, 2, "three in a row"));
         }
+        // Board is full and nobody won.
+        if self.possible_moves.is_empty() {
+            return Some(Outcome::draw(2, "draw"));
+        }
+
+        // Notify the next player of the move.
+        self.current_pid = get_next_pid(pid);
+        out.send(self.current_pid, &position);
+        None
+    }
+
+    fn on_invalid(&mut self, pid: usize, _error: &Error, _out: &mut Outbox<u8>) -> Option<Outcome> {
+        Some(Outcome::loss(pid, 2, "invalid input"))
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(20))
+    }
+
+    fn on_timeout(&mut self, _out: &mut Outbox<u8>) -> Option<Outcome> {
+        // Player current_pid failed to produce output in time, user auto-loses.
+        Some(Outcome::loss(self.current_pid, 2, "no move"))
+    }
+}
+
+fn main() {
+    let game = TicTacToe {
+        current_pid: rand::thread_rng().gen_range(0..2),
+        possible_moves: HashSet::from([0, 1, 2, 3, 4, 5, 6, 7, 8]),
+        player_moves: [HashSet::new(), HashSet::new()],
+    };
+    if let Err(err) = run_referee(game) {
+        eprintln!("{}", err);
+        process::exit(1);
     }
 }